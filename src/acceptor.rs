@@ -0,0 +1,300 @@
+//! ### Acceptor State
+//! What a senator promised and accepted, written to a log backend before the
+//! senator replies, so a restarted senator keeps every promise it made.
+//!
+//! A promise covers a range of slots: a master taking the lead prepares
+//! every slot from the first one it has not applied on, so that it can
+//! propose in all of them with a single round of messages. Accepting a
+//! proposal also promises its ballot for that slot. A senator accepts a
+//! proposal, or promises a range, when its ballot is not lower than any
+//! ballot it promised for those slots.
+//!
+//! The backend holds the range promises in slot 0, and the state of slot `i`
+//! in slot `i`.
+//!
+#![allow(unused)]
+
+use std::collections::BTreeMap;
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    issue::{Ballot, Entry},
+    logbackend::{LogBackend, Queryable, Writable},
+};
+
+/// 保存承诺过的编号范围的位置，议题的编号从1开始
+const RANGES_SLOT: u64 = 0;
+
+/// 准备请求的内容：准备 `Issue::id()..=to` 的编号
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct Prepare {
+    pub to: u64,
+}
+
+/// 承诺的内容：议员在准备的编号中接受过的提案，见证者接受的提案只有种类
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, Eq)]
+pub struct Promise {
+    pub accepted: Vec<Accepted>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct Accepted {
+    pub slot: u64,
+    pub ballot: Ballot,
+    pub entry: Entry,
+}
+
+/// 对 `from..=to` 的编号承诺的轮次
+#[derive(Serialize, Deserialize, Clone, Debug)]
+struct Range {
+    from: u64,
+    to: u64,
+    ballot: Ballot,
+}
+
+/// 一个编号上承诺的轮次与接受过的提案
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+struct Slot {
+    promised: Ballot,
+    accepted: Option<(Ballot, Entry)>,
+}
+
+pub struct Acceptor {
+    log: Box<dyn LogBackend>,
+    ranges: Vec<Range>,
+    slots: BTreeMap<u64, Slot>,
+    /// 打开时日志中是否已有之前保存的状态
+    restored: bool,
+}
+
+impl Acceptor {
+    /// 从 `log` 中恢复之前承诺与接受过的提案，日志无法读取时返回错误
+    pub fn open(log: Box<dyn LogBackend>) -> Result<Self> {
+        let mut acceptor = Self {
+            log,
+            ranges: Vec::new(),
+            slots: BTreeMap::new(),
+            restored: false,
+        };
+        let Some(last) = acceptor.log.last_id()? else {
+            return Ok(acceptor);
+        };
+
+        acceptor.restored = true;
+        if let Some(ranges) = acceptor.log.get(RANGES_SLOT)? {
+            acceptor.ranges = bincode::deserialize(&ranges)?;
+        }
+        for slot in 1..=last {
+            if let Some(state) = acceptor.log.get(slot)? {
+                acceptor.slots.insert(slot, bincode::deserialize(&state)?);
+            }
+        }
+        Ok(acceptor)
+    }
+
+    /// 打开时是否恢复了之前保存的状态
+    pub fn restored(&self) -> bool {
+        self.restored
+    }
+
+    /// `from..=to` 中任一编号承诺过的最高轮次
+    pub fn promised(&self, from: u64, to: u64) -> Ballot {
+        let ranges = self
+            .ranges
+            .iter()
+            .filter(|range| range.from <= to && from <= range.to)
+            .map(|range| &range.ballot);
+        let slots = self.slots.range(from..=to).map(|(_, slot)| &slot.promised);
+        ranges.chain(slots).max().cloned().unwrap_or_default()
+    }
+
+    /// 所有编号中承诺过的最高轮次
+    pub fn highest(&self) -> Ballot {
+        self.promised(0, u64::MAX)
+    }
+
+    /// 轮次不低于 `from..=to` 中承诺过的轮次时承诺 `ballot`，返回这些编号中
+    /// 接受过的提案；否则返回 `None`
+    pub fn prepare(&mut self, ballot: &Ballot, from: u64, to: u64) -> Result<Option<Promise>> {
+        if *ballot < self.promised(from, to) {
+            return Ok(None);
+        }
+
+        // 被新的承诺覆盖的范围不再需要
+        let mut ranges = self.ranges.clone();
+        ranges.retain(|range| !(from <= range.from && range.to <= to && range.ballot <= *ballot));
+        ranges.push(Range {
+            from,
+            to,
+            ballot: ballot.clone(),
+        });
+        self.log
+            .write(RANGES_SLOT, bincode::serialize(&ranges)?.into())?;
+        self.ranges = ranges;
+
+        let accepted = self
+            .slots
+            .range(from..=to)
+            .filter_map(|(slot, state)| {
+                state.accepted.as_ref().map(|(ballot, entry)| Accepted {
+                    slot: *slot,
+                    ballot: ballot.clone(),
+                    entry: entry.clone(),
+                })
+            })
+            .collect();
+        Ok(Some(Promise { accepted }))
+    }
+
+    /// 轮次不低于 `slot` 承诺过的轮次时接受提案并承诺该轮次，返回是否接受
+    pub fn accept(&mut self, ballot: &Ballot, slot: u64, entry: Entry) -> Result<bool> {
+        if *ballot < self.promised(slot, slot) {
+            return Ok(false);
+        }
+
+        let state = Slot {
+            promised: ballot.clone(),
+            accepted: Some((ballot.clone(), entry)),
+        };
+        self.log.write(slot, bincode::serialize(&state)?.into())?;
+        self.slots.insert(slot, state);
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, rc::Rc};
+
+    use anyhow::{anyhow, Result};
+    use bytes::Bytes;
+
+    use super::{Acceptor, Promise};
+    use crate::{
+        issue::{Ballot, Entry},
+        logbackend::{FileLogBackend, HeapLogBackend, LogBackend, Queryable, Writable},
+    };
+
+    /// 无法读取的日志
+    struct Unreadable;
+
+    impl Writable for Unreadable {
+        fn write(&self, _id: u64, _data: Bytes) -> Result<()> {
+            Ok(())
+        }
+    }
+
+    impl Queryable for Unreadable {
+        fn query(&self, _id: u64) -> Result<Bytes> {
+            Err(anyhow!("unreadable"))
+        }
+
+        fn get(&self, _id: u64) -> Result<Option<Bytes>> {
+            Err(anyhow!("unreadable"))
+        }
+
+        fn last_id(&self) -> Result<Option<u64>> {
+            Err(anyhow!("unreadable"))
+        }
+    }
+
+    impl LogBackend for Unreadable {}
+
+    fn ballot(round: u64, node: &str) -> Ballot {
+        Ballot::new(round, node.to_string())
+    }
+
+    #[test]
+    fn promises_survive_restart_test() {
+        // 重启后仍然保留的日志
+        let log = Rc::new(HeapLogBackend::new());
+        let mut acceptor = Acceptor::open(Box::new(log.clone())).unwrap();
+        assert!(!acceptor.restored());
+
+        assert!(acceptor
+            .accept(&ballot(1, "m1"), 3, Entry::client(Bytes::from("x = 1")))
+            .unwrap());
+        let promise = acceptor.prepare(&ballot(2, "m2"), 2, u64::MAX).unwrap();
+        let accepted = promise.unwrap().accepted;
+        assert_eq!(accepted.len(), 1);
+        assert_eq!(accepted[0].slot, 3);
+        assert_eq!(accepted[0].ballot, ballot(1, "m1"));
+
+        // 重启后仍然拒绝更低的轮次，并记得接受过的提案
+        let mut restarted = Acceptor::open(Box::new(log)).unwrap();
+        assert!(restarted.restored());
+        assert_eq!(restarted.highest(), ballot(2, "m2"));
+        assert!(!restarted
+            .accept(&ballot(1, "m1"), 4, Entry::client(Bytes::from("x = 2")))
+            .unwrap());
+        assert!(restarted.prepare(&ballot(1, "m3"), 5, 5).unwrap().is_none());
+        let promise = restarted.prepare(&ballot(3, "m1"), 1, u64::MAX).unwrap();
+        assert_eq!(
+            promise.unwrap().accepted[0].entry,
+            Entry::client(Bytes::from("x = 1"))
+        );
+    }
+
+    #[test]
+    fn promises_cover_their_range_test() {
+        let log = HeapLogBackend::new();
+        let mut acceptor = Acceptor::open(Box::new(log)).unwrap();
+
+        assert!(acceptor
+            .prepare(&ballot(5, "m2"), 10, 10)
+            .unwrap()
+            .is_some());
+        // 编号10之外的编号不受影响
+        assert!(acceptor.accept(&ballot(1, "m1"), 9, Entry::noop()).unwrap());
+        assert!(!acceptor
+            .accept(&ballot(1, "m1"), 10, Entry::noop())
+            .unwrap());
+        assert!(acceptor
+            .prepare(&ballot(2, "m1"), 1, u64::MAX)
+            .unwrap()
+            .is_none());
+        assert_eq!(acceptor.promised(1, 9), ballot(1, "m1"));
+        assert_eq!(
+            acceptor.prepare(&ballot(2, "m1"), 11, u64::MAX).unwrap(),
+            Some(Promise::default())
+        );
+    }
+
+    #[test]
+    fn promises_survive_restart_from_a_log_file_test() {
+        let path =
+            std::env::temp_dir().join(format!("somepox-acceptor-{}.log", std::process::id()));
+        let _ = fs::remove_file(&path);
+        let file_name = path.to_str().unwrap();
+
+        let mut acceptor =
+            Acceptor::open(Box::new(FileLogBackend::open(file_name).unwrap())).unwrap();
+        assert!(acceptor
+            .accept(&ballot(1, "m1"), 3, Entry::client(Bytes::from("x = 1")))
+            .unwrap());
+        assert!(acceptor
+            .prepare(&ballot(2, "m2"), 2, u64::MAX)
+            .unwrap()
+            .is_some());
+        drop(acceptor);
+
+        let mut restarted =
+            Acceptor::open(Box::new(FileLogBackend::open(file_name).unwrap())).unwrap();
+        assert!(restarted.restored());
+        assert_eq!(restarted.highest(), ballot(2, "m2"));
+        assert!(!restarted
+            .accept(&ballot(1, "m1"), 4, Entry::noop())
+            .unwrap());
+        let promise = restarted.prepare(&ballot(3, "m1"), 1, u64::MAX).unwrap();
+        assert_eq!(
+            promise.unwrap().accepted[0].entry,
+            Entry::client(Bytes::from("x = 1"))
+        );
+        let _ = fs::remove_file(&path);
+
+        // 无法读取日志时拒绝启动，而不是忘记承诺
+        assert!(Acceptor::open(Box::new(Unreadable)).is_err());
+    }
+}
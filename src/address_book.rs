@@ -0,0 +1,145 @@
+//! ### Address Book
+//! Resolve node ids into transport addresses.
+//!
+//! Nodes are known by a stable id (their name in `config.yaml`), and may be
+//! gathered into named groups, such as all the acceptors of the cluster, so
+//! that a mail can be sent to a whole group at once.
+//!
+#![allow(unused)]
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use serde::{Deserialize, Serialize};
+
+pub type NodeId = String;
+
+/// Who a mail is sent to.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub enum Recipient {
+    Node(NodeId),
+    Group(String),
+}
+
+#[derive(Clone)]
+pub struct AddressBook<Addr> {
+    nodes: BTreeMap<NodeId, Addr>,
+    groups: BTreeMap<String, BTreeSet<NodeId>>,
+}
+
+impl<Addr> Default for AddressBook<Addr> {
+    fn default() -> Self {
+        Self {
+            nodes: BTreeMap::new(),
+            groups: BTreeMap::new(),
+        }
+    }
+}
+
+impl<Addr> AddressBook<Addr>
+where
+    Addr: Clone + PartialEq,
+{
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Record the address of a node, replacing any previous one.
+    pub fn insert(&mut self, id: NodeId, address: Addr) {
+        self.nodes.insert(id, address);
+    }
+
+    /// Forget a node, and remove it from every group.
+    pub fn remove(&mut self, id: &str) {
+        self.nodes.remove(id);
+        for members in self.groups.values_mut() {
+            members.remove(id);
+        }
+    }
+
+    /// Add a node to a group, the group is created when missing.
+    pub fn join(&mut self, group: &str, id: NodeId) {
+        self.groups.entry(group.to_string()).or_default().insert(id);
+    }
+
+    /// Take a node out of a group, its address is kept.
+    pub fn leave(&mut self, group: &str, id: &str) {
+        if let Some(members) = self.groups.get_mut(group) {
+            members.remove(id);
+        }
+    }
+
+    pub fn resolve(&self, id: &str) -> Option<Addr> {
+        self.nodes.get(id).cloned()
+    }
+
+    /// The node an address belongs to.
+    pub fn id_of(&self, address: &Addr) -> Option<NodeId> {
+        self.nodes
+            .iter()
+            .find(|(_, addr)| *addr == address)
+            .map(|(id, _)| id.clone())
+    }
+
+    /// Members of a group, in id order.
+    pub fn group(&self, group: &str) -> Vec<NodeId> {
+        self.groups
+            .get(group)
+            .map(|members| members.iter().cloned().collect())
+            .unwrap_or_default()
+    }
+
+    /// Every node id a list of recipients stands for, without duplicates.
+    pub fn expand(&self, recipients: &[Recipient]) -> Vec<NodeId> {
+        let mut ids: Vec<NodeId> = Vec::new();
+        for recipient in recipients {
+            let members = match recipient {
+                Recipient::Node(id) => vec![id.clone()],
+                Recipient::Group(group) => self.group(group),
+            };
+            for id in members {
+                if !ids.contains(&id) {
+                    ids.push(id);
+                }
+            }
+        }
+        ids
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{AddressBook, Recipient};
+
+    #[test]
+    fn resolve_and_expand_test() {
+        let mut book = AddressBook::new();
+        book.insert("worker-1".to_string(), "127.0.0.1:18001".to_string());
+        book.insert("worker-2".to_string(), "127.0.0.1:18002".to_string());
+        book.join("worker", "worker-1".to_string());
+        book.join("worker", "worker-2".to_string());
+
+        assert_eq!(
+            book.resolve("worker-2"),
+            Some("127.0.0.1:18002".to_string())
+        );
+        assert_eq!(
+            book.id_of(&"127.0.0.1:18001".to_string()),
+            Some("worker-1".to_string())
+        );
+        assert_eq!(
+            book.expand(&[
+                Recipient::Node("worker-2".to_string()),
+                Recipient::Group("worker".to_string()),
+            ]),
+            vec!["worker-2".to_string(), "worker-1".to_string()]
+        );
+
+        book.leave("worker", "worker-2");
+        assert_eq!(book.group("worker"), vec!["worker-1".to_string()]);
+        assert!(book.resolve("worker-2").is_some());
+
+        book.remove("worker-1");
+        assert!(book.group("worker").is_empty());
+        assert!(book.resolve("worker-1").is_none());
+    }
+}
@@ -0,0 +1,66 @@
+//! ### Admin
+//! Command line client of the master's admin API.
+//!
+#![allow(unused)]
+
+use std::{
+    io::{Read, Write},
+    net::TcpStream,
+    time::Duration,
+};
+
+use anyhow::{anyhow, Result};
+use serde_json::json;
+
+/// 等待API回复的最长时间，应长于API等待决议的时间
+const RESPONSE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// 加入议员，议员的地址须与其配置中的 `address` 一致
+pub fn add_node(api: &str, id: &str, address: &str) -> Result<String> {
+    let body = json!({ "id": id, "address": address }).to_string();
+    request(api, "POST", "/admin/members", &body)
+}
+
+pub fn remove_node(api: &str, id: &str) -> Result<String> {
+    request(api, "DELETE", &format!("/admin/members/{}", id), "")
+}
+
+pub fn members(api: &str) -> Result<String> {
+    request(api, "GET", "/admin/members", "")
+}
+
+/// 集群状态，包括各议员的存活情况
+pub fn status(api: &str) -> Result<String> {
+    request(api, "GET", "/status", "")
+}
+
+/// 发送一个HTTP请求，返回响应体；状态码不是2xx时返回错误
+pub fn request(api: &str, method: &str, path: &str, body: &str) -> Result<String> {
+    let mut stream = TcpStream::connect(api)?;
+    stream.set_read_timeout(Some(RESPONSE_TIMEOUT))?;
+    write!(
+        stream,
+        "{} {} HTTP/1.1\r\nHost: {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        method,
+        path,
+        api,
+        body.len(),
+        body
+    )?;
+
+    let mut response = String::new();
+    stream.read_to_string(&mut response)?;
+    let (head, body) = response
+        .split_once("\r\n\r\n")
+        .ok_or(anyhow!("malformed response"))?;
+    let status = head
+        .split_whitespace()
+        .nth(1)
+        .ok_or(anyhow!("malformed response"))?;
+
+    if status.starts_with('2') {
+        Ok(body.to_string())
+    } else {
+        Err(anyhow!("{} {}: {} {}", method, path, status, body))
+    }
+}
@@ -1,49 +1,174 @@
-use std::{fmt, sync::mpsc::Sender};
+use std::{
+    fmt,
+    sync::mpsc::{channel, Receiver, Sender},
+    time::Duration,
+};
 
-use actix_web::{rt, web, App, HttpResponse, HttpServer, Responder};
+use actix_web::{
+    http::header::{self, ContentType},
+    rt, web, App, HttpRequest, HttpResponse, HttpServer, Responder,
+};
 use anyhow::{anyhow, Result};
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use bytes::Bytes;
 use serde::{Deserialize, Serialize};
 
+use crate::{
+    address_book::NodeId,
+    lock::{self, LockError, LockOp},
+    membership::Change,
+    read::Consistency,
+    roles::ClusterStatus,
+    session::{ClientId, Command},
+    shutdown::Shutdown,
+};
+
+/// 等待Master回复查询结果的最长时间
+const QUERY_TIMEOUT: Duration = Duration::from_secs(1);
+/// 等待日志可以安全读取的最长时间，应长于Master的读请求期限
+/// `PROPOSAL_TIMEOUT` 与一轮领导权确认的时间之和
+const READ_TIMEOUT: Duration = Duration::from_secs(5);
+/// 等待提交的日志形成决议的最长时间，应长于Master的提案表决时间
+const SUBMIT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// JSON形式提交的日志，`content` 为文本，`content_base64` 为base64编码的任意字节
 #[derive(Serialize, Deserialize)]
 struct LogRequest {
     content: Option<String>,
+    content_base64: Option<String>,
 }
 
 impl fmt::Display for LogRequest {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "log content: {:?}", self.content)
+        write!(
+            f,
+            "log content: {:?}, base64: {:?}",
+            self.content, self.content_base64
+        )
     }
 }
 
+/// 提交日志时可选的会话：同一客户端重复提交同一序号的日志只形成一次决议
+#[derive(Deserialize)]
+struct SessionParams {
+    client: Option<ClientId>,
+    seq: Option<u64>,
+}
+
+/// 日志形成决议后的回复
+#[derive(Serialize, Deserialize)]
+struct LogResponse {
+    id: u64,
+}
+
+/// 加入议员的请求
 #[derive(Serialize, Deserialize)]
+struct AddMemberRequest {
+    id: NodeId,
+    address: String,
+}
+
+/// 获取锁的请求，`ttl_ms` 默认为 `lock::DEFAULT_TTL`
+#[derive(Deserialize)]
+struct AcquireRequest {
+    owner: String,
+    ttl_ms: Option<u64>,
+}
+
+/// 续期锁的请求，`ttl_ms` 默认为 `lock::DEFAULT_TTL`
+#[derive(Deserialize)]
+struct RenewRequest {
+    owner: String,
+    token: u64,
+    ttl_ms: Option<u64>,
+}
+
+#[derive(Deserialize)]
+struct ReleaseRequest {
+    owner: String,
+    token: u64,
+}
+
+/// 锁命令生效后的回复，`token` 为锁的令牌
+#[derive(Serialize, Deserialize)]
+struct LockResponse {
+    token: u64,
+}
+
+#[derive(Deserialize)]
 struct QueryRequest {
     id: u64,
+    /// 默认为线性一致的读取
+    #[serde(default)]
+    consistency: Consistency,
 }
 
 impl fmt::Display for QueryRequest {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "query: QueryRequest (id : {}) ", self.id)
+        write!(
+            f,
+            "query: QueryRequest (id : {}, consistency : {:?}) ",
+            self.id, self.consistency
+        )
     }
 }
 
 pub enum CmdType {
-    Log(String),
-    Query(u64),
+    /// 提交日志，形成决议后经由附带的 `Sender` 返回日志编号，失败时返回原因
+    Log(Bytes, Sender<Result<u64>>),
+    /// 提交会话中的命令，同 `Log`；重复的命令返回原先的日志编号
+    Submit(Command, Sender<Result<u64>>),
+    /// 提议成员变更，形成决议后经由附带的 `Sender` 返回议题编号，失败时返回原因
+    Reconfigure(Change, Sender<Result<u64>>),
+    /// 提议对锁的命令，生效后经由附带的 `Sender` 返回议题编号；
+    /// 形成决议但未能生效时返回 `LockError`
+    Lock(String, LockOp, Sender<Result<u64>>),
+    /// 查询所有成员变更生效后的议员
+    Members(Sender<Vec<NodeId>>),
+    /// 查询集群状态
+    Status(Sender<ClusterStatus>),
+    /// 以指定的一致性查询日志，结果经由附带的 `Sender` 返回，日志不存在时为
+    /// `None`，无法确认领导权时返回原因
+    Query(u64, Consistency, Sender<Result<Option<Bytes>>>),
 }
 
-pub fn api_server_init(end_point: String, tx: Sender<CmdType>) -> Result<()> {
+pub fn api_server_init(end_point: String, tx: Sender<CmdType>, shutdown: Shutdown) -> Result<()> {
     rt::System::new()
-        .block_on(
-            HttpServer::new(move || {
+        .block_on(async move {
+            let server = HttpServer::new(move || {
                 App::new()
                     .app_data(web::Data::new(tx.clone()))
                     .service(web::resource("/").route(web::get().to(hello)))
                     .service(web::resource("/submit").route(web::post().to(log)))
                     .service(web::resource("/query").route(web::get().to(query)))
+                    .service(web::resource("/status").route(web::get().to(status)))
+                    .service(
+                        web::resource("/admin/members")
+                            .route(web::get().to(members))
+                            .route(web::post().to(add_member)),
+                    )
+                    .service(
+                        web::resource("/admin/members/{id}").route(web::delete().to(remove_member)),
+                    )
+                    .service(web::resource("/locks/{name}/acquire").route(web::post().to(acquire)))
+                    .service(web::resource("/locks/{name}/renew").route(web::post().to(renew)))
+                    .service(web::resource("/locks/{name}/release").route(web::post().to(release)))
             })
+            .shutdown_timeout(1)
             .bind(end_point)?
-            .run(),
-        )
+            .run();
+
+            // 收到停止信号后关闭API服务
+            let handle = server.handle();
+            rt::spawn(async move {
+                while !shutdown.is_triggered() {
+                    rt::time::sleep(Duration::from_millis(100)).await;
+                }
+                handle.stop(true).await;
+            });
+
+            server.await
+        })
         .map_err(|_| anyhow!("Error."))
 }
 
@@ -51,12 +176,196 @@ async fn hello() -> impl Responder {
     HttpResponse::Ok().body("Hello world!")
 }
 
-async fn log(log_req: web::Json<LogRequest>, data: web::Data<Sender<CmdType>>) -> impl Responder {
+/// 取出请求中的日志内容：
+/// 1. `application/octet-stream` 时，请求体即为日志内容
+/// 2. 否则按JSON格式的 `LogRequest` 解析
+///
+/// 成员变更、会话命令、锁命令与空操作由条目的种类区分，不限制客户端提交的内容
+fn log_content(content_type: Option<&str>, body: Bytes) -> Result<Bytes> {
+    if content_type == Some(ContentType::octet_stream().essence_str()) {
+        Ok(body)
+    } else {
+        log_request_content(&body)
+    }
+}
+
+fn log_request_content(body: &[u8]) -> Result<Bytes> {
+    let log_req: LogRequest = serde_json::from_slice(body)?;
     println!("REQ: {}", log_req);
-    if let Some(content) = &log_req.content {
-        let _ = data.send(CmdType::Log(content.clone()));
+    match (log_req.content, log_req.content_base64) {
+        (Some(content), None) => Ok(content.into()),
+        (None, Some(encoded)) => Ok(BASE64.decode(encoded)?.into()),
+        (Some(_), Some(_)) => Err(anyhow!("both `content` and `content_base64` are given")),
+        (None, None) => Err(anyhow!("missing `content` or `content_base64`")),
+    }
+}
+
+async fn log(
+    req: HttpRequest,
+    session: web::Query<SessionParams>,
+    body: Bytes,
+    data: web::Data<Sender<CmdType>>,
+) -> impl Responder {
+    let content_type = req
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.split(';').next())
+        .map(str::trim);
+
+    let content = match log_content(content_type, body) {
+        Ok(content) => content,
+        Err(e) => return HttpResponse::BadRequest().body(e.to_string()),
+    };
+
+    let (tx, rx) = channel();
+    let cmd = match session.into_inner() {
+        SessionParams {
+            client: Some(client),
+            seq: Some(seq),
+        } => CmdType::Submit(Command::new(client, seq, content), tx),
+        SessionParams {
+            client: None,
+            seq: None,
+        } => CmdType::Log(content, tx),
+        _ => return HttpResponse::BadRequest().body("`client` and `seq` go together"),
+    };
+    if data.send(cmd).is_err() {
+        return HttpResponse::ServiceUnavailable().finish();
+    }
+    decided(rx).await
+}
+
+/// 等待议题形成决议，回复议题编号
+async fn decided(rx: Receiver<Result<u64>>) -> HttpResponse {
+    // 等待决议时不阻塞API服务的事件循环
+    match web::block(move || rx.recv_timeout(SUBMIT_TIMEOUT)).await {
+        Ok(Ok(Ok(id))) => HttpResponse::Ok().json(LogResponse { id }),
+        Ok(Ok(Err(e))) => HttpResponse::ServiceUnavailable().body(e.to_string()),
+        _ => HttpResponse::GatewayTimeout().finish(),
+    }
+}
+
+async fn members(data: web::Data<Sender<CmdType>>) -> impl Responder {
+    let (tx, rx) = channel();
+    if data.send(CmdType::Members(tx)).is_err() {
+        return HttpResponse::ServiceUnavailable().finish();
+    }
+    match web::block(move || rx.recv_timeout(QUERY_TIMEOUT)).await {
+        Ok(Ok(members)) => HttpResponse::Ok().json(members),
+        _ => HttpResponse::ServiceUnavailable().finish(),
+    }
+}
+
+async fn status(data: web::Data<Sender<CmdType>>) -> impl Responder {
+    let (tx, rx) = channel();
+    if data.send(CmdType::Status(tx)).is_err() {
+        return HttpResponse::ServiceUnavailable().finish();
+    }
+    match web::block(move || rx.recv_timeout(QUERY_TIMEOUT)).await {
+        Ok(Ok(status)) => HttpResponse::Ok().json(status),
+        _ => HttpResponse::ServiceUnavailable().finish(),
+    }
+}
+
+async fn add_member(
+    req: web::Json<AddMemberRequest>,
+    data: web::Data<Sender<CmdType>>,
+) -> impl Responder {
+    let AddMemberRequest { id, address } = req.into_inner();
+    reconfigure(Change::Add { id, address }, data).await
+}
+
+async fn remove_member(id: web::Path<NodeId>, data: web::Data<Sender<CmdType>>) -> impl Responder {
+    let id = id.into_inner();
+    reconfigure(Change::Remove { id }, data).await
+}
+
+async fn reconfigure(change: Change, data: web::Data<Sender<CmdType>>) -> HttpResponse {
+    let (tx, rx) = channel();
+    if data.send(CmdType::Reconfigure(change, tx)).is_err() {
+        return HttpResponse::ServiceUnavailable().finish();
+    }
+    decided(rx).await
+}
+
+/// 锁的有效期，默认为 `lock::DEFAULT_TTL`，不能为0或超过 `lock::MAX_TTL`
+fn lock_ttl(ttl_ms: Option<u64>) -> Result<u64> {
+    let ttl_ms = ttl_ms.unwrap_or(lock::DEFAULT_TTL.as_millis() as u64);
+    let max = lock::MAX_TTL.as_millis() as u64;
+    if ttl_ms == 0 || ttl_ms > max {
+        return Err(anyhow!("`ttl_ms` must be within 1..={}", max));
+    }
+    Ok(ttl_ms)
+}
+
+async fn acquire(
+    name: web::Path<String>,
+    req: web::Json<AcquireRequest>,
+    data: web::Data<Sender<CmdType>>,
+) -> impl Responder {
+    let AcquireRequest { owner, ttl_ms } = req.into_inner();
+    let ttl_ms = match lock_ttl(ttl_ms) {
+        Ok(ttl_ms) => ttl_ms,
+        Err(e) => return HttpResponse::BadRequest().body(e.to_string()),
+    };
+    // 获取锁的议题编号即为令牌
+    let op = LockOp::Acquire { owner, ttl_ms };
+    lock_command(name.into_inner(), op, None, data).await
+}
+
+async fn renew(
+    name: web::Path<String>,
+    req: web::Json<RenewRequest>,
+    data: web::Data<Sender<CmdType>>,
+) -> impl Responder {
+    let RenewRequest {
+        owner,
+        token,
+        ttl_ms,
+    } = req.into_inner();
+    let ttl_ms = match lock_ttl(ttl_ms) {
+        Ok(ttl_ms) => ttl_ms,
+        Err(e) => return HttpResponse::BadRequest().body(e.to_string()),
+    };
+    let op = LockOp::Renew {
+        owner,
+        token,
+        ttl_ms,
+    };
+    lock_command(name.into_inner(), op, Some(token), data).await
+}
+
+async fn release(
+    name: web::Path<String>,
+    req: web::Json<ReleaseRequest>,
+    data: web::Data<Sender<CmdType>>,
+) -> impl Responder {
+    let ReleaseRequest { owner, token } = req.into_inner();
+    let op = LockOp::Release { owner, token };
+    lock_command(name.into_inner(), op, Some(token), data).await
+}
+
+/// 提议锁命令并等待其生效，回复锁的令牌：`token` 为 `None` 时即为议题编号；
+/// 锁被其他令牌持有、或令牌已不再持有锁时回复409
+async fn lock_command(
+    name: String,
+    op: LockOp,
+    token: Option<u64>,
+    data: web::Data<Sender<CmdType>>,
+) -> HttpResponse {
+    let (tx, rx) = channel();
+    if data.send(CmdType::Lock(name, op, tx)).is_err() {
+        return HttpResponse::ServiceUnavailable().finish();
+    }
+    match web::block(move || rx.recv_timeout(SUBMIT_TIMEOUT)).await {
+        Ok(Ok(Ok(id))) => HttpResponse::Ok().json(LockResponse {
+            token: token.unwrap_or(id),
+        }),
+        Ok(Ok(Err(e))) if e.is::<LockError>() => HttpResponse::Conflict().body(e.to_string()),
+        Ok(Ok(Err(e))) => HttpResponse::ServiceUnavailable().body(e.to_string()),
+        _ => HttpResponse::GatewayTimeout().finish(),
     }
-    HttpResponse::Ok().body("Log Send.")
 }
 
 async fn query(
@@ -64,7 +373,88 @@ async fn query(
     data: web::Data<Sender<CmdType>>,
 ) -> impl Responder {
     println!("query: {}", query_req);
-    // data.send(format!("query:{}", query_req));
-    let _ = data.send(CmdType::Query(query_req.id));
-    HttpResponse::Ok().body("Hello world!")
+
+    let (tx, rx) = channel();
+    let QueryRequest { id, consistency } = query_req.into_inner();
+    if data.send(CmdType::Query(id, consistency, tx)).is_err() {
+        return HttpResponse::ServiceUnavailable().finish();
+    }
+
+    // 等待Master回复时不阻塞API服务的事件循环
+    match web::block(move || rx.recv_timeout(READ_TIMEOUT)).await {
+        Ok(Ok(Ok(Some(content)))) => HttpResponse::Ok()
+            .content_type(ContentType::octet_stream())
+            .body(content),
+        Ok(Ok(Ok(None))) => HttpResponse::NotFound().finish(),
+        Ok(Ok(Err(e))) => HttpResponse::ServiceUnavailable().body(e.to_string()),
+        _ => HttpResponse::GatewayTimeout().finish(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::Bytes;
+
+    use super::{lock_ttl, log_content};
+    use crate::{
+        lock::{LockCommand, LockOp},
+        membership::Change,
+        session::Command,
+    };
+
+    #[test]
+    fn log_content_test() {
+        let blob = Bytes::from_static(&[0x08, 0x96, 0x01, 0xff, b'|']);
+
+        // raw body
+        assert_eq!(
+            log_content(Some("application/octet-stream"), blob.clone()).unwrap(),
+            blob
+        );
+        // base64 in JSON
+        let json = Bytes::from(r#"{"content_base64": "CJYB/3w="}"#);
+        assert_eq!(log_content(Some("application/json"), json).unwrap(), blob);
+        // plain text in JSON
+        let json = Bytes::from(r#"{"content": "hello"}"#);
+        assert_eq!(
+            log_content(None, json).unwrap(),
+            Bytes::from_static(b"hello")
+        );
+
+        assert!(log_content(None, Bytes::from("{}")).is_err());
+        assert!(log_content(None, Bytes::from(r#"{"content_base64": "%%"}"#)).is_err());
+        assert!(log_content(None, blob).is_err());
+
+        // 与成员变更、会话命令、锁命令内容相同的字节仍是客户端的日志
+        let change = Change::Remove {
+            id: "worker-1".to_string(),
+        };
+        let raw = change.entry().unwrap().content;
+        assert_eq!(
+            log_content(Some("application/octet-stream"), raw.clone()).unwrap(),
+            raw
+        );
+        let command = Command::new("alice".to_string(), 1, Bytes::from("x"));
+        let raw = command.entry().unwrap().content;
+        assert!(log_content(Some("application/octet-stream"), raw).is_ok());
+        let raw = Bytes::from_static(b"\0somepox:membership\0");
+        assert!(log_content(Some("application/octet-stream"), raw).is_ok());
+        let op = LockOp::Release {
+            owner: "alice".to_string(),
+            token: 1,
+        };
+        let raw = LockCommand::new("db".to_string(), op, 0)
+            .entry()
+            .unwrap()
+            .content;
+        assert!(log_content(Some("application/octet-stream"), raw).is_ok());
+    }
+
+    #[test]
+    fn lock_ttl_test() {
+        assert_eq!(lock_ttl(None).unwrap(), 10_000);
+        assert_eq!(lock_ttl(Some(500)).unwrap(), 500);
+        assert!(lock_ttl(Some(0)).is_err());
+        assert!(lock_ttl(Some(u64::MAX)).is_err());
+    }
 }
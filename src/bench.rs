@@ -0,0 +1,149 @@
+//! ### Benchmark
+//! Measure throughput and latency of the protocol versus the window size.
+//!
+//! A master and its workers run in turn on a single thread and talk through
+//! an in-process `Switch`, so the numbers tell the cost of the protocol and
+//! the mail boxes rather than of the network. The client keeps `window`
+//! proposals outstanding and measures each one from submission to the
+//! master reporting it applied.
+//!
+#![allow(unused)]
+
+use std::{
+    collections::HashMap,
+    fmt,
+    time::{Duration, Instant},
+};
+
+use anyhow::{anyhow, Result};
+use bytes::Bytes;
+
+use crate::{
+    address_book::AddressBook,
+    auth::Authenticator,
+    codec::Codec,
+    connection::Switch,
+    logbackend::HeapLogBackend,
+    mailbox::MailBox,
+    roles::{Master, Worker, MASTER_GROUP, WORKER_GROUP},
+};
+
+const SECRET: &[u8] = b"benchmark";
+/// 一次测量最长的时间，超过后视为集群无法形成决议
+const MAX_DURATION: Duration = Duration::from_secs(60);
+
+/// 一次测量的结果
+pub struct Report {
+    pub window: u64,
+    pub proposals: usize,
+    pub elapsed: Duration,
+    /// 按提交到应用的时间排序
+    latencies: Vec<Duration>,
+}
+
+impl Report {
+    /// 每秒应用的议题数
+    pub fn throughput(&self) -> f64 {
+        self.proposals as f64 / self.elapsed.as_secs_f64()
+    }
+
+    /// 第 `percent` 百分位的延迟
+    pub fn latency(&self, percent: usize) -> Duration {
+        let index = (self.latencies.len() * percent / 100).min(self.latencies.len() - 1);
+        self.latencies[index]
+    }
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "window {:>3}: {:>9.0} proposals/s, latency p50 {:>8.3}ms, p99 {:>8.3}ms",
+            self.window,
+            self.throughput(),
+            self.latency(50).as_secs_f64() * 1000.0,
+            self.latency(99).as_secs_f64() * 1000.0,
+        )
+    }
+}
+
+/// 以 `window` 的窗口，让 `workers` 名议员表决 `proposals` 个议题
+pub fn run(window: u64, proposals: usize, workers: usize) -> Result<Report> {
+    if proposals == 0 || workers == 0 {
+        return Err(anyhow!("nothing to measure"));
+    }
+    let switch = Switch::new();
+    let mut names = vec!["master".to_string()];
+    names.extend((1..=workers).map(|i| format!("worker-{}", i)));
+
+    let mut book = AddressBook::new();
+    for name in names.iter() {
+        book.insert(name.clone(), name.clone());
+        let group = if name == "master" {
+            MASTER_GROUP
+        } else {
+            WORKER_GROUP
+        };
+        book.join(group, name.clone());
+    }
+    let open = |name: &str| -> Result<MailBox<String, _>> {
+        Ok(MailBox::new(
+            name.to_string(),
+            Box::new(switch.connect(name)?),
+            book.clone(),
+            Authenticator::new(SECRET),
+            Codec::Binary,
+        ))
+    };
+
+    let master = Master::new(open("master")?, Box::new(HeapLogBackend::new()))?.with_window(window);
+    let workers = names[1..]
+        .iter()
+        .map(|name| open(name).and_then(|box_| Worker::new(box_, Box::new(HeapLogBackend::new()))))
+        .collect::<Result<Vec<_>>>()?;
+
+    let mut submitted = HashMap::new();
+    let mut latencies = Vec::with_capacity(proposals);
+    let start = Instant::now();
+    while latencies.len() < proposals {
+        if start.elapsed() > MAX_DURATION {
+            return Err(anyhow!("{} proposals are not applied", submitted.len()));
+        }
+
+        let issued = latencies.len() + submitted.len();
+        for i in issued..proposals.min(issued + window as usize - submitted.len()) {
+            let id = master.emmit_new_proposal(Bytes::from(format!("x = {}", i)))?;
+            submitted.insert(id, Instant::now());
+        }
+
+        master.tick(Duration::ZERO)?;
+        for worker in workers.iter() {
+            worker.tick(Duration::ZERO)?;
+        }
+        for (id, outcome) in master.take_outcomes() {
+            outcome?;
+            if let Some(at) = submitted.remove(&id) {
+                latencies.push(at.elapsed());
+            }
+        }
+    }
+
+    latencies.sort();
+    Ok(Report {
+        window,
+        proposals,
+        elapsed: start.elapsed(),
+        latencies,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn bench_applies_every_proposal_test() {
+        let report = super::run(4, 50, 3).unwrap();
+        assert_eq!(report.latencies.len(), 50);
+        assert!(report.throughput() > 0.0);
+        assert!(report.latency(50) <= report.latency(99));
+    }
+}
@@ -1,16 +1,110 @@
-use std::{collections::HashMap, fs::File, io::Read, path::PathBuf};
+use std::{
+    collections::{BTreeSet, HashMap},
+    fs::File,
+    io::Read,
+    path::PathBuf,
+};
 
 use anyhow::{anyhow, Result};
 use serde::Deserialize;
 
+use crate::{
+    auth::Authenticator,
+    codec::Codec,
+    detector::{HEARTBEAT_INTERVAL, SUSPICION_THRESHOLD},
+    mailbox::DEFAULT_CAPACITY,
+    membership::ALPHA,
+    mencius::Protocol,
+    queue::Overflow,
+    quorum::Quorums,
+};
+
 #[derive(Deserialize)]
 pub struct Config {
+    #[serde(skip)]
+    name: String,
+    id: Option<String>,
     api: Option<String>,
     address: String,
     address_book: HashMap<String, String>,
+    secret: Option<String>,
+    insecure: Option<bool>,
+    tls: Option<TlsConfig>,
+    codec: Option<Codec>,
+    mailbox: Option<MailBoxConfig>,
+    quorum: Option<Quorums>,
+    window: Option<u64>,
+    learners: Option<Vec<String>>,
+    witnesses: Option<Vec<String>>,
+    protocol: Option<Protocol>,
+    masters: Option<Vec<String>>,
+    heartbeat: Option<HeartbeatConfig>,
     log_backend: Option<LogType>,
 }
 
+/// 节点间使用双向TLS通信时的证书配置
+#[derive(Deserialize, Clone)]
+pub struct TlsConfig {
+    /// 本节点的证书
+    pub cert: PathBuf,
+    /// 本节点证书的私钥
+    pub key: PathBuf,
+    /// 签发集群内所有节点证书的CA证书
+    pub ca: PathBuf,
+}
+
+/// 邮箱的容量，未配置的项使用默认值
+#[derive(Deserialize, Clone)]
+pub struct MailBoxConfig {
+    #[serde(default = "default_capacity")]
+    pub inbox_capacity: usize,
+    #[serde(default = "default_capacity")]
+    pub outbox_capacity: usize,
+    /// 邮箱已满时如何处理新邮件
+    #[serde(default)]
+    pub overflow: Overflow,
+}
+
+fn default_capacity() -> usize {
+    DEFAULT_CAPACITY
+}
+
+impl Default for MailBoxConfig {
+    fn default() -> Self {
+        Self {
+            inbox_capacity: DEFAULT_CAPACITY,
+            outbox_capacity: DEFAULT_CAPACITY,
+            overflow: Overflow::default(),
+        }
+    }
+}
+
+/// 心跳的间隔，以及连续多少个间隔没有消息后怀疑节点失效；未配置的项使用默认值
+#[derive(Deserialize, Clone)]
+pub struct HeartbeatConfig {
+    #[serde(default = "default_interval_ms")]
+    pub interval_ms: u64,
+    #[serde(default = "default_suspicion_threshold")]
+    pub suspicion_threshold: f64,
+}
+
+fn default_interval_ms() -> u64 {
+    HEARTBEAT_INTERVAL.as_millis() as u64
+}
+
+fn default_suspicion_threshold() -> f64 {
+    SUSPICION_THRESHOLD
+}
+
+impl Default for HeartbeatConfig {
+    fn default() -> Self {
+        Self {
+            interval_ms: default_interval_ms(),
+            suspicion_threshold: default_suspicion_threshold(),
+        }
+    }
+}
+
 #[derive(Deserialize, Clone)]
 pub enum LogType {
     Heap,
@@ -18,10 +112,20 @@ pub enum LogType {
 }
 
 impl Config {
+    /// 节点的编号，未配置时使用配置的名称
+    pub fn id(&self) -> String {
+        self.id.clone().unwrap_or_else(|| self.name.clone())
+    }
+
     pub fn api(&self) -> String {
-        let mut endpoint = "127.0.0.1:8000".to_string();
-        self.api.as_ref().map(|s| endpoint = s.clone());
-        endpoint
+        self.api
+            .clone()
+            .unwrap_or_else(|| "127.0.0.1:8000".to_string())
+    }
+
+    /// 配置了的API地址，学习者只在配置时提供查询
+    pub fn configured_api(&self) -> Option<String> {
+        self.api.clone()
     }
 
     pub fn address(&self) -> String {
@@ -32,19 +136,142 @@ impl Config {
         self.address_book.clone()
     }
 
+    /// 集群共享的密钥，用于认证节点间的消息；未配置密钥时，只有明确配置了
+    /// `insecure: true` 才接受未认证的消息
+    pub fn authenticator(&self) -> Result<Authenticator> {
+        match (&self.secret, self.insecure.unwrap_or(false)) {
+            (Some(secret), _) => Ok(Authenticator::new(secret.as_bytes())),
+            (None, true) => Ok(Authenticator::insecure()),
+            (None, false) => Err(anyhow!(
+                "no `secret` configured, set `insecure: true` to accept unauthenticated peer messages"
+            )),
+        }
+    }
+
+    /// 是否接受未认证的节点间消息
+    pub fn insecure(&self) -> bool {
+        self.secret.is_none() && self.insecure.unwrap_or(false)
+    }
+
+    /// 未配置时，节点间使用明文UDP通信
+    pub fn tls(&self) -> Option<TlsConfig> {
+        self.tls.clone()
+    }
+
+    /// 发出邮件时使用的编码，默认为二进制编码
+    pub fn codec(&self) -> Codec {
+        self.codec.unwrap_or_default()
+    }
+
+    pub fn mailbox(&self) -> MailBoxConfig {
+        self.mailbox.clone().unwrap_or_default()
+    }
+
+    /// 检查邮箱已满时的处理方式：节点在同一个线程中收发邮件，`Block` 会让
+    /// 节点永远等待自己腾出空间
+    pub fn validate_mailbox(&self) -> Result<()> {
+        match self.mailbox().overflow {
+            Overflow::Block => Err(anyhow!(
+                "mailbox overflow `Block` would stall the node, use `Reject` or `DropOldest`"
+            )),
+            Overflow::DropOldest | Overflow::Reject => Ok(()),
+        }
+    }
+
+    /// 两个阶段的法定人数与议员的权重，默认均为过半数、权重均为1
+    pub fn quorum(&self) -> Quorums {
+        self.quorum.clone().unwrap_or_default()
+    }
+
+    /// 检查以地址簿中学习者与其他议长以外的节点为议员时，法定人数的配置是否
+    /// 可行，以及见证者都是议员
+    pub fn validate_quorum(&self) -> Result<()> {
+        let others = [self.learners(), self.masters()].concat();
+        let senators: BTreeSet<String> = self
+            .address_book
+            .keys()
+            .filter(|id| !others.contains(id))
+            .cloned()
+            .collect();
+        if let Some(witness) = self.witnesses().iter().find(|id| !senators.contains(*id)) {
+            return Err(anyhow!("witness `{}` is not a senator", witness));
+        }
+        let quorum = self.quorum();
+        quorum.validate_weights(&senators)?;
+        quorum.validate(&senators)
+    }
+
+    /// 地址簿中只复制决议、不参与表决的学习者，默认没有
+    pub fn learners(&self) -> Vec<String> {
+        self.learners.clone().unwrap_or_default()
+    }
+
+    /// 地址簿中只保存轮次的议员，默认没有
+    pub fn witnesses(&self) -> Vec<String> {
+        self.witnesses.clone().unwrap_or_default()
+    }
+
+    /// 议长之间分配编号的方式，默认由一名议长提议所有编号
+    pub fn protocol(&self) -> Protocol {
+        self.protocol.unwrap_or_default()
+    }
+
+    /// 地址簿中的其他议长，Mencius 模式下与本议长轮流拥有编号，默认没有
+    pub fn masters(&self) -> Vec<String> {
+        self.masters.clone().unwrap_or_default()
+    }
+
+    /// 同时表决的议题数的上限，默认为 `ALPHA`
+    pub fn window(&self) -> u64 {
+        self.window.unwrap_or(ALPHA)
+    }
+
+    /// 检查窗口在1到 `ALPHA` 之间，更大的窗口会让成员变更在之前的议题形成决议前生效
+    pub fn validate_window(&self) -> Result<()> {
+        match self.window() {
+            1..=ALPHA => Ok(()),
+            window => Err(anyhow!("window {} is not within 1..={}", window, ALPHA)),
+        }
+    }
+
+    pub fn heartbeat(&self) -> HeartbeatConfig {
+        self.heartbeat.clone().unwrap_or_default()
+    }
+
     pub fn log_backend(&self) -> LogType {
-        let mut log_backend = LogType::Heap;
-        self.log_backend.as_ref().map(|s| log_backend = s.clone());
-        log_backend
+        self.log_backend.clone().unwrap_or(LogType::Heap)
+    }
+
+    /// 保存邮箱会话编号的文件：日志保存在文件中时为日志文件名加上 `.session`；
+    /// 日志在内存中时不保存，会话编号取自时钟
+    pub fn session_file(&self) -> Option<PathBuf> {
+        match self.log_backend() {
+            LogType::Heap => None,
+            LogType::File(file_name) => Some(PathBuf::from(format!("{}.session", file_name))),
+        }
     }
 }
 
 impl Default for Config {
     fn default() -> Self {
         Self {
+            name: "master".to_string(),
+            id: None,
             api: Some("127.0.0.1:8000".to_string()),
             address: "127.0.0.1:18000".to_string(),
             address_book: HashMap::new(),
+            secret: None,
+            insecure: None,
+            tls: None,
+            codec: None,
+            mailbox: None,
+            quorum: None,
+            window: None,
+            learners: None,
+            witnesses: None,
+            protocol: None,
+            masters: None,
+            heartbeat: None,
             log_backend: Some(LogType::Heap),
         }
     }
@@ -58,13 +285,143 @@ pub fn load_config(path: PathBuf, name: Option<String>) -> Result<Config> {
 
             file_handler.read_to_string(&mut config_content)?;
 
-            let mut configs: HashMap<String, Config> = serde_yaml::from_str(&config_content)
-                .map_err(|_| anyhow!("Parse Config Error. Check your config file"))?;
-
-            configs
-                .remove(&n)
-                .ok_or(anyhow!("Config `{}` not exists.", n))
+            parse_config(&config_content, &n)
         }
         None => Ok(Config::default()),
     }
 }
+
+/// 从配置文件内容中取出名为 `name` 的配置
+pub fn parse_config(config_content: &str, name: &str) -> Result<Config> {
+    let mut configs: HashMap<String, Config> = serde_yaml::from_str(config_content)
+        .map_err(|_| anyhow!("Parse Config Error. Check your config file"))?;
+
+    let mut config = configs
+        .remove(name)
+        .ok_or(anyhow!("Config `{}` not exists.", name))?;
+    config.name = name.to_string();
+    Ok(config)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::{load_config, parse_config};
+    use crate::{membership::ALPHA, mencius::Protocol, quorum::Quorum};
+
+    #[test]
+    fn sample_config_test() {
+        let master = load_config(PathBuf::from("config.yaml"), Some("master".to_string())).unwrap();
+        assert_eq!(master.id(), "master");
+        assert!(master.authenticator().is_ok());
+        assert_eq!(master.address_book().len(), 4);
+        assert_eq!(master.quorum().phase2, Quorum::Majority);
+        master.validate_quorum().unwrap();
+        assert_eq!(master.window(), 16);
+        master.validate_window().unwrap();
+        assert_eq!(master.learners(), vec!["learner-1".to_string()]);
+        assert_eq!(master.witnesses(), vec!["worker-3".to_string()]);
+        assert_eq!(master.protocol(), Protocol::Leader);
+        assert!(master.masters().is_empty());
+
+        let worker =
+            load_config(PathBuf::from("config.yaml"), Some("worker-2".to_string())).unwrap();
+        assert_eq!(worker.address(), "127.0.0.1:18002");
+        assert_eq!(
+            worker.address_book().get("master"),
+            Some(&"127.0.0.1:18000".to_string())
+        );
+    }
+
+    #[test]
+    fn validate_quorum_test() {
+        let config = |quorum: &str| {
+            let content = format!(
+                "master:\n  address: 127.0.0.1:0\n  address_book:\n    w1: a\n    w2: b\n    w3: c\n  quorum:\n{}",
+                quorum
+            );
+            parse_config(&content, "master").unwrap()
+        };
+
+        let flexible = config("    phase1: !Size 3\n    phase2: !Size 1\n");
+        assert!(flexible.validate_quorum().is_ok());
+        let disjoint = config("    phase1: !Size 2\n    phase2: !Size 1\n");
+        assert!(disjoint.validate_quorum().is_err());
+        let weighted = config("    phase2: !Size 2\n    weights:\n      w1: 2\n");
+        assert!(weighted.validate_quorum().is_ok());
+        let typo = config("    weights:\n      w9: 2\n");
+        assert!(typo.validate_quorum().is_err());
+    }
+
+    #[test]
+    fn validate_roles_test() {
+        let config = |roles: &str| {
+            let content = format!(
+                "master:\n  address: 127.0.0.1:0\n  address_book:\n    w1: a\n    w2: b\n    l1: c\n{}",
+                roles
+            );
+            parse_config(&content, "master").unwrap()
+        };
+
+        // 学习者不计入议员，w1 与 w2 的过半数为2票
+        let learner =
+            config("  learners: [l1]\n  quorum:\n    phase1: !Size 1\n    phase2: !Size 2\n");
+        assert!(learner.validate_quorum().is_ok());
+        let voting = config("  quorum:\n    phase1: !Size 1\n    phase2: !Size 2\n");
+        assert!(voting.validate_quorum().is_err());
+
+        assert!(config("  witnesses: [w2]\n").validate_quorum().is_ok());
+        // 其他议长也不计入议员
+        let mencius = config("  protocol: Mencius\n  masters: [l1]\n  quorum:\n    phase1: !Size 1\n    phase2: !Size 2\n");
+        assert_eq!(mencius.protocol(), Protocol::Mencius);
+        assert!(mencius.validate_quorum().is_ok());
+        let witness = config("  learners: [l1]\n  witnesses: [l1]\n");
+        assert!(witness.validate_quorum().is_err());
+    }
+
+    #[test]
+    fn validate_mailbox_test() {
+        let config = |overflow: &str| {
+            let content = format!(
+                "master:\n  address: 127.0.0.1:0\n  address_book: {{}}\n  mailbox:\n    overflow: {}\n",
+                overflow
+            );
+            parse_config(&content, "master").unwrap()
+        };
+        assert!(config("Reject").validate_mailbox().is_ok());
+        assert!(config("DropOldest").validate_mailbox().is_ok());
+        assert!(config("Block").validate_mailbox().is_err());
+    }
+
+    #[test]
+    fn authenticator_test() {
+        let config = |auth: &str| {
+            let content = format!(
+                "master:\n  address: 127.0.0.1:0\n  address_book: {{}}\n{}",
+                auth
+            );
+            parse_config(&content, "master").unwrap()
+        };
+        assert!(config("  secret: test\n").authenticator().is_ok());
+        assert!(config("  insecure: true\n").authenticator().is_ok());
+        // 未配置密钥时必须明确选择不认证
+        assert!(config("").authenticator().is_err());
+        assert!(config("  insecure: false\n").authenticator().is_err());
+    }
+
+    #[test]
+    fn validate_window_test() {
+        let config = |window: u64| {
+            let content = format!(
+                "master:\n  address: 127.0.0.1:0\n  address_book: {{}}\n  window: {}\n",
+                window
+            );
+            parse_config(&content, "master").unwrap()
+        };
+        assert!(config(1).validate_window().is_ok());
+        assert!(config(ALPHA).validate_window().is_ok());
+        assert!(config(0).validate_window().is_err());
+        assert!(config(ALPHA + 1).validate_window().is_err());
+    }
+}
@@ -0,0 +1,249 @@
+//! ### In-Process Channel Connection
+//! Exchange messages between nodes living in the same process.
+//!
+//! Every `Channel` is a port plugged into a shared `Switch`. A switch either
+//! delivers packets immediately, or (in manual mode) keeps them in flight
+//! until the owner decides which one arrives next, which is what the
+//! deterministic simulator uses to reorder, drop and partition traffic.
+//!
+#![allow(unused)]
+
+use std::{
+    collections::{BTreeMap, BTreeSet, VecDeque},
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use anyhow::{anyhow, Result};
+use bytes::Bytes;
+
+use super::Connection;
+
+/// 经过 `Switch` 传递的消息
+#[derive(Clone)]
+pub struct Packet {
+    pub from: String,
+    pub to: String,
+    pub data: Bytes,
+}
+
+#[derive(Default)]
+struct SwitchState {
+    manual: bool,
+    known: BTreeSet<String>,
+    ports: BTreeMap<String, VecDeque<(String, Bytes)>>,
+    in_flight: VecDeque<Packet>,
+    cut: BTreeSet<(String, String)>,
+}
+
+impl SwitchState {
+    fn arrive(&mut self, packet: Packet) -> bool {
+        if self.cut.contains(&(packet.from.clone(), packet.to.clone())) {
+            return false;
+        }
+
+        match self.ports.get_mut(&packet.to) {
+            Some(inbox) => {
+                inbox.push_back((packet.from, packet.data));
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+#[derive(Clone, Default)]
+pub struct Switch {
+    state: Arc<Mutex<SwitchState>>,
+}
+
+impl Switch {
+    /// 发送后立即送达每个数据包的交换机
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 数据包一直在途，直到调用 `deliver` 才送达的交换机
+    pub fn manual() -> Self {
+        let switch = Self::default();
+        switch.state.lock().unwrap().manual = true;
+        switch
+    }
+
+    /// 在交换机上接入新的端口
+    pub fn connect(&self, address: &str) -> Result<Channel> {
+        let mut state = self.state.lock().map_err(|_| anyhow!("Switch poisoned"))?;
+
+        if state.ports.contains_key(address) {
+            return Err(anyhow!("address `{}` already in use", address));
+        }
+        state.known.insert(address.to_string());
+        state.ports.insert(address.to_string(), VecDeque::new());
+
+        Ok(Channel {
+            addr: address.to_string(),
+            switch: self.clone(),
+        })
+    }
+
+    /// 拔出端口，收件箱中等待的消息全部丢失
+    pub fn disconnect(&self, address: &str) {
+        if let Ok(mut state) = self.state.lock() {
+            state.ports.remove(address);
+        }
+    }
+
+    pub fn in_flight(&self) -> usize {
+        self.state.lock().map(|s| s.in_flight.len()).unwrap_or(0)
+    }
+
+    /// 送达第 `index` 个在途的数据包
+    ///
+    /// 返回该数据包，以及是否到达了接收者；经过切断的链路、或发往已拔出的
+    /// 端口的数据包丢失
+    pub fn deliver(&self, index: usize) -> Option<(Packet, bool)> {
+        let mut state = self.state.lock().ok()?;
+        let packet = state.in_flight.remove(index)?;
+        let arrived = state.arrive(packet.clone());
+        Some((packet, arrived))
+    }
+
+    /// 丢弃第 `index` 个在途的数据包
+    pub fn drop_packet(&self, index: usize) -> Option<Packet> {
+        self.state.lock().ok()?.in_flight.remove(index)
+    }
+
+    /// 切断 `a` 与 `b` 之间双向的链路
+    pub fn cut(&self, a: &str, b: &str) {
+        if let Ok(mut state) = self.state.lock() {
+            state.cut.insert((a.to_string(), b.to_string()));
+            state.cut.insert((b.to_string(), a.to_string()));
+        }
+    }
+
+    /// 恢复所有切断的链路
+    pub fn heal(&self) {
+        if let Ok(mut state) = self.state.lock() {
+            state.cut.clear();
+        }
+    }
+
+    fn send(&self, from: &str, to: &str, data: Bytes) -> Result<()> {
+        let mut state = self.state.lock().map_err(|_| anyhow!("Switch poisoned"))?;
+
+        if !state.known.contains(to) {
+            return Err(anyhow!("unknown address `{}`", to));
+        }
+
+        let packet = Packet {
+            from: from.to_string(),
+            to: to.to_string(),
+            data,
+        };
+        if state.manual {
+            state.in_flight.push_back(packet);
+        } else {
+            state.arrive(packet);
+        }
+        Ok(())
+    }
+
+    fn recv(&self, address: &str) -> Result<(String, Bytes)> {
+        self.state
+            .lock()
+            .map_err(|_| anyhow!("Switch poisoned"))?
+            .ports
+            .get_mut(address)
+            .ok_or(anyhow!("address `{}` is not connected", address))?
+            .pop_front()
+            .ok_or(anyhow!("no message for `{}`", address))
+    }
+}
+
+pub struct Channel {
+    addr: String,
+    switch: Switch,
+}
+
+impl Connection for Channel {
+    type Addr = String;
+
+    fn address(&self) -> Self::Addr {
+        self.addr.clone()
+    }
+
+    /// 发送消息：发往交换机从未见过的地址时返回错误，发往已知但已拔出的端口时
+    /// 消息被静默丢弃
+    fn send(&self, address: Self::Addr, data: Bytes) -> Result<(Self::Addr, Self::Addr, usize)> {
+        let size = data.len();
+        self.switch.send(&self.addr, &address, data)?;
+        Ok((self.addr.clone(), address, size))
+    }
+
+    /// 接收消息，不阻塞：收件箱为空时返回错误
+    fn recv(&self) -> Result<(Self::Addr, Self::Addr, Bytes)> {
+        let (remote_addr, data) = self.switch.recv(&self.addr)?;
+        Ok((self.addr.clone(), remote_addr, data))
+    }
+
+    /// 同 `recv`：时间只在交换机的持有者推进时流逝，等待也不会收到新消息
+    fn recv_timeout(&self, _timeout: Duration) -> Result<(Self::Addr, Self::Addr, Bytes)> {
+        self.recv()
+    }
+}
+
+impl Drop for Channel {
+    fn drop(&mut self) {
+        self.switch.disconnect(&self.addr);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::connection::{Connection, Switch};
+
+    #[test]
+    fn channel_immediate_delivery_test() {
+        let switch = Switch::new();
+        let a = switch.connect("a").unwrap();
+        let b = switch.connect("b").unwrap();
+
+        a.send("b".to_string(), "ping".into()).unwrap();
+        let (local, remote, data) = b.recv().unwrap();
+        assert_eq!(local, "b");
+        assert_eq!(remote, "a");
+        assert_eq!(&data[..], b"ping");
+
+        assert!(b.recv().is_err(), "inbox should be empty");
+        assert!(a.send("a,b".to_string(), "ping".into()).is_err());
+    }
+
+    #[test]
+    fn channel_manual_delivery_test() {
+        let switch = Switch::manual();
+        let a = switch.connect("a").unwrap();
+        let b = switch.connect("b").unwrap();
+
+        a.send("b".to_string(), "1".into()).unwrap();
+        a.send("b".to_string(), "2".into()).unwrap();
+        assert_eq!(switch.in_flight(), 2);
+        assert!(b.recv().is_err(), "nothing delivered yet");
+
+        // 乱序送达
+        let (_, arrived) = switch.deliver(1).unwrap();
+        assert!(arrived);
+        assert_eq!(&b.recv().unwrap().2[..], b"2");
+
+        // 切断的链路丢失数据包
+        switch.cut("a", "b");
+        let (_, arrived) = switch.deliver(0).unwrap();
+        assert!(!arrived);
+
+        // 已拔出的端口同样丢失数据包
+        switch.heal();
+        drop(b);
+        a.send("b".to_string(), "3".into()).unwrap();
+        let (_, arrived) = switch.deliver(0).unwrap();
+        assert!(!arrived);
+    }
+}
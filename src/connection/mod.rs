@@ -0,0 +1,37 @@
+#![allow(unused)]
+
+mod channel;
+mod net;
+mod tls;
+
+pub use channel::{Channel, Packet, Switch};
+pub use net::Net;
+pub use tls::Tls;
+
+use std::time::Duration;
+
+use anyhow::Result;
+use bytes::Bytes;
+
+/**
+   ### Connection 抽象描述通信过程 ###
+
+   Connection 主要通过两个方式实现通信 Send (发送) / Receive (接受)
+
+   典型的实现包括三个:
+   1. 线程：通过变量通信
+   2. 进程：通过IPC通信
+   3. 网络：通过Socket通信
+
+   连接需要能在收发线程间共享，因此要求 `Send + Sync`
+*/
+pub trait Connection: Send + Sync {
+    type Addr: Clone;
+    fn address(&self) -> Self::Addr;
+    fn send(&self, address: Self::Addr, data: Bytes) -> Result<(Self::Addr, Self::Addr, usize)>;
+    fn recv(&self) -> Result<(Self::Addr, Self::Addr, Bytes)>;
+    /// 最多等待 `timeout`，期间没有消息到达时返回错误
+    fn recv_timeout(&self, timeout: Duration) -> Result<(Self::Addr, Self::Addr, Bytes)>;
+}
+
+pub struct Ipc {}
@@ -0,0 +1,217 @@
+//! ### UDP Based Connection
+//! Use `UdpSocket` to exchange messages between nodes.
+//!
+#![allow(unused)]
+
+use anyhow::{anyhow, Result};
+use bytes::Bytes;
+use std::{
+    io::ErrorKind,
+    net::{ToSocketAddrs, UdpSocket},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc::{channel, Receiver, Sender},
+        Arc, Mutex, MutexGuard,
+    },
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+
+use super::Connection;
+
+/// 接收线程检查是否应当停止的间隔
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// IPv4 上一个UDP数据报能承载的最大长度
+const MAX_DATAGRAM: usize = 65_507;
+
+/// 发送方与接收线程共用一个未连接的套接字：数据报由 `send_to` 指定地址，
+/// 套接字因此接收来自所有节点的数据报
+pub struct Net {
+    sock: Arc<UdpSocket>,
+    handler: Option<JoinHandle<()>>,
+    /// 接收线程交来的消息，加锁使连接可以在线程间共享
+    channel: Mutex<Receiver<(String, Bytes)>>,
+    running: Arc<AtomicBool>,
+    addr: String,
+}
+
+impl Net {
+    pub fn new(endpoint: String) -> Result<Net> {
+        let sc = Arc::new(UdpSocket::bind(endpoint)?);
+        sc.set_read_timeout(Some(POLL_INTERVAL))?;
+        let addr = sc.local_addr()?.to_string();
+        let running = Arc::new(AtomicBool::new(true));
+        let (tx, rx) = channel();
+
+        let sc_ref = sc.clone();
+        let running_ref = running.clone();
+        let serv_handler = thread::Builder::new()
+            .name("udp_socket".to_string())
+            .spawn(move || {
+                let mut buffer = vec![0u8; MAX_DATAGRAM];
+
+                while running_ref.load(Ordering::Acquire) {
+                    match sc_ref.recv_from(&mut buffer) {
+                        Ok((amt, src)) => {
+                            let data = Bytes::copy_from_slice(&buffer[..amt]);
+                            if tx.send((src.to_string(), data)).is_err() {
+                                break;
+                            }
+                        }
+                        Err(e)
+                            if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {}
+                        // 之前的数据报被对方拒收，套接字仍然可用
+                        Err(e) if e.kind() == ErrorKind::ConnectionRefused => {}
+                        Err(_) => break,
+                    }
+                }
+            })?;
+
+        Ok(Net {
+            sock: sc,
+            handler: Some(serv_handler),
+            channel: Mutex::new(rx),
+            running,
+            addr,
+        })
+    }
+
+    fn lock_channel(&self) -> Result<MutexGuard<'_, Receiver<(String, Bytes)>>> {
+        self.channel
+            .lock()
+            .map_err(|_| anyhow!("receiving channel poisoned"))
+    }
+}
+
+impl Connection for Net {
+    type Addr = String;
+
+    /// 套接字实际绑定的地址
+    fn address(&self) -> Self::Addr {
+        self.addr.clone()
+    }
+
+    /// 发送消息，至多 `MAX_DATAGRAM` 字节
+    fn send(&self, address: Self::Addr, data: Bytes) -> Result<(Self::Addr, Self::Addr, usize)> {
+        let remote_address = address
+            .to_socket_addrs()?
+            .next()
+            .ok_or(anyhow!("can not resolve address `{}`", address))?;
+
+        let record_size = self.sock.send_to(&data, remote_address)?;
+        Ok((self.addr.clone(), remote_address.to_string(), record_size))
+    }
+
+    /// 接收消息，阻塞直至消息到达
+    fn recv(&self) -> Result<(Self::Addr, Self::Addr, Bytes)> {
+        let (remote_addr, data) = self.lock_channel()?.recv()?;
+        let local_addr = self.addr.clone();
+        Ok((local_addr, remote_addr, data))
+    }
+
+    /// 接收消息，最多等待 `timeout`
+    fn recv_timeout(&self, timeout: Duration) -> Result<(Self::Addr, Self::Addr, Bytes)> {
+        let (remote_addr, data) = self.lock_channel()?.recv_timeout(timeout)?;
+        Ok((self.addr.clone(), remote_addr, data))
+    }
+}
+
+impl Drop for Net {
+    fn drop(&mut self) {
+        self.running.store(false, Ordering::Release);
+        if let Some(handler) = self.handler.take() {
+            let _ = handler.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use std::thread;
+
+    use crate::connection::{Connection, Net};
+    use anyhow::Result;
+    use bytes::Bytes;
+
+    #[test]
+    fn test_1() {
+        let test_message_1 = "test_message_1".to_string();
+        let test_message_2 = "test_message_2".to_string();
+
+        let test_addr_1 = "127.0.0.1:18001";
+        let test_addr_2 = "127.0.0.1:18002";
+
+        let test_conn_1 = Net::new(test_addr_1.to_string()).unwrap();
+        let test_conn_2 = Net::new(test_addr_2.to_string()).unwrap();
+
+        let mut send_result: Result<(String, String, usize)>;
+        let mut recv_result: Result<()>;
+
+        send_result = test_conn_1.send(test_addr_2.to_string(), test_message_1.clone().into());
+        assert!(send_result.is_ok(), "Err = {}", send_result.unwrap_err());
+
+        recv_result = test_conn_2.recv().map(|(_, _, v)| {
+            let result = String::from_utf8(v.to_vec()).unwrap();
+            assert_eq!(test_message_1, result);
+            println!("conn 1 => conn 2 | OK.");
+        });
+        assert!(recv_result.is_ok(), "Err = {}", recv_result.unwrap_err());
+
+        send_result = test_conn_2.send(test_addr_1.to_string(), test_message_2.clone().into());
+        assert!(send_result.is_ok(), "Err = {}", send_result.unwrap_err());
+
+        recv_result = test_conn_1.recv().map(|(_, _, v)| {
+            let result = String::from_utf8(v.to_vec()).unwrap();
+            assert_eq!(test_message_2, result);
+            println!("conn 2 => conn 1 | OK.");
+        });
+        assert!(recv_result.is_ok(), "Err = {}", recv_result.unwrap_err());
+    }
+
+    #[test]
+    fn multi_peer_test() {
+        let peers: Vec<Net> = (0..3)
+            .map(|_| Net::new("127.0.0.1:0".to_string()).unwrap())
+            .collect();
+        let addresses: Vec<String> = peers.iter().map(|peer| peer.address()).collect();
+
+        let handlers: Vec<_> = peers
+            .into_iter()
+            .enumerate()
+            .map(|(i, peer)| {
+                let addresses = addresses.clone();
+                thread::spawn(move || {
+                    let me = addresses[i].clone();
+                    let others: Vec<String> = addresses.into_iter().filter(|a| *a != me).collect();
+
+                    for other in others.iter() {
+                        let (local, remote, size) = peer
+                            .send(other.clone(), format!("hello from {}", me).into())
+                            .unwrap();
+                        assert_eq!(local, me);
+                        assert_eq!(&remote, other);
+                        assert_eq!(size, format!("hello from {}", me).len());
+                    }
+
+                    let mut senders = Vec::new();
+                    for _ in others.iter() {
+                        let (local, remote, data) = peer.recv().unwrap();
+                        assert_eq!(local, me);
+                        assert_eq!(data, Bytes::from(format!("hello from {}", remote)));
+                        senders.push(remote);
+                    }
+                    let mut expected = others.clone();
+                    expected.sort();
+                    senders.sort();
+                    assert_eq!(senders, expected);
+                })
+            })
+            .collect();
+
+        for handler in handlers {
+            handler.join().unwrap();
+        }
+    }
+}
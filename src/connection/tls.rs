@@ -0,0 +1,529 @@
+//! ### TLS Based Connection
+//! Exchange messages over TCP, encrypted with mutual TLS: every node shows a
+//! certificate signed by the cluster CA, and refuses peers that can not.
+//!
+//! Every peer sent to gets a writer thread, which dials the peer and keeps
+//! the connection open; `send` only queues the message for that thread, so
+//! a slow or unreachable peer never blocks sending to the others. Incoming
+//! connections are served by one thread each. Messages are framed as
+//!
+//!     | length: u32 | payload |
+//!
+//! and the first frame of every connection carries the listening address of
+//! the dialing node, which is reported as the remote address of every
+//! message received on that connection. The host of that address must be
+//! one the dialing node's verified certificate is issued for, so a node can
+//! not claim the address of a host it holds no certificate for.
+//!
+#![allow(unused)]
+
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::{BufReader, ErrorKind, Read, Write},
+    net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs},
+    path::Path,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc::{
+            channel, sync_channel, Receiver, RecvTimeoutError, Sender, SyncSender, TrySendError,
+        },
+        Arc, Mutex, MutexGuard,
+    },
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+
+use anyhow::{anyhow, Result};
+use bytes::Bytes;
+use rustls::{
+    pki_types::{CertificateDer, PrivateKeyDer, ServerName},
+    server::WebPkiClientVerifier,
+    ClientConfig, ClientConnection, RootCertStore, ServerConfig, ServerConnection, StreamOwned,
+};
+
+use webpki::EndEntityCert;
+
+use super::Connection;
+
+/// How often serving threads wake up to check whether they should stop.
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(3);
+const MAX_FRAME_LEN: usize = 16 * 1024 * 1024;
+/// How many messages may wait for a peer's writer thread before new ones
+/// are dropped.
+const PEER_QUEUE_LEN: usize = 1024;
+
+type ClientStream = StreamOwned<ClientConnection, TcpStream>;
+
+/// 向一个节点发送消息的线程
+struct Peer {
+    queue: SyncSender<Bytes>,
+    handler: JoinHandle<()>,
+}
+
+pub struct Tls {
+    addr: String,
+    client_config: Arc<ClientConfig>,
+    peers: Mutex<HashMap<String, Peer>>,
+    /// 接收线程交来的消息，加锁使连接可以在线程间共享
+    channel: Mutex<Receiver<(String, Bytes)>>,
+    running: Arc<AtomicBool>,
+    handler: Option<JoinHandle<()>>,
+}
+
+impl Tls {
+    /// Listen on `endpoint`, presenting the certificate `cert` with its
+    /// private key `key`; peers must present a certificate signed by `ca`.
+    pub fn new(endpoint: String, cert: &Path, key: &Path, ca: &Path) -> Result<Tls> {
+        let certs = load_certs(cert)?;
+        let key = load_key(key)?;
+
+        let mut roots = RootCertStore::empty();
+        for ca_cert in load_certs(ca)? {
+            roots.add(ca_cert)?;
+        }
+        let roots = Arc::new(roots);
+
+        let verifier = WebPkiClientVerifier::builder(roots.clone()).build()?;
+        let mut server_config = ServerConfig::builder()
+            .with_client_cert_verifier(verifier)
+            .with_single_cert(certs.clone(), key.clone_key())?;
+        server_config.send_tls13_tickets = 0;
+
+        let client_config = ClientConfig::builder()
+            .with_root_certificates(roots)
+            .with_client_auth_cert(certs, key)?;
+
+        let listener = TcpListener::bind(endpoint)?;
+        listener.set_nonblocking(true)?;
+        let addr = listener.local_addr()?.to_string();
+
+        let running = Arc::new(AtomicBool::new(true));
+        let (tx, rx) = channel();
+
+        let running_ref = running.clone();
+        let server_config = Arc::new(server_config);
+        let handler = thread::Builder::new()
+            .name("tls_listener".to_string())
+            .spawn(move || accept(listener, server_config, tx, running_ref))?;
+
+        Ok(Tls {
+            addr,
+            client_config: Arc::new(client_config),
+            peers: Mutex::new(HashMap::new()),
+            channel: Mutex::new(rx),
+            running,
+            handler: Some(handler),
+        })
+    }
+
+    fn lock_channel(&self) -> Result<MutexGuard<'_, Receiver<(String, Bytes)>>> {
+        self.channel
+            .lock()
+            .map_err(|_| anyhow!("receiving channel poisoned"))
+    }
+
+    /// Start the writer thread for the peer at `address`.
+    fn spawn_peer(&self, address: &str) -> Result<Peer> {
+        let (queue, rx) = sync_channel(PEER_QUEUE_LEN);
+        let local = self.addr.clone();
+        let remote = address.to_string();
+        let config = self.client_config.clone();
+        let running = self.running.clone();
+        let handler = thread::Builder::new()
+            .name("tls_writer".to_string())
+            .spawn(move || write_to(&local, &remote, config, rx, running))?;
+        Ok(Peer { queue, handler })
+    }
+}
+
+impl Connection for Tls {
+    type Addr = String;
+
+    /// the address the listener is actually bound to
+    fn address(&self) -> Self::Addr {
+        self.addr.clone()
+    }
+
+    /// send a message, queued for the peer's writer thread: a message the
+    /// peer can not take in time is lost, as with UDP
+    fn send(&self, address: Self::Addr, data: Bytes) -> Result<(Self::Addr, Self::Addr, usize)> {
+        let len = data.len();
+        let queue = {
+            let mut peers = self
+                .peers
+                .lock()
+                .map_err(|_| anyhow!("peer table poisoned"))?;
+            match peers.get(&address) {
+                Some(peer) if !peer.handler.is_finished() => peer.queue.clone(),
+                _ => {
+                    let peer = self.spawn_peer(&address)?;
+                    let queue = peer.queue.clone();
+                    peers.insert(address.clone(), peer);
+                    queue
+                }
+            }
+        };
+
+        match queue.try_send(data) {
+            Ok(()) => Ok((self.addr.clone(), address, len)),
+            Err(TrySendError::Full(_)) => {
+                Err(anyhow!("too many messages queued for `{}`", address))
+            }
+            Err(TrySendError::Disconnected(_)) => Err(anyhow!("writer for `{}` stopped", address)),
+        }
+    }
+
+    /// recv message, block until a message arrives
+    fn recv(&self) -> Result<(Self::Addr, Self::Addr, Bytes)> {
+        let (remote_addr, data) = self.lock_channel()?.recv()?;
+        Ok((self.addr.clone(), remote_addr, data))
+    }
+
+    /// recv message, waiting at most `timeout`
+    fn recv_timeout(&self, timeout: Duration) -> Result<(Self::Addr, Self::Addr, Bytes)> {
+        let (remote_addr, data) = self.lock_channel()?.recv_timeout(timeout)?;
+        Ok((self.addr.clone(), remote_addr, data))
+    }
+}
+
+impl Drop for Tls {
+    fn drop(&mut self) {
+        self.running.store(false, Ordering::Release);
+        if let Some(handler) = self.handler.take() {
+            let _ = handler.join();
+        }
+        if let Ok(peers) = self.peers.get_mut() {
+            for (_, peer) in peers.drain() {
+                drop(peer.queue);
+                let _ = peer.handler.join();
+            }
+        }
+    }
+}
+
+fn load_certs(path: &Path) -> Result<Vec<CertificateDer<'static>>> {
+    let mut reader = BufReader::new(File::open(path)?);
+    let certs = rustls_pemfile::certs(&mut reader).collect::<Result<Vec<_>, _>>()?;
+
+    if certs.is_empty() {
+        return Err(anyhow!("no certificate found in {}", path.display()));
+    }
+    Ok(certs)
+}
+
+fn load_key(path: &Path) -> Result<PrivateKeyDer<'static>> {
+    let mut reader = BufReader::new(File::open(path)?);
+    rustls_pemfile::private_key(&mut reader)?
+        .ok_or(anyhow!("no private key found in {}", path.display()))
+}
+
+fn dial(local: &str, address: &str, config: Arc<ClientConfig>) -> Result<ClientStream> {
+    let remote = address
+        .to_socket_addrs()?
+        .next()
+        .ok_or(anyhow!("can not resolve address `{}`", address))?;
+
+    let tcp = TcpStream::connect_timeout(&remote, CONNECT_TIMEOUT)?;
+    tcp.set_nodelay(true)?;
+    let conn = ClientConnection::new(config, ServerName::from(remote.ip()))?;
+
+    let mut stream = StreamOwned::new(conn, tcp);
+    write_frame(&mut stream, local.as_bytes())?;
+    Ok(stream)
+}
+
+/// Write every queued message to the peer at `remote`, dialing it whenever
+/// not connected; a message which can not be written is dropped.
+fn write_to(
+    local: &str,
+    remote: &str,
+    config: Arc<ClientConfig>,
+    rx: Receiver<Bytes>,
+    running: Arc<AtomicBool>,
+) {
+    let mut stream: Option<ClientStream> = None;
+
+    while running.load(Ordering::Acquire) {
+        let data = match rx.recv_timeout(POLL_INTERVAL) {
+            Ok(data) => data,
+            Err(RecvTimeoutError::Timeout) => continue,
+            Err(RecvTimeoutError::Disconnected) => break,
+        };
+
+        if stream.is_none() {
+            stream = dial(local, remote, config.clone()).ok();
+        }
+        // 连接已损坏，下一条消息重新建立
+        if let Some(conn) = stream.as_mut() {
+            if write_frame(conn, &data).is_err() {
+                stream = None;
+            }
+        }
+    }
+}
+
+fn write_frame(stream: &mut impl Write, payload: &[u8]) -> Result<()> {
+    let len = u32::try_from(payload.len()).map_err(|_| anyhow!("message too large"))?;
+    stream.write_all(&len.to_be_bytes())?;
+    stream.write_all(payload)?;
+    stream.flush()?;
+    Ok(())
+}
+
+/// Take the first complete frame out of `buffer`.
+fn take_frame(buffer: &mut Vec<u8>) -> Result<Option<Bytes>> {
+    if buffer.len() < 4 {
+        return Ok(None);
+    }
+    let len = u32::from_be_bytes([buffer[0], buffer[1], buffer[2], buffer[3]]) as usize;
+    if len > MAX_FRAME_LEN {
+        return Err(anyhow!("frame of {} bytes is too large", len));
+    }
+    if buffer.len() < 4 + len {
+        return Ok(None);
+    }
+
+    let frame = Bytes::copy_from_slice(&buffer[4..4 + len]);
+    buffer.drain(..4 + len);
+    Ok(Some(frame))
+}
+
+fn accept(
+    listener: TcpListener,
+    config: Arc<ServerConfig>,
+    tx: Sender<(String, Bytes)>,
+    running: Arc<AtomicBool>,
+) {
+    let mut servers = Vec::new();
+
+    while running.load(Ordering::Acquire) {
+        match listener.accept() {
+            Ok((stream, _)) => {
+                let config = config.clone();
+                let tx = tx.clone();
+                let running = running.clone();
+                let server = thread::Builder::new()
+                    .name("tls_peer".to_string())
+                    .spawn(move || {
+                        let _ = serve(stream, config, tx, running);
+                    });
+                if let Ok(server) = server {
+                    servers.push(server);
+                }
+            }
+            // 没有新连接，或接受连接失败，稍后重试
+            Err(_) => thread::sleep(POLL_INTERVAL),
+        }
+        servers.retain(|server: &JoinHandle<()>| !server.is_finished());
+    }
+
+    for server in servers {
+        let _ = server.join();
+    }
+}
+
+/// Read frames from one incoming connection until it closes.
+fn serve(
+    stream: TcpStream,
+    config: Arc<ServerConfig>,
+    tx: Sender<(String, Bytes)>,
+    running: Arc<AtomicBool>,
+) -> Result<()> {
+    stream.set_nonblocking(false)?;
+    stream.set_read_timeout(Some(POLL_INTERVAL))?;
+    let mut tls = StreamOwned::new(ServerConnection::new(config)?, stream);
+
+    let mut peer: Option<String> = None;
+    let mut pending = Vec::new();
+    let mut buffer = [0u8; 4096];
+
+    while running.load(Ordering::Acquire) {
+        match tls.read(&mut buffer) {
+            Ok(0) => break,
+            Ok(amt) => {
+                pending.extend_from_slice(&buffer[..amt]);
+                while let Some(frame) = take_frame(&mut pending)? {
+                    match &peer {
+                        // 第一帧为对方的监听地址，须与对方的证书相符
+                        None => {
+                            let claimed = String::from_utf8(frame.to_vec())?;
+                            check_identity(&tls.conn, &claimed)?;
+                            peer = Some(claimed);
+                        }
+                        Some(peer) => tx.send((peer.clone(), frame))?,
+                    }
+                }
+            }
+            Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {}
+            Err(e) => return Err(e.into()),
+        }
+    }
+    Ok(())
+}
+
+/// Check that the verified certificate of the peer is issued for the host of
+/// the address `claimed`.
+fn check_identity(conn: &ServerConnection, claimed: &str) -> Result<()> {
+    let cert = conn
+        .peer_certificates()
+        .and_then(|certs| certs.first())
+        .ok_or(anyhow!("peer presented no certificate"))?;
+    let host = claimed
+        .rsplit_once(':')
+        .map(|(host, _)| host.trim_start_matches('[').trim_end_matches(']'))
+        .ok_or(anyhow!("malformed peer address `{}`", claimed))?;
+    let name = ServerName::try_from(host.to_string())
+        .map_err(|_| anyhow!("malformed peer address `{}`", claimed))?;
+
+    EndEntityCert::try_from(cert)
+        .and_then(|cert| cert.verify_is_valid_for_subject_name(&name))
+        .map_err(|e| anyhow!("peer certificate is not valid for `{}`: {}", claimed, e))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        fs,
+        path::{Path, PathBuf},
+        time::{Duration, Instant},
+    };
+
+    use bytes::Bytes;
+    use rcgen::{BasicConstraints, Certificate, CertificateParams, IsCa, KeyPair};
+
+    use crate::connection::{Connection, Tls};
+
+    struct Ca {
+        cert: Certificate,
+        key: KeyPair,
+    }
+
+    impl Ca {
+        fn new() -> Self {
+            let mut params = CertificateParams::new(Vec::new()).unwrap();
+            params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+            let key = KeyPair::generate().unwrap();
+            Self {
+                cert: params.self_signed(&key).unwrap(),
+                key,
+            }
+        }
+
+        /// Write the CA certificate and a node certificate for `host` signed
+        /// by this CA into `dir`, returns (cert, key, ca) paths.
+        fn issue(&self, dir: &Path, name: &str, host: &str) -> (PathBuf, PathBuf, PathBuf) {
+            let node_key = KeyPair::generate().unwrap();
+            let node_cert = CertificateParams::new(vec![host.to_string()])
+                .unwrap()
+                .signed_by(&node_key, &self.cert, &self.key)
+                .unwrap();
+
+            let paths = (
+                dir.join(format!("{}.crt", name)),
+                dir.join(format!("{}.key", name)),
+                dir.join(format!("{}-ca.crt", name)),
+            );
+            fs::write(&paths.0, node_cert.pem()).unwrap();
+            fs::write(&paths.1, node_key.serialize_pem()).unwrap();
+            fs::write(&paths.2, self.cert.pem()).unwrap();
+            paths
+        }
+    }
+
+    fn test_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("somepox-{}-{}", name, std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn node(dir: &Path, ca: &Ca, name: &str) -> Tls {
+        node_for(dir, ca, name, "127.0.0.1")
+    }
+
+    fn node_for(dir: &Path, ca: &Ca, name: &str, host: &str) -> Tls {
+        let (cert, key, ca) = ca.issue(dir, name, host);
+        Tls::new("127.0.0.1:0".to_string(), &cert, &key, &ca).unwrap()
+    }
+
+    #[test]
+    fn mutual_tls_exchange_test() {
+        let dir = test_dir("tls-exchange");
+        let ca = Ca::new();
+        let node_1 = node(&dir, &ca, "node-1");
+        let node_2 = node(&dir, &ca, "node-2");
+
+        node_1
+            .send(node_2.address(), Bytes::from("hello node-2"))
+            .unwrap();
+        let (local, remote, data) = node_2.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert_eq!(local, node_2.address());
+        assert_eq!(remote, node_1.address());
+        assert_eq!(data, Bytes::from("hello node-2"));
+
+        node_2
+            .send(node_1.address(), Bytes::from("hello node-1"))
+            .unwrap();
+        node_2
+            .send(node_1.address(), Bytes::from(vec![7u8; 100_000]))
+            .unwrap();
+        let (_, remote, data) = node_1.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert_eq!(remote, node_2.address());
+        assert_eq!(data, Bytes::from("hello node-1"));
+        let (_, _, data) = node_1.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert_eq!(data.len(), 100_000);
+
+        let _ = fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn reject_peer_from_other_ca_test() {
+        let dir = test_dir("tls-reject");
+        let node_1 = node(&dir, &Ca::new(), "node-1");
+        let intruder = node(&dir, &Ca::new(), "intruder");
+
+        // the handshake fails on one side or the other, nothing is delivered
+        let _ = intruder.send(node_1.address(), Bytes::from("let me in"));
+        let _ = intruder.send(node_1.address(), Bytes::from("let me in"));
+        assert!(node_1.recv_timeout(Duration::from_millis(500)).is_err());
+
+        let _ = node_1.send(intruder.address(), Bytes::from("who are you"));
+        assert!(intruder.recv_timeout(Duration::from_millis(500)).is_err());
+
+        let _ = fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn unreachable_peer_does_not_block_test() {
+        let dir = test_dir("tls-unreachable");
+        let ca = Ca::new();
+        let node_1 = node(&dir, &ca, "node-1");
+        let node_2 = node(&dir, &ca, "node-2");
+
+        // 连接一个不可达的地址只占用它自己的发送线程
+        let start = Instant::now();
+        let _ = node_1.send("10.255.255.1:18000".to_string(), Bytes::from("anyone?"));
+        node_1.send(node_2.address(), Bytes::from("hello")).unwrap();
+        assert!(start.elapsed() < Duration::from_secs(1));
+
+        let (_, _, data) = node_2.recv_timeout(Duration::from_secs(2)).unwrap();
+        assert_eq!(data, Bytes::from("hello"));
+
+        let _ = fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn reject_address_not_in_certificate_test() {
+        let dir = test_dir("tls-identity");
+        let ca = Ca::new();
+        let node_1 = node(&dir, &ca, "node-1");
+        // 证书由同一CA签发，但不是为它声称的监听地址签发的
+        let impostor = node_for(&dir, &ca, "impostor", "10.0.0.9");
+
+        let _ = impostor.send(node_1.address(), Bytes::from("i am 127.0.0.1"));
+        assert!(node_1.recv_timeout(Duration::from_millis(500)).is_err());
+
+        let _ = fs::remove_dir_all(dir);
+    }
+}
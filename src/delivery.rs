@@ -0,0 +1,371 @@
+//! ### Reliable Delivery
+//! Bookkeeping for at-least-once delivery over lossy transports, with
+//! duplicate suppression on the receiving side.
+//!
+//! Every mail a `MailBox` sends carries the sender's session and a sequence
+//! id. The receiver acknowledges each copy it gets, and the sender keeps
+//! retransmitting with exponential backoff until the ack arrives or it runs
+//! out of attempts. The session is picked when the mail box is opened, so
+//! a restarted node is not mistaken for a replay of its previous life.
+//!
+//! Sessions are kept in a `SessionFile`: every start takes a session higher
+//! than any used before, whatever the wall clock says, and a receiver
+//! remembers the latest session of each sender across its own restarts, so
+//! mail of an older session is still dropped. Mail of a sender's current
+//! session may be delivered again after the receiver restarts. Without a
+//! session file the session is read from the wall clock.
+//!
+//! Mail is numbered per (sender, recipient) pair, so a recipient sees every
+//! id of its sender unless the sender gave up on one. Ids more than
+//! `DEDUP_WINDOW` behind the newest one received are taken as given up, which
+//! keeps the record of each sender bounded.
+//!
+#![allow(unused)]
+
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fs,
+    io::{ErrorKind, Write},
+    path::PathBuf,
+    time::Duration,
+};
+
+use anyhow::Result;
+use bytes::Bytes;
+use serde::{Deserialize, Serialize};
+
+use crate::address_book::{NodeId, Recipient};
+
+/// 已收到的编号之后，最多记录多少个编号；更早的邮件视为发件人已放弃
+pub const DEDUP_WINDOW: u64 = 1024;
+
+/// 两个邮箱之间传递的帧
+#[derive(Serialize, Deserialize)]
+pub enum Frame<Content> {
+    Mail {
+        session: u64,
+        id: u64,
+        /// 发件人填写的全部收件人，组不展开
+        to: Vec<Recipient>,
+        content: Content,
+    },
+    Ack {
+        session: u64,
+        id: u64,
+    },
+    /// 心跳，不需要确认
+    Ping,
+}
+
+#[derive(Clone, Debug)]
+pub struct RetryPolicy {
+    /// 首次重传前等待的时间，之后每次翻倍
+    pub initial: Duration,
+    /// 两次重传之间最长的等待时间
+    pub max: Duration,
+    /// 最多发送的次数（含首次发送），之后放弃该邮件
+    pub max_attempts: u32,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            initial: Duration::from_millis(50),
+            max: Duration::from_secs(2),
+            max_attempts: 10,
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// 第 `attempts` 次发送后，到下次重传的等待时间
+    pub fn backoff(&self, attempts: u32) -> Duration {
+        let factor = 1u32
+            .checked_shl(attempts.saturating_sub(1))
+            .unwrap_or(u32::MAX);
+        self.initial.saturating_mul(factor).min(self.max)
+    }
+}
+
+/// 累计的投递统计，用于监控
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct DeliveryStats {
+    /// 首次发送的邮件份数
+    pub sent: u64,
+    /// 重传的邮件份数
+    pub retransmitted: u64,
+    /// 收到确认的邮件份数
+    pub acked: u64,
+    /// 重传次数耗尽仍未确认而放弃的邮件份数
+    pub expired: u64,
+    /// 收到并丢弃的重复邮件份数
+    pub duplicates: u64,
+    /// 仍在等待确认的邮件份数
+    pub pending: u64,
+}
+
+/// 一份等待确认的邮件
+pub struct Pending {
+    pub data: Bytes,
+    pub attempts: u32,
+    pub next_retry: Duration,
+}
+
+/// 等待确认的邮件，以 (邮件编号, 收件人) 索引
+#[derive(Default)]
+pub struct Outbox {
+    pending: BTreeMap<(u64, NodeId), Pending>,
+}
+
+impl Outbox {
+    pub fn insert(&mut self, id: u64, receiver: NodeId, data: Bytes, now: Duration) {
+        self.pending.insert(
+            (id, receiver),
+            Pending {
+                data,
+                attempts: 0,
+                next_retry: now,
+            },
+        );
+    }
+
+    /// 收到确认，返回该邮件是否仍在等待确认
+    pub fn ack(&mut self, id: u64, receiver: &str) -> bool {
+        self.pending.remove(&(id, receiver.to_string())).is_some()
+    }
+
+    /// 到了发送时间的邮件
+    pub fn due(&self, now: Duration) -> Vec<(u64, NodeId)> {
+        self.pending
+            .iter()
+            .filter(|(_, pending)| pending.next_retry <= now)
+            .map(|(key, _)| key.clone())
+            .collect()
+    }
+
+    pub fn get_mut(&mut self, key: &(u64, NodeId)) -> Option<&mut Pending> {
+        self.pending.get_mut(key)
+    }
+
+    pub fn remove(&mut self, key: &(u64, NodeId)) {
+        self.pending.remove(key);
+    }
+
+    pub fn len(&self) -> usize {
+        self.pending.len()
+    }
+}
+
+/// 记录每个发件人已收到的邮件编号
+#[derive(Default)]
+pub struct Dedup {
+    senders: BTreeMap<NodeId, Seen>,
+}
+
+/// 编号不超过 `floor` 的邮件都已收到，`above` 为其余已收到的编号
+#[derive(Default)]
+struct Seen {
+    session: u64,
+    floor: u64,
+    above: BTreeSet<u64>,
+}
+
+impl Dedup {
+    /// 从每个发件人最新的会话开始记录，这些会话中的编号都视为尚未收到
+    pub fn restore(sessions: &BTreeMap<NodeId, u64>) -> Self {
+        let senders = sessions
+            .iter()
+            .map(|(sender, session)| {
+                let seen = Seen {
+                    session: *session,
+                    ..Seen::default()
+                };
+                (sender.clone(), seen)
+            })
+            .collect();
+        Self { senders }
+    }
+
+    /// 发件人最新的会话
+    pub fn session(&self, sender: &str) -> Option<u64> {
+        self.senders.get(sender).map(|seen| seen.session)
+    }
+
+    /// 是否已经收到过该邮件，来自发件人旧会话的邮件一律视为已收到
+    pub fn seen(&self, sender: &str, session: u64, id: u64) -> bool {
+        match self.senders.get(sender) {
+            Some(seen) if session == seen.session => id <= seen.floor || seen.above.contains(&id),
+            Some(seen) => session < seen.session,
+            None => false,
+        }
+    }
+
+    /// 记录的编号总数，不含 `floor` 以下的编号
+    pub fn tracked(&self) -> usize {
+        self.senders.values().map(|seen| seen.above.len()).sum()
+    }
+
+    /// 记录收到的邮件，发件人新的会话清空之前的记录
+    pub fn record(&mut self, sender: &str, session: u64, id: u64) {
+        let seen = self.senders.entry(sender.to_string()).or_default();
+        if session < seen.session {
+            return;
+        }
+        if session > seen.session {
+            *seen = Seen {
+                session,
+                ..Seen::default()
+            };
+        }
+
+        if id > seen.floor {
+            seen.above.insert(id);
+        }
+        // 落后太多的编号不会再收到，不再等待
+        if id > seen.floor.saturating_add(DEDUP_WINDOW) {
+            seen.floor = id - DEDUP_WINDOW;
+            seen.above = seen.above.split_off(&(seen.floor + 1));
+        }
+        while seen.above.remove(&(seen.floor + 1)) {
+            seen.floor += 1;
+        }
+    }
+}
+
+/// 会话文件的内容
+#[derive(Serialize, Deserialize, Default)]
+struct Sessions {
+    /// 本节点用过的最大会话编号
+    own: u64,
+    /// 每个发件人最新的会话编号
+    senders: BTreeMap<NodeId, u64>,
+}
+
+/// 保存会话编号的文件，每次改动都先写入临时文件再替换
+pub struct SessionFile {
+    path: PathBuf,
+    sessions: Sessions,
+}
+
+impl SessionFile {
+    /// 打开会话文件，不存在时为空；内容无法解析时返回错误
+    pub fn open(path: impl Into<PathBuf>) -> Result<Self> {
+        let path = path.into();
+        let sessions = match fs::read(&path) {
+            Ok(raw) => bincode::deserialize(&raw)?,
+            Err(e) if e.kind() == ErrorKind::NotFound => Sessions::default(),
+            Err(e) => return Err(e.into()),
+        };
+        Ok(Self { path, sessions })
+    }
+
+    /// 本次启动的会话编号，写入文件后才使用：比之前用过的都大，不受时钟回拨
+    /// 影响；时钟的读数 `now` 更大时使用 `now`，会话文件丢失后仍大于之前的会话
+    pub fn next_session(&mut self, now: u64) -> Result<u64> {
+        let session = now.max(self.sessions.own + 1);
+        self.sessions.own = session;
+        self.save()?;
+        Ok(session)
+    }
+
+    /// 每个发件人最新的会话编号
+    pub fn senders(&self) -> &BTreeMap<NodeId, u64> {
+        &self.sessions.senders
+    }
+
+    /// 记录发件人新的会话
+    pub fn record(&mut self, sender: &str, session: u64) -> Result<()> {
+        self.sessions.senders.insert(sender.to_string(), session);
+        self.save()
+    }
+
+    fn save(&self) -> Result<()> {
+        let mut name = self.path.clone().into_os_string();
+        name.push(".tmp");
+        let tmp = PathBuf::from(name);
+        let mut file = fs::File::create(&tmp)?;
+        file.write_all(&bincode::serialize(&self.sessions)?)?;
+        file.sync_all()?;
+        fs::rename(&tmp, &self.path)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, time::Duration};
+
+    use super::{Dedup, RetryPolicy, SessionFile, DEDUP_WINDOW};
+
+    #[test]
+    fn backoff_test() {
+        let policy = RetryPolicy {
+            initial: Duration::from_millis(10),
+            max: Duration::from_millis(50),
+            max_attempts: 5,
+        };
+        let waits: Vec<u128> = (1..=5).map(|n| policy.backoff(n).as_millis()).collect();
+        assert_eq!(waits, vec![10, 20, 40, 50, 50]);
+        assert_eq!(policy.backoff(200), Duration::from_millis(50));
+    }
+
+    #[test]
+    fn dedup_test() {
+        let mut dedup = Dedup::default();
+        let mut first_time = |sender: &str, session, id| {
+            let new = !dedup.seen(sender, session, id);
+            dedup.record(sender, session, id);
+            new
+        };
+
+        assert!(first_time("w1", 1, 2));
+        assert!(first_time("w1", 1, 1));
+        assert!(!first_time("w1", 1, 1));
+        assert!(!first_time("w1", 1, 2));
+        assert!(first_time("w2", 1, 1), "分别记录每个发件人");
+
+        // w1 重启
+        assert!(first_time("w1", 2, 1));
+        assert!(!first_time("w1", 1, 3), "旧会话的邮件");
+    }
+
+    #[test]
+    fn dedup_stays_bounded_test() {
+        let mut dedup = Dedup::default();
+        // 发件人放弃了所有奇数编号的邮件
+        for id in (2..20 * DEDUP_WINDOW).step_by(2) {
+            assert!(!dedup.seen("w1", 1, id));
+            dedup.record("w1", 1, id);
+            assert!(dedup.tracked() as u64 <= DEDUP_WINDOW);
+        }
+        assert!(dedup.seen("w1", 1, 20 * DEDUP_WINDOW - 2));
+        assert!(dedup.seen("w1", 1, 3), "早已放弃的编号");
+        assert!(!dedup.seen("w1", 1, 20 * DEDUP_WINDOW - 1));
+    }
+
+    #[test]
+    fn session_file_test() {
+        let path = std::env::temp_dir().join(format!("somepox-{}.session", std::process::id()));
+        let _ = fs::remove_file(&path);
+
+        let mut file = SessionFile::open(&path).unwrap();
+        assert_eq!(file.next_session(1000).unwrap(), 1000);
+        file.record("w1", 7).unwrap();
+
+        // 重启时时钟回拨，会话编号仍比之前的大
+        let mut file = SessionFile::open(&path).unwrap();
+        assert_eq!(file.next_session(10).unwrap(), 1001);
+        assert_eq!(file.senders().get("w1"), Some(&7));
+
+        // 收件人重启后仍拒绝旧会话的邮件，当前会话的编号重新记录
+        let mut dedup = Dedup::restore(file.senders());
+        assert!(dedup.seen("w1", 6, 1));
+        assert!(!dedup.seen("w1", 7, 1));
+        dedup.record("w1", 7, 1);
+        assert!(dedup.seen("w1", 7, 1));
+
+        fs::write(&path, b"\x01").unwrap();
+        assert!(SessionFile::open(&path).is_err());
+        let _ = fs::remove_file(&path);
+    }
+}
@@ -0,0 +1,115 @@
+//! ### Failure Detector
+//! Tell which peers are alive from when they were last heard of.
+//!
+//! Nodes ping their peers every `interval`, and any authenticated message
+//! counts as a sign of life. The suspicion of a peer is the number of
+//! intervals passed since it was last heard of; once it reaches the
+//! threshold the peer is suspected to have failed. A peer never heard of is
+//! suspected from the start.
+//!
+#![allow(unused)]
+
+use std::{collections::BTreeMap, time::Duration};
+
+use serde::Serialize;
+
+use crate::address_book::NodeId;
+
+/// 默认发送心跳的间隔
+pub const HEARTBEAT_INTERVAL: Duration = Duration::from_millis(100);
+/// 默认连续多少个间隔没有消息后怀疑节点失效
+pub const SUSPICION_THRESHOLD: f64 = 5.0;
+
+/// 一个节点的存活情况
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct PeerStatus {
+    pub id: NodeId,
+    pub alive: bool,
+    /// 距上次收到该节点消息的毫秒数，从未收到时为 `None`
+    pub silent_ms: Option<u64>,
+    /// 怀疑程度，从未收到时为 `None`
+    pub suspicion: Option<f64>,
+}
+
+#[derive(Clone)]
+pub struct FailureDetector {
+    interval: Duration,
+    threshold: f64,
+    last_seen: BTreeMap<NodeId, Duration>,
+}
+
+impl Default for FailureDetector {
+    fn default() -> Self {
+        Self::new(HEARTBEAT_INTERVAL, SUSPICION_THRESHOLD)
+    }
+}
+
+impl FailureDetector {
+    /// `interval` 至少为1毫秒
+    pub fn new(interval: Duration, threshold: f64) -> Self {
+        Self {
+            interval: interval.max(Duration::from_millis(1)),
+            threshold,
+            last_seen: BTreeMap::new(),
+        }
+    }
+
+    pub fn interval(&self) -> Duration {
+        self.interval
+    }
+
+    /// 在 `now` 收到了 `peer` 的消息
+    pub fn heartbeat(&mut self, peer: &str, now: Duration) {
+        let last_seen = self.last_seen.entry(peer.to_string()).or_default();
+        *last_seen = (*last_seen).max(now);
+    }
+
+    /// 对 `peer` 的怀疑程度：距上次收到消息经过的间隔数
+    pub fn suspicion(&self, peer: &str, now: Duration) -> Option<f64> {
+        self.last_seen.get(peer).map(|seen| {
+            now.saturating_sub(*seen).as_micros() as f64 / self.interval.as_micros() as f64
+        })
+    }
+
+    pub fn is_alive(&self, peer: &str, now: Duration) -> bool {
+        self.suspicion(peer, now)
+            .is_some_and(|suspicion| suspicion < self.threshold)
+    }
+
+    pub fn status(&self, peer: &str, now: Duration) -> PeerStatus {
+        PeerStatus {
+            id: peer.to_string(),
+            alive: self.is_alive(peer, now),
+            silent_ms: self
+                .last_seen
+                .get(peer)
+                .map(|seen| now.saturating_sub(*seen).as_millis() as u64),
+            suspicion: self.suspicion(peer, now),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::FailureDetector;
+
+    #[test]
+    fn suspect_silent_peers_test() {
+        let ms = Duration::from_millis;
+        let mut detector = FailureDetector::new(ms(100), 3.0);
+        assert!(!detector.is_alive("worker-1", ms(0)), "never heard of");
+
+        detector.heartbeat("worker-1", ms(1000));
+        // 迟到的消息不会让节点显得更久未响应
+        detector.heartbeat("worker-1", ms(900));
+        assert!(detector.is_alive("worker-1", ms(1299)));
+        assert!(!detector.is_alive("worker-1", ms(1300)));
+        assert_eq!(detector.suspicion("worker-1", ms(1150)), Some(1.5));
+
+        let status = detector.status("worker-1", ms(1300));
+        assert_eq!((status.alive, status.silent_ms), (false, Some(300)));
+        assert_eq!(detector.status("worker-2", ms(0)).suspicion, None);
+    }
+}
@@ -9,7 +9,7 @@ use anyhow::{anyhow, Result};
 
 pub trait Task: Send + 'static {
     fn get_name(&self) -> String;
-    fn task(&self) -> impl Fn() -> () + Send + 'static;
+    fn task(&self) -> impl Fn() + Send + 'static;
 }
 
 pub struct Executor {
@@ -25,7 +25,7 @@ impl Executor {
 
     pub fn spawn<F, A>(&mut self, task_name: String, task: F, args: A) -> Result<()>
     where
-        F: FnOnce(A) -> (),
+        F: FnOnce(A),
         F: Send + 'static,
         A: Send + 'static,
     {
@@ -35,7 +35,7 @@ impl Executor {
     }
 
     pub fn join(&mut self) -> Result<()> {
-        for (task_name, task_handler) in self.jobs.drain().into_iter() {
+        for (task_name, task_handler) in self.jobs.drain() {
             let join_result = task_handler.join();
             if join_result.is_err() {
                 return Err(anyhow!("Error while finishing task `{}`", task_name));
@@ -45,7 +45,7 @@ impl Executor {
     }
 
     pub fn execute(&mut self, task: impl Task) -> Result<()> {
-        let task_name = (&task.get_name()).clone();
+        let task_name = task.get_name().clone();
         let handler = thread::Builder::new()
             .name(task.get_name())
             .spawn(task.task())?;
@@ -67,7 +67,7 @@ mod tests {
             String::from("test_task")
         }
 
-        fn task(&self) -> impl Fn() -> () + Send + 'static {
+        fn task(&self) -> impl Fn() + Send + 'static {
             move || {
                 println!("Hello from inside");
             }
@@ -1,112 +1,282 @@
+//! ### Issue
+//! The message exchanged by Master and Worker. It is encoded by the mail
+//! box's `Codec`, after the codec's version and format bytes; with the
+//! binary codec the fields are laid out as:
+//!
+//!     | type: u32 | kind: u32 | round: u64 | ballot node length: u64 |
+//!     | ballot node | slot: u64 | sender length: u64 | sender id |
+//!     | content length: u64 | content |
+//!
+//! A ballot is a round number paired with the id of the master using it, so
+//! two masters never use the same ballot.
+//!
+//! The kind tells what a proposed entry holds next to its content, so the
+//! content a client submits can be any bytes. The log keeps both, see
+//! `Entry`.
+//!
 #![allow(unused)]
 
+use anyhow::Result;
 use bytes::Bytes;
+use serde::{Deserialize, Serialize};
 
-#[derive(Clone, Eq, PartialEq, Hash)]
+use crate::address_book::NodeId;
+
+/// 轮次：先比较轮数，轮数相同时比较议长的编号，不同议长的轮次因此不会相同
+#[derive(Clone, Debug, Default, Eq, PartialEq, Ord, PartialOrd, Hash, Serialize, Deserialize)]
+pub struct Ballot {
+    pub round: u64,
+    pub node: NodeId,
+}
+
+impl Ballot {
+    pub fn new(round: u64, node: NodeId) -> Self {
+        Self { round, node }
+    }
+}
+
+impl std::fmt::Display for Ballot {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}.{}", self.round, self.node)
+    }
+}
+
+/// 日志条目的种类
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq, Hash, Serialize, Deserialize)]
+pub enum EntryKind {
+    /// 客户端提交的日志
+    #[default]
+    Client,
+    /// 成员变更，见 `membership::Change`
+    Membership,
+    /// 客户端会话中的命令，见 `session::Command`
+    Session,
+    /// 空操作：跳过的编号，或第一阶段之后补齐的空缺
+    Noop,
+    /// 锁命令，见 `lock::LockCommand`
+    Lock,
+}
+
+/// 写入日志的条目
+#[derive(Clone, Debug, Default, Eq, PartialEq, Hash, Serialize, Deserialize)]
+pub struct Entry {
+    pub kind: EntryKind,
+    pub content: Bytes,
+}
+
+impl Entry {
+    pub fn new(kind: EntryKind, content: Bytes) -> Self {
+        Self { kind, content }
+    }
+
+    /// 客户端提交的日志
+    pub fn client(content: Bytes) -> Self {
+        Self::new(EntryKind::Client, content)
+    }
+
+    pub fn noop() -> Self {
+        Self::new(EntryKind::Noop, Bytes::new())
+    }
+
+    /// 写入日志的内容
+    pub fn encode(&self) -> Result<Bytes> {
+        Ok(bincode::serialize(self)?.into())
+    }
+
+    pub fn decode(raw: &[u8]) -> Result<Self> {
+        Ok(bincode::deserialize(raw)?)
+    }
+}
+
+/// 字段的顺序即为编码后的顺序
+#[derive(Clone, Debug, Eq, PartialEq, Hash, Serialize, Deserialize)]
 pub struct Issue {
-    content: String,
-    id: u64,
     issue_type: IssueType,
+    kind: EntryKind,
+    ballot: Ballot,
+    id: u64,
+    sender: NodeId,
+    content: Bytes,
 }
 
 impl Issue {
-    pub fn new(content: String, id: u64, issue_type: IssueType) -> Self {
+    pub fn new(content: Bytes, id: u64, issue_type: IssueType) -> Self {
         Issue {
             content,
             id,
             issue_type,
+            kind: EntryKind::default(),
+            ballot: Ballot::default(),
+            sender: NodeId::new(),
         }
     }
 
-    pub fn content(&self) -> String {
-        self.content.clone()
+    /// 设置议题所属的选票轮次
+    pub fn with_ballot(mut self, ballot: Ballot) -> Self {
+        self.ballot = ballot;
+        self
     }
 
-    pub fn id(&self) -> u64 {
-        self.id.clone()
+    /// 设置提议的条目的种类，默认为客户端提交的日志
+    pub fn with_kind(mut self, kind: EntryKind) -> Self {
+        self.kind = kind;
+        self
     }
 
-    pub fn issue_type(&self) -> IssueType {
-        self.issue_type.clone()
+    /// 设置发出议题的节点
+    pub fn with_sender(mut self, sender: NodeId) -> Self {
+        self.sender = sender;
+        self
     }
-}
-
-impl TryFrom<Bytes> for Issue {
-    type Error = anyhow::Error;
 
-    fn try_from(value: Bytes) -> Result<Self, Self::Error> {
-        let raw_issue_content = String::from_utf8(value.to_vec())
-            .map_err(|_| anyhow::anyhow!("Failed to convert bytes to string"))?;
+    /// 议题的内容，对共识过程是不透明的字节
+    pub fn content(&self) -> Bytes {
+        self.content.clone()
+    }
 
-        let parts: Vec<&str> = raw_issue_content.split("|").collect();
+    /// 议题在日志中的位置(slot)
+    pub fn id(&self) -> u64 {
+        self.id
+    }
 
-        if parts.len() != 3 {
-            return Err(anyhow::anyhow!(
-                "Invalid issue format, expected 3 parts, got {}",
-                parts.len()
-            ));
-        } else {
-            let parts: Vec<&str> = raw_issue_content.split("|").collect();
+    pub fn kind(&self) -> EntryKind {
+        self.kind
+    }
 
-            let it = IssueType::try_from(parts[0])?;
-            let id = u64::from_str_radix(parts[1], 10)?;
-            let ct = parts[2];
+    /// 提议或投票的条目
+    pub fn entry(&self) -> Entry {
+        Entry::new(self.kind, self.content.clone())
+    }
 
-            Ok(Issue {
-                content: ct.to_string(),
-                id: id,
-                issue_type: it,
-            })
-        }
+    pub fn issue_type(&self) -> IssueType {
+        self.issue_type.clone()
     }
-}
 
-impl Into<Bytes> for Issue {
-    fn into(self) -> Bytes {
-        let coded_issue_type = self.clone().issue_type.into();
-        let coded_issue_id = self.clone().id.to_string();
-        let coded_issue_content = self.clone().content;
+    pub fn ballot(&self) -> Ballot {
+        self.ballot.clone()
+    }
 
-        vec![coded_issue_type, coded_issue_id, coded_issue_content]
-            .join("|")
-            .into_bytes()
-            .into()
+    pub fn sender(&self) -> NodeId {
+        self.sender.clone()
     }
 }
 
-#[derive(Clone, Eq, PartialEq, Hash)]
+#[derive(Clone, Debug, Eq, PartialEq, Hash, Serialize, Deserialize)]
 pub enum IssueType {
     Proposal,
     Vote,
     Resolution,
+    /// 拒绝轮次过低的提案，`ballot` 为议员承诺过的最高轮次
+    Reject,
+    /// 议长确认自己仍是领导者，`id` 为确认的轮次编号
+    Confirm,
+    /// 回复确认，`ballot` 为议员承诺过的最高轮次，与确认的轮次相同时即为同意
+    Confirmed,
+    /// 第一阶段的准备，`id` 起的编号都不再接受更低的轮次，见 `acceptor::Prepare`
+    Prepare,
+    /// 回复准备，带有议员在这些编号中接受过的提案，见 `acceptor::Promise`
+    Promise,
 }
 
-impl Into<String> for IssueType {
-    fn into(self) -> String {
-        match self {
-            IssueType::Proposal => "p".to_string(),
-            IssueType::Vote => "v".to_string(),
-            IssueType::Resolution => "r".to_string(),
-        }
+#[cfg(test)]
+mod tests {
+    use bytes::Bytes;
+    use proptest::prelude::*;
+
+    use super::{Ballot, Entry, EntryKind, Issue, IssueType};
+    use crate::codec::{Codec, WIRE_VERSION};
+
+    fn issue_type() -> impl Strategy<Value = IssueType> {
+        prop_oneof![
+            Just(IssueType::Proposal),
+            Just(IssueType::Vote),
+            Just(IssueType::Resolution),
+            Just(IssueType::Reject),
+            Just(IssueType::Confirm),
+            Just(IssueType::Confirmed),
+            Just(IssueType::Prepare),
+            Just(IssueType::Promise),
+        ]
+    }
+
+    fn entry_kind() -> impl Strategy<Value = EntryKind> {
+        prop_oneof![
+            Just(EntryKind::Client),
+            Just(EntryKind::Membership),
+            Just(EntryKind::Session),
+            Just(EntryKind::Noop),
+            Just(EntryKind::Lock),
+        ]
     }
-}
 
-impl TryFrom<&str> for IssueType {
-    type Error = anyhow::Error;
+    proptest! {
+        #[test]
+        fn round_trip_test(
+            content in any::<Vec<u8>>(),
+            id in any::<u64>(),
+            round in any::<u64>(),
+            sender in "[a-z0-9-]{0,32}",
+            issue_type in issue_type(),
+            kind in entry_kind(),
+        ) {
+            let issue = Issue::new(content.into(), id, issue_type)
+                .with_kind(kind)
+                .with_ballot(Ballot::new(round, sender.clone()))
+                .with_sender(sender);
 
-    fn try_from(value: &str) -> Result<Self, Self::Error> {
-        match value {
-            "r" => Ok(IssueType::Resolution),
-            "v" => Ok(IssueType::Vote),
-            "p" => Ok(IssueType::Proposal),
-            _ => Err(anyhow::anyhow!("not a valid issue_type")),
+            for codec in [Codec::Json, Codec::Binary] {
+                let coded = codec.encode(&issue).unwrap();
+                prop_assert_eq!(codec.decode::<Issue>(coded).unwrap(), issue.clone());
+            }
+            let entry = issue.entry();
+            prop_assert_eq!(Entry::decode(&entry.encode().unwrap()).unwrap(), entry);
+        }
+
+        #[test]
+        fn arbitrary_bytes_never_panic_test(raw in proptest::collection::vec(any::<u8>(), 0..128)) {
+            let _ = Codec::Binary.decode::<Issue>(Bytes::from(raw));
         }
     }
-}
-impl TryFrom<String> for IssueType {
-    type Error = anyhow::Error;
 
-    fn try_from(value: String) -> Result<Self, Self::Error> {
-        IssueType::try_from(value.as_str())
+    #[test]
+    fn pipe_in_content_test() {
+        let issue = Issue::new(Bytes::from("a|b||c"), 7, IssueType::Proposal);
+        let coded = Codec::Binary.encode(&issue).unwrap();
+        assert_eq!(Codec::Binary.decode::<Issue>(coded).unwrap(), issue);
+    }
+
+    #[test]
+    fn reject_malformed_test() {
+        let issue = Issue::new(Bytes::from("hello"), 1, IssueType::Vote).with_sender("w".into());
+        let coded = Codec::Binary.encode(&issue).unwrap();
+
+        // unknown version
+        let mut future = coded.to_vec();
+        future[0] = WIRE_VERSION + 1;
+        let err = Codec::Binary
+            .decode::<Issue>(Bytes::from(future))
+            .unwrap_err();
+        assert!(err.to_string().contains("version"));
+
+        // unknown type
+        let mut bad_type = coded.to_vec();
+        bad_type[2] = 0xff;
+        assert!(Codec::Binary
+            .decode::<Issue>(Bytes::from(bad_type))
+            .is_err());
+
+        // truncated
+        assert!(Codec::Binary
+            .decode::<Issue>(coded.slice(..coded.len() - 1))
+            .is_err());
+    }
+
+    #[test]
+    fn ballots_of_masters_differ_test() {
+        let ballot = |round, node: &str| Ballot::new(round, node.to_string());
+        assert!(ballot(1, "master") < ballot(1, "master-2"));
+        assert!(ballot(1, "master-2") < ballot(2, "master"));
+        assert!(Ballot::default() < ballot(0, "master"));
+        assert_eq!(ballot(3, "master").to_string(), "3.master");
     }
 }
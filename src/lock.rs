@@ -0,0 +1,246 @@
+//! ### Lock
+//! Distributed locks, a state machine fed from the log.
+//!
+//! Acquiring, renewing and releasing a lock are written into the log as
+//! entries of kind `EntryKind::Lock`, and every master applies them in slot
+//! order, so the lock table survives a restart or a change of master the
+//! same way the log does. A lock is granted with a *fencing token*, the slot
+//! of the entry which acquired it: tokens only grow, so a storage service
+//! can reject a write carrying a token lower than one it has already seen.
+//!
+//! A lock is held for a TTL and expires unless renewed. Time is read from
+//! the log, not from the applying master: each command carries the clock of
+//! the master proposing it, and the table only moves its time forward, so
+//! every replica expires the same locks at the same slot. Clock skew between
+//! masters only makes a lock expire somewhat earlier or later.
+//!
+#![allow(unused)]
+
+use std::{collections::BTreeMap, fmt, time::Duration};
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+
+use crate::issue::{Entry, EntryKind};
+
+/// 未指定时锁的默认有效期
+pub const DEFAULT_TTL: Duration = Duration::from_secs(10);
+/// 锁的最长有效期
+pub const MAX_TTL: Duration = Duration::from_secs(3600);
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub enum LockOp {
+    /// 获取未被持有或已过期的锁，令牌为该命令的编号
+    Acquire { owner: String, ttl_ms: u64 },
+    /// 延长仍持有的锁的有效期，从命令的时间起算
+    Renew {
+        owner: String,
+        token: u64,
+        ttl_ms: u64,
+    },
+    /// 释放仍持有的锁
+    Release { owner: String, token: u64 },
+}
+
+/// 写入日志的锁命令
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct LockCommand {
+    pub name: String,
+    pub op: LockOp,
+    /// 提议该命令的议长的时间，UNIX纪元起的毫秒数
+    pub at: u64,
+}
+
+impl LockCommand {
+    pub fn new(name: String, op: LockOp, at: u64) -> Self {
+        Self { name, op, at }
+    }
+
+    /// 写入日志的条目
+    pub fn entry(&self) -> Result<Entry> {
+        let raw = bincode::serialize(self)?;
+        Ok(Entry::new(EntryKind::Lock, raw.into()))
+    }
+
+    /// 条目不是锁命令时返回 `None`
+    pub fn decode(entry: &Entry) -> Option<LockCommand> {
+        (entry.kind == EntryKind::Lock)
+            .then(|| bincode::deserialize(&entry.content).ok())
+            .flatten()
+    }
+}
+
+/// 形成决议、但未能生效的锁命令
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum LockError {
+    /// 锁仍被其他令牌持有
+    Held {
+        name: String,
+        owner: String,
+        token: u64,
+    },
+    /// 令牌已不再持有锁：已释放、已过期、或不属于该客户端
+    Lost { name: String, token: u64 },
+}
+
+impl fmt::Display for LockError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LockError::Held { name, owner, token } => write!(
+                f,
+                "lock `{}` is held by `{}` with token {}",
+                name, owner, token
+            ),
+            LockError::Lost { name, token } => {
+                write!(f, "token {} no longer holds lock `{}`", token, name)
+            }
+        }
+    }
+}
+
+impl std::error::Error for LockError {}
+
+/// 锁的持有者
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Holder {
+    pub owner: String,
+    pub token: u64,
+    /// 到期的时间，UNIX纪元起的毫秒数
+    pub expires_at: u64,
+}
+
+/// 按编号顺序应用锁命令得到的锁表
+#[derive(Default)]
+pub struct Locks {
+    /// 锁名 -> 持有者，过期的持有者在下一次获取时被替换
+    held: BTreeMap<String, Holder>,
+    /// 已应用的命令中最晚的时间
+    now: u64,
+}
+
+impl Locks {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 应用在 `slot` 形成决议的锁命令，命令未能生效时返回原因
+    pub fn apply(&mut self, slot: u64, command: &LockCommand) -> Result<(), LockError> {
+        self.now = self.now.max(command.at);
+        let now = self.now;
+        let name = &command.name;
+        let current = self
+            .held
+            .get_mut(name)
+            .filter(|holder| holder.expires_at > now);
+
+        match (&command.op, current) {
+            (LockOp::Acquire { .. }, Some(holder)) => Err(LockError::Held {
+                name: name.clone(),
+                owner: holder.owner.clone(),
+                token: holder.token,
+            }),
+            (LockOp::Acquire { owner, ttl_ms }, None) => {
+                let holder = Holder {
+                    owner: owner.clone(),
+                    token: slot,
+                    expires_at: now.saturating_add(*ttl_ms),
+                };
+                self.held.insert(name.clone(), holder);
+                Ok(())
+            }
+            (
+                LockOp::Renew {
+                    owner,
+                    token,
+                    ttl_ms,
+                },
+                Some(holder),
+            ) if holder.owner == *owner && holder.token == *token => {
+                holder.expires_at = now.saturating_add(*ttl_ms);
+                Ok(())
+            }
+            (LockOp::Release { owner, token }, Some(holder))
+                if holder.owner == *owner && holder.token == *token =>
+            {
+                self.held.remove(name);
+                Ok(())
+            }
+            (LockOp::Renew { token, .. } | LockOp::Release { token, .. }, _) => {
+                Err(LockError::Lost {
+                    name: name.clone(),
+                    token: *token,
+                })
+            }
+        }
+    }
+
+    /// 锁当前的持有者，按已应用的命令中最晚的时间判断是否过期
+    pub fn holder(&self, name: &str) -> Option<&Holder> {
+        self.held
+            .get(name)
+            .filter(|holder| holder.expires_at > self.now)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{LockCommand, LockError, LockOp, Locks};
+    use crate::issue::Entry;
+
+    #[test]
+    fn lock_table_test() {
+        let command = |op, at| LockCommand::new("db".to_string(), op, at);
+        let acquire = |owner: &str| LockOp::Acquire {
+            owner: owner.to_string(),
+            ttl_ms: 100,
+        };
+        let entry = command(acquire("alice"), 0).entry().unwrap();
+        assert_eq!(
+            LockCommand::decode(&entry),
+            Some(command(acquire("alice"), 0))
+        );
+        // 内容相同的客户端日志不是锁命令
+        assert_eq!(LockCommand::decode(&Entry::client(entry.content)), None);
+
+        let mut locks = Locks::new();
+        locks.apply(3, &command(acquire("alice"), 1000)).unwrap();
+        assert_eq!(locks.holder("db").unwrap().token, 3);
+        assert!(matches!(
+            locks.apply(4, &command(acquire("bob"), 1050)),
+            Err(LockError::Held { token: 3, .. })
+        ));
+
+        // 续期从命令的时间起算，令牌或客户端不符时不生效
+        let renew = |owner: &str, token| LockOp::Renew {
+            owner: owner.to_string(),
+            token,
+            ttl_ms: 100,
+        };
+        locks.apply(5, &command(renew("alice", 3), 1080)).unwrap();
+        assert_eq!(locks.holder("db").unwrap().expires_at, 1180);
+        assert!(locks.apply(6, &command(renew("bob", 3), 1090)).is_err());
+        assert!(locks.apply(7, &command(renew("alice", 4), 1090)).is_err());
+
+        // 时间不会倒退：较早的时间仍按最晚的时间判断
+        assert!(locks.apply(8, &command(acquire("bob"), 1000)).is_err());
+
+        // 过期后可以被其他客户端获取，原来的令牌失效
+        locks.apply(9, &command(acquire("bob"), 1180)).unwrap();
+        assert_eq!(locks.holder("db").unwrap().token, 9);
+        let release = |owner: &str, token| LockOp::Release {
+            owner: owner.to_string(),
+            token,
+        };
+        assert_eq!(
+            locks.apply(10, &command(release("alice", 3), 1190)),
+            Err(LockError::Lost {
+                name: "db".to_string(),
+                token: 3
+            })
+        );
+        locks.apply(11, &command(release("bob", 9), 1200)).unwrap();
+        assert!(locks.holder("db").is_none());
+        locks.apply(12, &command(acquire("alice"), 1200)).unwrap();
+        assert_eq!(locks.holder("db").unwrap().token, 12);
+    }
+}
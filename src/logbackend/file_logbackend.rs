@@ -1,43 +1,112 @@
 //! ### File Based Log Backend
 //! Use File to Store Log
 //!
+//! every write is appended to the file as a record:
+//!
+//!     | id: u64 | length: u32 | data |
+//!
+//! the file is read back when the backend is opened, a later record of an
+//! id replaces the earlier ones. A record cut short by a crash while being
+//! written is dropped and the file truncated before it.
+//!
 #![allow(unused)]
 use super::{LogBackend, Queryable, Writable};
 use anyhow::{anyhow, Result};
-use bytes::Bytes;
-use std::{fs::File, io::Write};
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+use std::{
+    cell::RefCell,
+    collections::BTreeMap,
+    fs::File,
+    io::{Read, Write},
+};
+
+/// 每条记录头部的长度：编号与内容的长度
+const HEADER_LEN: usize = 8 + 4;
 
 pub struct FileLogBackend {
-    file_name: String,
+    file: RefCell<File>,
+    /// 文件中每个编号最后写入的内容
+    table: RefCell<BTreeMap<u64, Bytes>>,
+    /// 文件中完整记录的总长度
+    len: RefCell<u64>,
 }
 
 impl FileLogBackend {
-    pub fn new(file_name: &str) -> Self {
-        Self {
-            file_name: file_name.to_string(),
-        }
-    }
-
-    fn get_file_handler(&self) -> Result<File> {
-        File::options()
+    /// 打开日志文件，不存在时创建，并读入其中的全部记录
+    pub fn open(file_name: &str) -> Result<Self> {
+        let mut file = File::options()
             .create(true)
+            .read(true)
             .append(true)
-            .open(&self.file_name)
-            .map_err(|e| anyhow!(e))
+            .open(file_name)
+            .map_err(|e| anyhow!("can't open log file `{}`: {}", file_name, e))?;
+        let mut raw = Vec::new();
+        file.read_to_end(&mut raw)?;
+
+        let mut table = BTreeMap::new();
+        let mut rest = Bytes::from(raw);
+        let mut len = 0;
+        while rest.len() >= HEADER_LEN {
+            let mut header = &rest[..HEADER_LEN];
+            let id = header.get_u64();
+            let data_len = header.get_u32() as usize;
+            if rest.len() < HEADER_LEN + data_len {
+                break;
+            }
+            rest.advance(HEADER_LEN);
+            table.insert(id, rest.split_to(data_len));
+            len += (HEADER_LEN + data_len) as u64;
+        }
+        // 写了一半的记录
+        if !rest.is_empty() {
+            file.set_len(len)?;
+        }
+
+        Ok(Self {
+            file: RefCell::new(file),
+            table: RefCell::new(table),
+            len: RefCell::new(len),
+        })
     }
 }
 
 impl Writable for FileLogBackend {
-    fn write(&self, _id: u64, data: Bytes) -> Result<()> {
-        self.get_file_handler()?
-            .write_all(&data)
-            .map_err(|e| anyhow!(e))
+    fn write(&self, id: u64, data: Bytes) -> Result<()> {
+        let data_len = u32::try_from(data.len())
+            .map_err(|_| anyhow!("log entry {} is longer than 4GiB", id))?;
+        let mut record = BytesMut::with_capacity(HEADER_LEN + data.len());
+        record.put_u64(id);
+        record.put_u32(data_len);
+        record.put_slice(&data);
+
+        let mut file = self.file.try_borrow_mut()?;
+        let mut len = self.len.try_borrow_mut()?;
+        if let Err(e) = file.write_all(&record) {
+            // 去掉写了一半的记录，之后的记录仍从完整记录的末尾开始
+            let _ = file.set_len(*len);
+            return Err(anyhow!(e));
+        }
+        *len += record.len() as u64;
+        self.table.try_borrow_mut()?.insert(id, data);
+        Ok(())
+    }
+
+    fn flush(&self) -> Result<()> {
+        self.file.try_borrow()?.sync_data().map_err(|e| anyhow!(e))
     }
 }
 
 impl Queryable for FileLogBackend {
     fn query(&self, id: u64) -> Result<Bytes> {
-        todo!()
+        self.get(id)?.ok_or_else(|| anyhow!("Item Not Found."))
+    }
+
+    fn get(&self, id: u64) -> Result<Option<Bytes>> {
+        Ok(self.table.try_borrow()?.get(&id).cloned())
+    }
+
+    fn last_id(&self) -> Result<Option<u64>> {
+        Ok(self.table.try_borrow()?.keys().next_back().copied())
     }
 }
 
@@ -46,9 +115,13 @@ impl LogBackend for FileLogBackend {}
 #[cfg(test)]
 mod tests {
     use crate::logbackend::FileLogBackend;
-    use crate::logbackend::Writable;
-    use anyhow::Result;
-    use std::{fmt::Display, fs, path::Path};
+    use crate::logbackend::{Queryable, Writable};
+    use std::{
+        fmt::Display,
+        fs::{self, OpenOptions},
+        io::Write,
+        path::PathBuf,
+    };
 
     impl Display for FileLogBackend {
         fn fmt(&self, _f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
@@ -56,34 +129,50 @@ mod tests {
         }
     }
 
-    const TEST_FILE_NAME: &str = "test.log";
-
-    fn prepare_test() -> Result<()> {
-        if Path::new(TEST_FILE_NAME).exists() {
-            let _ = fs::remove_file(TEST_FILE_NAME).unwrap();
-        }
-
-        Ok(())
+    fn test_file(name: &str) -> PathBuf {
+        let path =
+            std::env::temp_dir().join(format!("somepox-{}-{}.log", name, std::process::id()));
+        let _ = fs::remove_file(&path);
+        path
     }
 
     #[test]
     fn file_logbackend_write_test() {
-        prepare_test().unwrap();
-
-        let backend = FileLogBackend::new(TEST_FILE_NAME);
-
-        backend.write(1, "test-1\n".into()).unwrap();
-        backend.write(2, "test-2\n".into()).unwrap();
-        backend.write(3, "test-3\n".into()).unwrap();
-
-        let raw_content = fs::read(TEST_FILE_NAME).unwrap();
-        let binding = String::from_utf8(raw_content).unwrap();
-        let log_content: Vec<&str> = binding.split("\n").collect();
-
-        assert_eq!(log_content[0], "test-1");
-        assert_eq!(log_content[1], "test-2");
-        assert_eq!(log_content[2], "test-3");
-
-        assert!(true, "Error in write file log.")
+        let path = test_file("write");
+        let file_name = path.to_str().unwrap();
+
+        let backend = FileLogBackend::open(file_name).unwrap();
+        assert_eq!(backend.last_id().unwrap(), None);
+        backend.write(1, "test-1".into()).unwrap();
+        backend.write(3, "test-3".into()).unwrap();
+        backend.write(1, "test-1.1".into()).unwrap();
+        backend.flush().unwrap();
+
+        assert_eq!(backend.query(1).unwrap(), "test-1.1");
+        assert_eq!(backend.get(2).unwrap(), None);
+        assert!(backend.query(2).is_err());
+        assert_eq!(backend.last_id().unwrap(), Some(3));
+        drop(backend);
+
+        // 重新打开时读回每个编号最后写入的内容
+        let backend = FileLogBackend::open(file_name).unwrap();
+        assert_eq!(backend.query(1).unwrap(), "test-1.1");
+        assert_eq!(backend.query(3).unwrap(), "test-3");
+        assert_eq!(backend.last_id().unwrap(), Some(3));
+        drop(backend);
+
+        // 写了一半的记录被截去，之后写入的记录仍能读回
+        let mut file = OpenOptions::new().append(true).open(&path).unwrap();
+        file.write_all(&[0, 0, 0, 0, 0, 0, 0, 4, 0, 0]).unwrap();
+        drop(file);
+        let backend = FileLogBackend::open(file_name).unwrap();
+        assert_eq!(backend.last_id().unwrap(), Some(3));
+        backend.write(5, "test-5".into()).unwrap();
+        drop(backend);
+        let backend = FileLogBackend::open(file_name).unwrap();
+        assert_eq!(backend.query(5).unwrap(), "test-5");
+        assert_eq!(backend.query(3).unwrap(), "test-3");
+
+        let _ = fs::remove_file(&path);
     }
 }
@@ -46,7 +46,10 @@ impl Writable for HeapLogBackend {
         let mut table_ref = self.table.try_borrow_mut()?;
 
         match table_ref.get(&id) {
-            Some(version_his_ref) => Ok(version_his_ref.borrow_mut().push_back(data.clone())),
+            Some(version_his_ref) => {
+                version_his_ref.borrow_mut().push_back(data.clone());
+                Ok(())
+            }
             None => {
                 table_ref.insert(id, RefCell::new(LinkedList::from([data.clone()])));
                 Ok(())
@@ -67,6 +70,17 @@ impl Queryable for HeapLogBackend {
             None => Err(anyhow!("Item Not Found.")),
         }
     }
+
+    fn get(&self, id: u64) -> Result<Option<Bytes>> {
+        let table_ref = self.table.try_borrow()?;
+        Ok(table_ref
+            .get(&id)
+            .and_then(|version_his_ref| version_his_ref.borrow().back().cloned()))
+    }
+
+    fn last_id(&self) -> Result<Option<u64>> {
+        Ok(self.table.try_borrow()?.keys().next_back().copied())
+    }
 }
 
 impl LogBackend for HeapLogBackend {}
@@ -88,7 +102,7 @@ mod tests {
 
                 let content: String = list
                     .iter()
-                    .flat_map(|item| String::from_utf8((&item).to_vec()))
+                    .flat_map(|item| String::from_utf8(item.to_vec()))
                     .map(|str| str + ",")
                     .collect();
 
@@ -119,25 +133,26 @@ mod tests {
     }
 
     #[test]
+    #[allow(clippy::assertions_on_constants)]
     fn heap_logbackend_write_test() {
         let test_backend = HeapLogBackend::new();
 
         let r1 = test_backend.write(1, "test1".into());
         assert!(r1.is_ok(), "Error in write log into heap backend");
 
-        print!("{}", test_backend.to_string());
+        print!("{}", test_backend);
         println!("\n-------------");
 
         let r2 = test_backend.write(2, "test2".into());
         assert!(r2.is_ok(), "Error in write log into heap backend");
 
-        print!("{}", test_backend.to_string());
+        print!("{}", test_backend);
         println!("\n-------------");
 
         let r3 = test_backend.write(1, "test1.1".into());
         assert!(r3.is_ok(), "Error in write log into heap backend");
 
-        print!("{}", test_backend.to_string());
+        print!("{}", test_backend);
         println!("\n-------------");
 
         assert!(true, "Error in write log into heap backend");
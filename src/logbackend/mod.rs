@@ -11,10 +11,49 @@ use bytes::Bytes;
 
 pub trait Writable {
     fn write(&self, id: u64, data: Bytes) -> Result<()>;
+
+    /// make sure everything written so far is persisted
+    fn flush(&self) -> Result<()> {
+        Ok(())
+    }
 }
 
 pub trait Queryable {
     fn query(&self, id: u64) -> Result<Bytes>;
+
+    /// the data written at `id`, `None` when nothing is written there;
+    /// unlike `query`, an error means the backend could not be read
+    fn get(&self, id: u64) -> Result<Option<Bytes>>;
+
+    /// the largest id written so far, `None` when nothing is written
+    fn last_id(&self) -> Result<Option<u64>>;
 }
 
 pub trait LogBackend: Queryable + Writable {}
+
+/// 共享同一份日志，例如重启前后的同一个节点
+impl<T: Writable + ?Sized> Writable for std::rc::Rc<T> {
+    fn write(&self, id: u64, data: Bytes) -> Result<()> {
+        (**self).write(id, data)
+    }
+
+    fn flush(&self) -> Result<()> {
+        (**self).flush()
+    }
+}
+
+impl<T: Queryable + ?Sized> Queryable for std::rc::Rc<T> {
+    fn query(&self, id: u64) -> Result<Bytes> {
+        (**self).query(id)
+    }
+
+    fn get(&self, id: u64) -> Result<Option<Bytes>> {
+        (**self).get(id)
+    }
+
+    fn last_id(&self) -> Result<Option<u64>> {
+        (**self).last_id()
+    }
+}
+
+impl<T: LogBackend + ?Sized> LogBackend for std::rc::Rc<T> {}
@@ -1,86 +1,482 @@
 #![allow(unused)]
 
-use std::{cell::RefCell, collections::VecDeque};
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex, MutexGuard, RwLock, RwLockReadGuard},
+    thread,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
 
-use anyhow::{anyhow, Result};
+use anyhow::{anyhow, Error, Result};
 use bytes::Bytes;
+use serde::{de::DeserializeOwned, Serialize};
 
-use crate::connection::Connection;
+use crate::{
+    address_book::{AddressBook, NodeId, Recipient},
+    auth::Authenticator,
+    clock::{Clock, SystemClock},
+    codec::Codec,
+    connection::Connection,
+    delivery::{Dedup, DeliveryStats, Frame, Outbox, RetryPolicy, SessionFile},
+    detector::{FailureDetector, PeerStatus},
+    queue::{BoundedQueue, Overflow, QueueStats},
+};
 
+/// 一次 `pump` 最多处理的消息数，以免持续到达的消息让调用者无法处理收件箱
+const MAX_PUMP_BATCH: usize = 256;
+
+/// 收件箱已满时 `recv_matching` 两次检查之间的间隔
+const FULL_INBOX_BACKOFF: Duration = Duration::from_millis(1);
+
+/// 收件箱与发件箱默认的容量
+pub const DEFAULT_CAPACITY: usize = 1024;
+
+/// 邮箱可以在收发线程间共享 (`Send + Sync`)，收件箱与发件箱的容量有限，
+/// 已满时按 `Overflow` 处理新邮件。
 pub struct MailBox<Addr, Content>
 where
-    Addr: Clone,
-    Content: Clone + TryFrom<Bytes> + Into<Bytes>,
+    Addr: Clone + PartialEq,
+    Content: Clone + Serialize + DeserializeOwned,
 {
-    send_list: RefCell<VecDeque<Mail<Addr, Content>>>,
-    recv_list: RefCell<VecDeque<Mail<Addr, Content>>>,
+    id: NodeId,
+    send_list: BoundedQueue<Mail<Content>>,
+    recv_list: BoundedQueue<Mail<Content>>,
+    address_book: RwLock<AddressBook<Addr>>,
+    auth: Authenticator,
+    codec: Codec,
     conn: Box<dyn Connection<Addr = Addr>>,
+    clock: Arc<dyn Clock>,
+    retry: RetryPolicy,
+    /// 本次启动的会话编号，与邮件编号一起唯一标识一封邮件
+    session: u64,
+    /// 保存会话编号的文件，见 `with_session_file`
+    session_file: Option<Mutex<SessionFile>>,
+    /// 发给每个收件人的上一封邮件的编号
+    last_mail_ids: Mutex<HashMap<NodeId, u64>>,
+    outbox: Mutex<Outbox>,
+    dedup: Mutex<Dedup>,
+    stats: Mutex<DeliveryStats>,
+    detector: Mutex<FailureDetector>,
+    /// 上次发送心跳的时间
+    last_ping: Mutex<Option<Duration>>,
+}
+
+/// 一次发送的结果：成功发出的邮件份数，以及每个发送失败的收件人
+#[derive(Default)]
+pub struct DeliveryReport {
+    pub sent: usize,
+    pub failures: Vec<(NodeId, Error)>,
 }
 
 impl<Addr, Content> MailBox<Addr, Content>
 where
-    Addr: Clone,
-    Content: Clone + TryFrom<Bytes> + Into<Bytes>,
+    Addr: Clone + PartialEq,
+    Content: Clone + Serialize + DeserializeOwned,
 {
-    pub fn new(conn: Box<dyn Connection<Addr = Addr>>) -> Self {
+    pub fn new(
+        id: NodeId,
+        conn: Box<dyn Connection<Addr = Addr>>,
+        address_book: AddressBook<Addr>,
+        auth: Authenticator,
+        codec: Codec,
+    ) -> Self {
         MailBox {
-            send_list: RefCell::new(VecDeque::new()),
-            recv_list: RefCell::new(VecDeque::new()),
-            conn: conn,
+            id,
+            send_list: BoundedQueue::new(DEFAULT_CAPACITY, Overflow::default()),
+            recv_list: BoundedQueue::new(DEFAULT_CAPACITY, Overflow::default()),
+            address_book: RwLock::new(address_book),
+            auth,
+            codec,
+            conn,
+            clock: Arc::new(SystemClock::new()),
+            retry: RetryPolicy::default(),
+            session: wall_clock(),
+            session_file: None,
+            last_mail_ids: Mutex::new(HashMap::new()),
+            outbox: Mutex::new(Outbox::default()),
+            dedup: Mutex::new(Dedup::default()),
+            stats: Mutex::new(DeliveryStats::default()),
+            detector: Mutex::new(FailureDetector::default()),
+            last_ping: Mutex::new(None),
         }
     }
 
+    /// 设置收件箱与发件箱的容量，以及已满时的处理方式
+    ///
+    /// 收件箱已满并拒绝新邮件时不会确认该邮件，发件人稍后会重传。
+    pub fn with_capacity(mut self, inbox: usize, outbox: usize, overflow: Overflow) -> Self {
+        self.recv_list = BoundedQueue::new(inbox, overflow);
+        self.send_list = BoundedQueue::new(outbox, overflow);
+        self
+    }
+
+    /// 使用指定的时钟计算重传时间
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// 会话编号保存在 `file` 中：本次启动换用比之前都大的会话编号，不受时钟
+    /// 回拨影响；并恢复每个发件人最新的会话，重启后仍拒绝旧会话的邮件
+    ///
+    /// 未设置时会话编号取自时钟
+    pub fn with_session_file(mut self, mut file: SessionFile) -> Result<Self> {
+        self.session = file.next_session(wall_clock())?;
+        self.dedup = Mutex::new(Dedup::restore(file.senders()));
+        self.session_file = Some(Mutex::new(file));
+        Ok(self)
+    }
+
+    pub fn with_retry_policy(mut self, retry: RetryPolicy) -> Self {
+        self.retry = retry;
+        self
+    }
+
+    /// 设置心跳的间隔与判定节点失效的怀疑程度
+    pub fn with_failure_detector(mut self, detector: FailureDetector) -> Self {
+        self.detector = Mutex::new(detector);
+        self
+    }
+
+    /// 本节点的编号
+    pub fn id(&self) -> NodeId {
+        self.id.clone()
+    }
+
+    /// 邮箱时钟的读数
+    pub fn now(&self) -> Duration {
+        self.clock.now()
+    }
+
+    /// 地址簿当前的副本
+    pub fn address_book(&self) -> AddressBook<Addr> {
+        self.address_book
+            .read()
+            .map(|book| book.clone())
+            .unwrap_or_default()
+    }
+
+    /// 修改地址簿，之后收发的邮件按修改后的地址簿解析
+    pub fn update_address_book<F>(&self, update: F) -> Result<()>
+    where
+        F: FnOnce(&mut AddressBook<Addr>),
+    {
+        let mut book = self
+            .address_book
+            .write()
+            .map_err(|_| anyhow!("MailBox poisoned"))?;
+        update(&mut book);
+        Ok(())
+    }
+
+    /// 自启动以来的投递统计
+    pub fn stats(&self) -> DeliveryStats {
+        lock(&self.stats)
+            .map(|stats| stats.clone())
+            .unwrap_or_default()
+    }
+
+    /// 最近是否收到过 `peer` 的消息，见 `FailureDetector`
+    pub fn is_alive(&self, peer: &str) -> bool {
+        lock(&self.detector).is_ok_and(|detector| detector.is_alive(peer, self.clock.now()))
+    }
+
+    pub fn peer_status(&self, peer: &str) -> PeerStatus {
+        let now = self.clock.now();
+        match lock(&self.detector) {
+            Ok(detector) => detector.status(peer, now),
+            Err(_) => FailureDetector::default().status(peer, now),
+        }
+    }
+
+    /// 距上次发送心跳已满一个间隔时，向 `peers` 发送心跳，返回发出的份数
+    ///
+    /// 心跳不需要确认，丢失了也不重传
+    pub fn heartbeat(&self, peers: &[NodeId]) -> Result<usize> {
+        let now = self.clock.now();
+        let interval = lock(&self.detector)?.interval();
+        let mut last_ping = lock(&self.last_ping)?;
+        if last_ping.is_some_and(|last| now < last + interval) {
+            return Ok(0);
+        }
+        *last_ping = Some(now);
+
+        let sealed = self
+            .auth
+            .seal(&self.id, &self.codec.encode(&Frame::<Content>::Ping)?)?;
+        let book = self.book()?;
+        let sent = peers
+            .iter()
+            .filter_map(|peer| book.resolve(peer))
+            .filter(|address| self.conn.send(address.clone(), sealed.clone()).is_ok())
+            .count();
+        Ok(sent)
+    }
+
+    /// 收件箱的深度统计
+    pub fn inbox_stats(&self) -> QueueStats {
+        self.recv_list.stats()
+    }
+
+    /// 发件箱中尚未发出的邮件的深度统计
+    pub fn outbox_stats(&self) -> QueueStats {
+        self.send_list.stats()
+    }
+
     /// 从收件箱获取新邮件
-    pub fn get_mail(&self) -> Result<Mail<Addr, Content>> {
-        match self.recv_list.try_borrow_mut()?.pop_front() {
-            Some(mail) => Ok(mail),
-            None => Err(anyhow!("MailBox is empty")),
+    pub fn get_mail(&self) -> Result<Mail<Content>> {
+        self.recv_list.pop().ok_or(anyhow!("MailBox is empty"))
+    }
+
+    /// 取出收件箱中的下一封邮件，收件箱为空时先接收一次已到达的消息，
+    /// 不等待
+    pub fn try_recv(&self) -> Option<Mail<Content>> {
+        self.recv_matching(|_| true, Duration::ZERO).ok()
+    }
+
+    /// 等待下一封邮件，最多等待 `timeout`
+    pub fn recv_timeout(&self, timeout: Duration) -> Result<Mail<Content>> {
+        self.recv_matching(|_| true, timeout)
+    }
+
+    /// 等待第一封满足 `predicate` 的邮件，最多等待 `timeout`。
+    ///
+    /// 不满足条件的邮件按原有顺序留在收件箱中，等待期间照常收发、确认与重传。
+    /// 收件箱被不满足条件的邮件占满时（`DropOldest` 除外）无法再接收新邮件，
+    /// 只能等到超时。
+    pub fn recv_matching<P>(&self, mut predicate: P, timeout: Duration) -> Result<Mail<Content>>
+    where
+        P: FnMut(&Mail<Content>) -> bool,
+    {
+        // 超时按实际经过的时间计算，注入的时钟只用于重传
+        let deadline = Instant::now() + timeout;
+        loop {
+            if let Some(mail) = self.recv_list.take(&mut predicate) {
+                return Ok(mail);
+            }
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if self.recv_list.is_full() && self.recv_list.overflow() != Overflow::DropOldest {
+                thread::sleep(remaining.min(FULL_INBOX_BACKOFF));
+            } else {
+                self.pump(remaining)?;
+            }
+            if remaining.is_zero() {
+                return self
+                    .recv_list
+                    .take(&mut predicate)
+                    .ok_or(anyhow!("no matching mail within {:?}", timeout));
+            }
         }
     }
 
     /// 将邮件放置入发件箱
-    pub fn put_mail(&self, mail: Mail<Addr, Content>) -> Result<()> {
-        Ok(self.send_list.try_borrow_mut()?.push_back(mail))
+    pub fn put_mail(&self, mail: Mail<Content>) -> Result<()> {
+        self.send_list.push(mail)
     }
 
-    /// 将所有发件箱中的待发邮件发送至接收者。
-    pub fn flush(&self) -> Result<()> {
-        for mail in self.send_list.borrow_mut().iter() {
-            for receivers in mail.receivers().iter() {
-                self.conn
-                    .send(receivers.to_owned().into(), mail.body().into())?;
+    /// 发送发件箱中的新邮件，并重传到期仍未确认的邮件。
+    ///
+    /// 收件人为组时发送给组内的每个节点，每个收件人分别确认、分别重传；
+    /// 某个收件人发送失败不影响其他收件人，失败的收件人记录在返回的
+    /// `DeliveryReport` 中。以下邮件会被移出发件箱，不再重传：
+    /// 1. 收件人已确认的邮件
+    /// 2. 收件人不在地址簿中的邮件
+    /// 3. 发送次数达到上限仍未确认的邮件
+    pub fn flush(&self) -> Result<DeliveryReport> {
+        let mut report = DeliveryReport::default();
+        let now = self.clock.now();
+        let book = self.book()?;
+        let mut outbox = lock(&self.outbox)?;
+        let mut stats = lock(&self.stats)?;
+        let mut last_mail_ids = lock(&self.last_mail_ids)?;
+
+        // 按收件人分别为新邮件编号，放入待确认列表
+        while let Some(mail) = self.send_list.pop() {
+            for receiver in book.expand(&mail.receivers()) {
+                let last = last_mail_ids.entry(receiver.clone()).or_default();
+                *last += 1;
+
+                let frame = Frame::Mail {
+                    session: self.session,
+                    id: *last,
+                    to: mail.receivers(),
+                    content: &*mail.body,
+                };
+                let sealed = self.auth.seal(&self.id, &self.codec.encode(&frame)?)?;
+                outbox.insert(*last, receiver, sealed, now);
             }
         }
-        Ok(())
+        drop(last_mail_ids);
+
+        for key in outbox.due(now) {
+            let receiver = key.1.clone();
+            let Some(address) = book.resolve(&receiver) else {
+                outbox.remove(&key);
+                let e = anyhow!("unknown node `{}`", receiver);
+                report.failures.push((receiver, e));
+                continue;
+            };
+            let Some(pending) = outbox.get_mut(&key) else {
+                continue;
+            };
+
+            if pending.attempts >= self.retry.max_attempts {
+                let e = anyhow!("not acknowledged after {} attempts", pending.attempts);
+                outbox.remove(&key);
+                stats.expired += 1;
+                report.failures.push((receiver, e));
+                continue;
+            }
+
+            let first = pending.attempts == 0;
+            pending.attempts += 1;
+            pending.next_retry = now + self.retry.backoff(pending.attempts);
+
+            match self.conn.send(address, pending.data.clone()) {
+                Ok(_) => {
+                    report.sent += 1;
+                    if first {
+                        stats.sent += 1;
+                    } else {
+                        stats.retransmitted += 1;
+                    }
+                }
+                Err(e) => report.failures.push((receiver, e)),
+            }
+        }
+
+        stats.pending = outbox.len() as u64;
+        Ok(report)
     }
 
-    /// Block 阻塞直至收到新邮件，并添加至收件箱。
+    /// Block 阻塞直至收到新消息。新邮件添加至收件箱并回复确认，确认则将
+    /// 对应邮件移出发件箱。
+    ///
+    /// 以下邮件会被丢弃：
+    /// 1. 认证失败的邮件
+    /// 2. 无法解码的邮件
+    /// 3. 发件人不在地址簿中的邮件
+    /// 4. 实际来源地址与发件人登记的地址不一致的邮件（伪造的发件人）
+    /// 5. 已经收到过的邮件（仍会再次确认）
     pub fn fill_msg_box(&self) -> Result<()> {
-        let mail = self.conn.recv()?;
-        Ok(self.recv_list.try_borrow_mut()?.push_back(mail.try_into()?))
+        let (_, remote, data) = self.conn.recv()?;
+        self.accept(remote, data)
+    }
+
+    /// 驱动邮箱的收发：发送到期的邮件，再接收所有已到达的消息。
+    ///
+    /// 尚无消息到达时最多等待 `timeout`；无法接受的消息被丢弃，不影响
+    /// 之后的消息。收件箱已满时（`DropOldest` 除外）不再接收，消息留在连接中。
+    pub fn pump(&self, timeout: Duration) -> Result<DeliveryReport> {
+        let report = self.flush()?;
+
+        let mut wait = timeout;
+        for _ in 0..MAX_PUMP_BATCH {
+            if self.recv_list.is_full() && self.recv_list.overflow() != Overflow::DropOldest {
+                break;
+            }
+            let Ok((_, remote, data)) = self.conn.recv_timeout(wait) else {
+                break;
+            };
+            let _ = self.accept(remote, data);
+            wait = Duration::ZERO;
+        }
+        Ok(report)
+    }
+
+    fn book(&self) -> Result<RwLockReadGuard<'_, AddressBook<Addr>>> {
+        self.address_book
+            .read()
+            .map_err(|_| anyhow!("MailBox poisoned"))
+    }
+
+    /// 处理收到的一条消息，见 `fill_msg_box`
+    fn accept(&self, remote: Addr, data: Bytes) -> Result<()> {
+        let (sender, payload) = self.auth.open(data)?;
+
+        match self.book()?.resolve(&sender) {
+            Some(address) if address == remote => {}
+            Some(_) => return Err(anyhow!("drop mail spoofing node `{}`", sender)),
+            None => return Err(anyhow!("drop mail from unknown node `{}`", sender)),
+        }
+        // 认证过的任何消息都说明发件人存活
+        lock(&self.detector)?.heartbeat(&sender, self.clock.now());
+
+        match self.codec.decode(payload)? {
+            Frame::Ping => {}
+            Frame::Ack { session, id } => {
+                let mut outbox = lock(&self.outbox)?;
+                let mut stats = lock(&self.stats)?;
+                if session == self.session && outbox.ack(id, &sender) {
+                    stats.acked += 1;
+                    stats.pending = outbox.len() as u64;
+                }
+            }
+            Frame::Mail {
+                session,
+                id,
+                to,
+                content,
+            } => {
+                let mut dedup = lock(&self.dedup)?;
+                if dedup.seen(&sender, session, id) {
+                    lock(&self.stats)?.duplicates += 1;
+                } else {
+                    // 发件人新的会话先写入会话文件，写入失败时不确认
+                    if let Some(file) = self.session_file.as_ref() {
+                        if dedup.session(&sender) != Some(session) {
+                            lock(file)?.record(&sender, session)?;
+                        }
+                    }
+                    // 收件箱拒绝时不确认，由发件人稍后重传
+                    let mut mail = Mail::new(sender.clone(), to, content);
+                    mail.received_at = Some(self.clock.now());
+                    self.recv_list.push(mail)?;
+                    dedup.record(&sender, session, id);
+                }
+                drop(dedup);
+
+                // 每次收到都要确认，之前的确认可能已经丢失
+                let ack = Frame::<Content>::Ack { session, id };
+                let sealed = self.auth.seal(&self.id, &self.codec.encode(&ack)?)?;
+                let _ = self.conn.send(remote, sealed);
+            }
+        }
+        Ok(())
     }
 }
 
-pub struct Mail<Addr, Content>
+/// UNIX纪元起的纳秒数
+fn wall_clock() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |since| since.as_nanos() as u64)
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> Result<MutexGuard<'_, T>> {
+    mutex.lock().map_err(|_| anyhow!("MailBox poisoned"))
+}
+
+pub struct Mail<Content>
 where
-    Addr: Clone,
-    Content: Clone + TryFrom<Bytes> + Into<Bytes>,
+    Content: Clone + Serialize + DeserializeOwned,
 {
-    from: Addr,
-    to: Vec<Addr>,
+    from: NodeId,
+    to: Vec<Recipient>,
     body: Box<Content>,
+    received_at: Option<Duration>,
 }
 
-impl<Addr, Content> Mail<Addr, Content>
+impl<Content> Mail<Content>
 where
-    Addr: Clone,
-    Content: Clone + TryFrom<Bytes> + Into<Bytes>,
+    Content: Clone + Serialize + DeserializeOwned,
 {
-    pub fn new(from: Addr, to: Vec<Addr>, content: Content) -> Mail<Addr, Content> {
+    pub fn new(from: NodeId, to: Vec<Recipient>, content: Content) -> Mail<Content> {
         Mail {
             from,
             to,
             body: Box::new(content),
+            received_at: None,
         }
     }
 
@@ -88,45 +484,503 @@ where
         (*self.body).clone()
     }
 
-    pub fn sender(&self) -> Addr {
+    /// 收到的邮件为认证过的发件节点编号
+    pub fn sender(&self) -> NodeId {
         self.from.clone()
     }
 
-    pub fn receivers(&self) -> Vec<Addr> {
+    /// 收到的邮件为发件人填写的全部收件人，组不展开
+    pub fn receivers(&self) -> Vec<Recipient> {
         self.to.clone()
     }
+
+    /// 收到邮件时邮箱时钟的读数，本地创建的邮件为 `None`
+    pub fn received_at(&self) -> Option<Duration> {
+        self.received_at
+    }
 }
 
-impl<Addr, Content> TryFrom<(Addr, Addr, Bytes)> for Mail<Addr, Content>
-where
-    Addr: Clone,
-    Content: Clone + TryFrom<Bytes> + Into<Bytes>,
-{
-    type Error = anyhow::Error;
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::{
+            atomic::{AtomicBool, Ordering},
+            Arc,
+        },
+        thread,
+        time::{Duration, Instant},
+    };
 
-    fn try_from(value: (Addr, Addr, Bytes)) -> std::result::Result<Self, Self::Error> {
-        Ok(Mail::new(
-            value.0,
-            vec![value.1],
-            Content::try_from(value.2)
-                .map_err(|_| anyhow!("can not deserialize bytes into Main::Content"))?,
-        ))
+    use super::{Mail, MailBox};
+    use crate::{
+        address_book::{AddressBook, Recipient},
+        auth::Authenticator,
+        clock::ManualClock,
+        codec::Codec,
+        connection::{Connection, Switch},
+        delivery::{RetryPolicy, SessionFile},
+        detector::FailureDetector,
+        issue::Issue,
+        queue::Overflow,
+    };
+
+    /// 连接到 `switch` 的邮箱
+    fn mail_box(switch: &Switch, book: &AddressBook<String>, id: &str) -> MailBox<String, String> {
+        mail_box_with_codec(switch, book, id, Codec::Binary)
     }
-}
 
-impl<Addr, Content> TryFrom<(Addr, Vec<Addr>, Bytes)> for Mail<Addr, Content>
-where
-    Addr: Clone,
-    Content: Clone + TryFrom<Bytes> + Into<Bytes>,
-{
-    type Error = anyhow::Error;
-
-    fn try_from(value: (Addr, Vec<Addr>, Bytes)) -> std::result::Result<Self, Self::Error> {
-        Ok(Mail::new(
-            value.0,
-            value.1,
-            Content::try_from(value.2)
-                .map_err(|_| anyhow!("can not deserialize bytes into Main::Content"))?,
-        ))
+    fn mail_box_with_codec(
+        switch: &Switch,
+        book: &AddressBook<String>,
+        id: &str,
+        codec: Codec,
+    ) -> MailBox<String, String> {
+        MailBox::new(
+            id.to_string(),
+            Box::new(switch.connect(id).unwrap()),
+            book.clone(),
+            Authenticator::new(b"secret"),
+            codec,
+        )
+    }
+
+    #[test]
+    fn group_delivery_reports_failures_test() {
+        let switch = Switch::new();
+
+        let mut book = AddressBook::new();
+        for id in ["master", "worker-1", "worker-2", "worker-3"] {
+            book.insert(id.to_string(), id.to_string());
+        }
+        for id in ["worker-1", "worker-2", "worker-3", "worker-4"] {
+            book.join("worker", id.to_string());
+        }
+
+        let master = mail_box(&switch, &book, "master");
+        let workers: Vec<_> = ["worker-1", "worker-2"]
+            .iter()
+            .map(|id| mail_box_with_codec(&switch, &book, id, Codec::Json))
+            .collect();
+
+        let mail = Mail::new(
+            "master".to_string(),
+            vec![Recipient::Group("worker".to_string())],
+            "proposal".to_string(),
+        );
+        master.put_mail(mail).unwrap();
+        let report = master.flush().unwrap();
+
+        // worker-3 的地址没有连接到 switch，worker-4 没有地址
+        assert_eq!(report.sent, 2);
+        let failed: Vec<String> = report.failures.into_iter().map(|(id, _)| id).collect();
+        assert_eq!(failed, vec!["worker-3".to_string(), "worker-4".to_string()]);
+
+        for worker in workers.iter() {
+            worker.fill_msg_box().unwrap();
+            let mail = worker.get_mail().unwrap();
+            assert_eq!(mail.sender(), "master");
+            assert_eq!(
+                mail.receivers(),
+                vec![Recipient::Group("worker".to_string())]
+            );
+            assert!(mail.received_at().is_some());
+            assert_eq!(mail.body(), "proposal");
+        }
+    }
+
+    #[test]
+    fn dedup_stays_bounded_across_recipients_test() {
+        let switch = Switch::new();
+        let ids = ["master", "worker-1", "worker-2", "worker-3"];
+        let mut book = AddressBook::new();
+        for id in ids {
+            book.insert(id.to_string(), id.to_string());
+        }
+        let boxes: Vec<_> = ids.iter().map(|id| mail_box(&switch, &book, id)).collect();
+        let (master, workers) = boxes.split_first().unwrap();
+
+        // 轮流发给不同的收件人，每个收件人只收到发件人的一部分邮件
+        for round in 0..3000 {
+            let to = ids[1 + round % workers.len()].to_string();
+            let mail = Mail::new(master.id(), vec![Recipient::Node(to)], round.to_string());
+            master.put_mail(mail).unwrap();
+            master.flush().unwrap();
+            for worker in workers {
+                worker.pump(Duration::ZERO).unwrap();
+                while worker.get_mail().is_ok() {}
+            }
+            master.pump(Duration::ZERO).unwrap();
+        }
+
+        for worker in workers {
+            assert_eq!(worker.dedup.lock().unwrap().tracked(), 0);
+        }
+        assert_eq!(master.stats().acked, 3000);
+    }
+
+    #[test]
+    fn reject_unauthenticated_mail_test() {
+        let switch = Switch::new();
+
+        let mut book = AddressBook::new();
+        for id in ["master", "worker-1", "intruder"] {
+            book.insert(id.to_string(), id.to_string());
+        }
+
+        let master = mail_box(&switch, &book, "master");
+        let intruder = switch.connect("intruder").unwrap();
+        let stranger = switch.connect("stranger").unwrap();
+
+        // 密钥不同
+        let sealed = Authenticator::new(b"guess")
+            .seal("worker-1", b"vote")
+            .unwrap();
+        intruder.send("master".to_string(), sealed).unwrap();
+        assert!(master.fill_msg_box().is_err());
+
+        // 密钥相同，但冒充其他节点
+        let sealed = Authenticator::new(b"secret")
+            .seal("worker-1", b"vote")
+            .unwrap();
+        intruder.send("master".to_string(), sealed).unwrap();
+        assert!(master.fill_msg_box().is_err());
+
+        // 密钥相同，但节点不在地址簿中
+        let sealed = Authenticator::new(b"secret")
+            .seal("stranger", b"vote")
+            .unwrap();
+        stranger.send("master".to_string(), sealed).unwrap();
+        assert!(master.fill_msg_box().is_err());
+
+        assert!(master.get_mail().is_err(), "不应收下任何邮件");
+    }
+
+    #[test]
+    fn retransmit_until_acked_test() {
+        let switch = Switch::manual();
+        let clock = ManualClock::new();
+
+        let mut book = AddressBook::new();
+        for id in ["master", "worker-1"] {
+            book.insert(id.to_string(), id.to_string());
+        }
+        let open = |id: &str| {
+            mail_box(&switch, &book, id)
+                .with_clock(Arc::new(clock.clone()))
+                .with_retry_policy(RetryPolicy {
+                    initial: Duration::from_millis(10),
+                    max: Duration::from_millis(40),
+                    max_attempts: 5,
+                })
+        };
+        let master = open("master");
+        let worker = open("worker-1");
+
+        let to_worker = vec![Recipient::Node("worker-1".to_string())];
+        master
+            .put_mail(Mail::new(master.id(), to_worker, "prepare".to_string()))
+            .unwrap();
+
+        // 第一份丢失，退避的时间之前不会重传
+        assert_eq!(master.flush().unwrap().sent, 1);
+        switch.drop_packet(0).unwrap();
+        assert_eq!(master.flush().unwrap().sent, 0);
+
+        // 两次重传都送达
+        clock.advance(Duration::from_millis(10));
+        assert_eq!(master.flush().unwrap().sent, 1);
+        clock.advance(Duration::from_millis(20));
+        assert_eq!(master.flush().unwrap().sent, 1);
+        while switch.in_flight() > 0 {
+            switch.deliver(0);
+        }
+
+        worker.fill_msg_box().unwrap();
+        worker.fill_msg_box().unwrap();
+        assert_eq!(worker.get_mail().unwrap().body(), "prepare");
+        assert!(worker.get_mail().is_err(), "重复的邮件被丢弃");
+        assert_eq!(worker.stats().duplicates, 1);
+
+        // 两份都得到确认，邮件离开发件箱
+        while switch.in_flight() > 0 {
+            switch.deliver(0);
+        }
+        master.fill_msg_box().unwrap();
+        master.fill_msg_box().unwrap();
+        let stats = master.stats();
+        assert_eq!(
+            (stats.sent, stats.retransmitted, stats.acked, stats.pending),
+            (1, 2, 1, 0)
+        );
+
+        clock.advance(Duration::from_secs(1));
+        assert_eq!(master.flush().unwrap().sent, 0);
+        assert_eq!(switch.in_flight(), 0);
+    }
+
+    #[test]
+    fn give_up_after_max_attempts_test() {
+        let switch = Switch::manual();
+        let clock = ManualClock::new();
+
+        let mut book = AddressBook::new();
+        book.insert("worker-1".to_string(), "worker-1".to_string());
+        let _worker = switch.connect("worker-1").unwrap();
+
+        let master = mail_box(&switch, &book, "master")
+            .with_clock(Arc::new(clock.clone()))
+            .with_retry_policy(RetryPolicy {
+                initial: Duration::from_millis(10),
+                max: Duration::from_millis(10),
+                max_attempts: 3,
+            });
+
+        let to_worker = vec![Recipient::Node("worker-1".to_string())];
+        master
+            .put_mail(Mail::new(master.id(), to_worker, "prepare".to_string()))
+            .unwrap();
+
+        for _ in 0..3 {
+            assert_eq!(master.flush().unwrap().sent, 1);
+            clock.advance(Duration::from_millis(10));
+        }
+        let report = master.flush().unwrap();
+        assert_eq!(report.sent, 0);
+        assert_eq!(report.failures.len(), 1);
+
+        let stats = master.stats();
+        assert_eq!((stats.expired, stats.pending), (1, 0));
+    }
+
+    #[test]
+    fn shared_between_threads_test() {
+        fn assert_send_sync<T: Send + Sync>() {}
+        assert_send_sync::<MailBox<String, Issue>>();
+
+        let switch = Switch::new();
+        let mut book = AddressBook::new();
+        for id in ["master", "worker-1"] {
+            book.insert(id.to_string(), id.to_string());
+        }
+        // 收发在不同的线程中，`Block` 才不会永远等待
+        let open =
+            |id: &str| Arc::new(mail_box(&switch, &book, id).with_capacity(4, 4, Overflow::Block));
+        let master = open("master");
+        let worker = open("worker-1");
+
+        // worker 的收发在后台线程，处理在当前线程
+        let stop = Arc::new(AtomicBool::new(false));
+        let receiver = {
+            let (worker, stop) = (worker.clone(), stop.clone());
+            thread::spawn(move || {
+                while !stop.load(Ordering::Acquire) {
+                    let _ = worker.pump(Duration::from_millis(1));
+                }
+            })
+        };
+        let sender = {
+            let master = master.clone();
+            thread::spawn(move || {
+                for i in 0..20 {
+                    let to = vec![Recipient::Node("worker-1".to_string())];
+                    let mail = Mail::new(master.id(), to, i.to_string());
+                    master.put_mail(mail).unwrap();
+                    master.pump(Duration::from_millis(1)).unwrap();
+                }
+                while master.stats().pending > 0 {
+                    master.pump(Duration::from_millis(1)).unwrap();
+                }
+            })
+        };
+
+        let mut received = Vec::new();
+        while received.len() < 20 {
+            match worker.get_mail() {
+                Ok(mail) => received.push(mail.body()),
+                Err(_) => thread::sleep(Duration::from_millis(1)),
+            }
+        }
+        sender.join().unwrap();
+        stop.store(true, Ordering::Release);
+        receiver.join().unwrap();
+
+        assert_eq!(received, (0..20).map(|i| i.to_string()).collect::<Vec<_>>());
+        assert!(worker.inbox_stats().high_watermark <= 4);
+    }
+
+    #[test]
+    fn recv_matching_test() {
+        let switch = Switch::new();
+        let mut book = AddressBook::new();
+        for id in ["master", "worker-1"] {
+            book.insert(id.to_string(), id.to_string());
+        }
+        let master = mail_box(&switch, &book, "master");
+        let worker = mail_box(&switch, &book, "worker-1");
+
+        assert!(master.try_recv().is_none());
+        let start = Instant::now();
+        assert!(master.recv_timeout(Duration::from_millis(20)).is_err());
+        assert!(start.elapsed() >= Duration::from_millis(20));
+
+        for body in ["promise 1", "accepted 1", "promise 2"] {
+            let to = vec![Recipient::Node("master".to_string())];
+            worker
+                .put_mail(Mail::new(worker.id(), to, body.to_string()))
+                .unwrap();
+        }
+        worker.flush().unwrap();
+
+        // 跳过的邮件留在收件箱，保持原有顺序
+        let promise = |mail: &Mail<String>| mail.body().starts_with("promise");
+        let timeout = Duration::from_secs(1);
+        assert_eq!(
+            master.recv_matching(promise, timeout).unwrap().body(),
+            "promise 1"
+        );
+        assert_eq!(
+            master.recv_matching(promise, timeout).unwrap().body(),
+            "promise 2"
+        );
+        assert!(master.recv_matching(promise, Duration::ZERO).is_err());
+        assert_eq!(master.try_recv().unwrap().body(), "accepted 1");
+        assert!(master.try_recv().is_none());
+    }
+
+    #[test]
+    fn heartbeat_marks_peers_alive_test() {
+        let switch = Switch::manual();
+        let clock = ManualClock::new();
+
+        let mut book = AddressBook::new();
+        for id in ["master", "worker-1"] {
+            book.insert(id.to_string(), id.to_string());
+        }
+        let open = |id: &str| {
+            mail_box(&switch, &book, id)
+                .with_clock(Arc::new(clock.clone()))
+                .with_failure_detector(FailureDetector::new(Duration::from_millis(100), 3.0))
+        };
+        let master = open("master");
+        let worker = open("worker-1");
+        let peers = vec!["worker-1".to_string(), "worker-9".to_string()];
+
+        assert!(!worker.is_alive("master"));
+        assert_eq!(master.heartbeat(&peers).unwrap(), 1);
+        assert_eq!(master.heartbeat(&peers).unwrap(), 0, "未到时间");
+        switch.deliver(0);
+        worker.pump(Duration::ZERO).unwrap();
+
+        // 心跳不进入收件箱，也不需要确认
+        assert!(worker.is_alive("master"));
+        assert!(worker.try_recv().is_none());
+        assert_eq!(switch.in_flight(), 0);
+
+        clock.advance(Duration::from_millis(300));
+        assert!(!worker.is_alive("master"));
+        assert_eq!(worker.peer_status("master").silent_ms, Some(300));
+        assert_eq!(master.heartbeat(&peers).unwrap(), 1);
+    }
+
+    #[test]
+    fn full_inbox_defers_ack_test() {
+        let switch = Switch::manual();
+        let clock = ManualClock::new();
+
+        let mut book = AddressBook::new();
+        for id in ["master", "worker-1"] {
+            book.insert(id.to_string(), id.to_string());
+        }
+        let open = |id: &str| {
+            mail_box(&switch, &book, id)
+                .with_clock(Arc::new(clock.clone()))
+                .with_capacity(1, 8, Overflow::Reject)
+        };
+        let master = open("master");
+        let worker = open("worker-1");
+
+        for body in ["first", "second"] {
+            let to = vec![Recipient::Node("worker-1".to_string())];
+            master
+                .put_mail(Mail::new(master.id(), to, body.to_string()))
+                .unwrap();
+        }
+        assert_eq!(master.flush().unwrap().sent, 2);
+        while switch.in_flight() > 0 {
+            switch.deliver(0);
+        }
+
+        // 收件箱只能容纳一封，第二封被拒绝且不确认
+        worker.fill_msg_box().unwrap();
+        assert!(worker.fill_msg_box().is_err());
+        assert_eq!(worker.inbox_stats().rejected, 1);
+        assert_eq!(switch.in_flight(), 1);
+
+        switch.deliver(0);
+        master.fill_msg_box().unwrap();
+        assert_eq!(master.stats().pending, 1);
+
+        // 收件箱有空位后，重传的邮件被收下
+        assert_eq!(worker.get_mail().unwrap().body(), "first");
+        clock.advance(Duration::from_secs(1));
+        assert_eq!(master.flush().unwrap().sent, 1);
+        switch.deliver(0);
+        worker.fill_msg_box().unwrap();
+        assert_eq!(worker.get_mail().unwrap().body(), "second");
+    }
+
+    #[test]
+    fn sessions_survive_restart_test() {
+        let switch = Switch::manual();
+        let mut book = AddressBook::new();
+        for id in ["master", "worker-1"] {
+            book.insert(id.to_string(), id.to_string());
+        }
+        let path = |id: &str| {
+            let name = format!("somepox-restart-{}-{}.session", id, std::process::id());
+            std::env::temp_dir().join(name)
+        };
+        for id in ["master", "worker-1"] {
+            let _ = std::fs::remove_file(path(id));
+        }
+        let open = |id: &str| {
+            let file = SessionFile::open(path(id)).unwrap();
+            mail_box(&switch, &book, id)
+                .with_session_file(file)
+                .unwrap()
+        };
+        let send = |master: &MailBox<String, String>, body: &str| {
+            let to = vec![Recipient::Node("worker-1".to_string())];
+            master
+                .put_mail(Mail::new(master.id(), to, body.to_string()))
+                .unwrap();
+            master.flush().unwrap();
+        };
+
+        let master = open("master");
+        let worker = open("worker-1");
+        send(&master, "old");
+        let old_session = master.session;
+        drop(master);
+
+        // 议长重启后换用更大的会话编号，邮件编号重新开始也能送达
+        let master = open("master");
+        assert!(master.session > old_session);
+        send(&master, "new");
+        switch.deliver(1);
+        worker.fill_msg_box().unwrap();
+        assert_eq!(worker.get_mail().unwrap().body(), "new");
+
+        // 议员重启后，迟到的旧会话的邮件仍被丢弃
+        drop(worker);
+        let worker = open("worker-1");
+        switch.deliver(0);
+        worker.fill_msg_box().unwrap();
+        assert!(worker.get_mail().is_err());
+        assert_eq!(worker.stats().duplicates, 1);
+
+        for id in ["master", "worker-1"] {
+            let _ = std::fs::remove_file(path(id));
+        }
     }
 }
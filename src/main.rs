@@ -1,21 +1,62 @@
-use std::{collections::HashMap, path::PathBuf, sync::mpsc::channel, thread, time::Duration};
+use std::{
+    collections::HashMap,
+    path::PathBuf,
+    sync::mpsc::{channel, Sender, TryRecvError},
+    thread,
+    time::Duration,
+};
 
 use anyhow::{anyhow, Result};
 use clap::{Parser, Subcommand};
 
+use address_book::AddressBook;
 use api::api_server_init;
 use config::{load_config, Config, LogType};
+use connection::{Connection, Net, Tls};
+use delivery::SessionFile;
+use detector::FailureDetector;
+use issue::Issue;
+use lock::LockError;
 use logbackend::{FileLogBackend, HeapLogBackend, LogBackend};
-use roles::{Master, Worker};
+use mailbox::MailBox;
+use read::Consistency;
+use roles::{Learner, Master, Worker, LEARNER_GROUP, MASTER_GROUP, WORKER_GROUP};
+use shutdown::Shutdown;
 
+mod acceptor;
+mod address_book;
+mod admin;
 mod api;
+mod auth;
+mod bench;
+mod clock;
+mod codec;
 mod config;
 mod connection;
+mod delivery;
+mod detector;
 mod executor;
 mod issue;
+mod lock;
 mod logbackend;
 mod mailbox;
+mod membership;
+mod mencius;
+mod queue;
+mod quorum;
+mod read;
+mod rng;
 mod roles;
+mod session;
+mod shutdown;
+#[cfg(test)]
+mod sim;
+
+/// 服务线程检查停止信号的间隔
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Master 每轮等待邮件的时间，同时决定API请求最长的排队时间
+const MASTER_TICK: Duration = Duration::from_millis(10);
 
 /// A Simple Paxos Algorithm Implement.
 #[derive(Parser)]
@@ -37,54 +78,201 @@ struct Args {
 enum Role {
     Master,
     Worker,
+    /// Start a worker which keeps ballots but not the proposed values
+    Witness,
+    /// Start a non-voting node which replicates the log and serves stale reads
+    Learner,
+    /// Add a worker to the cluster through consensus
+    AddNode {
+        /// Id of the new worker
+        id: String,
+        /// Address the new worker listens on
+        address: String,
+    },
+    /// Remove a worker from the cluster through consensus
+    RemoveNode {
+        /// Id of the worker
+        id: String,
+    },
+    /// List the workers of the cluster
+    Members,
+    /// Show which workers the master sees alive
+    Status,
+    /// Measure throughput and latency versus the window size, in process
+    Bench {
+        /// Window sizes to measure
+        #[arg(long, value_delimiter = ',', default_value = "1,4,16,64")]
+        windows: Vec<u64>,
+        /// Proposals to apply for each window size
+        #[arg(long, default_value_t = 5000)]
+        proposals: usize,
+        /// Number of workers
+        #[arg(long, default_value_t = 3)]
+        workers: usize,
+    },
+}
+
+/// 配置文件中Master的API地址，管理命令经由它执行
+fn master_api(config: Option<PathBuf>) -> String {
+    config
+        .and_then(|config_path| load_config(config_path, Some("master".to_string())).ok())
+        .unwrap_or_default()
+        .api()
+}
+
+/// 按配置建立节点的邮箱：监听配置的地址，地址簿中的学习者加入 `LEARNER_GROUP`
+/// 组，其他议长加入 `MASTER_GROUP` 组，其他节点都加入 `group` 组
+fn open_mail_box(cfg: &Config, group: &str) -> Result<MailBox<String, Issue>> {
+    let (learners, masters) = (cfg.learners(), cfg.masters());
+    let mut address_book = AddressBook::new();
+    for (id, address) in cfg.address_book() {
+        address_book.insert(id.clone(), address);
+        if learners.contains(&id) {
+            address_book.join(LEARNER_GROUP, id);
+        } else if masters.contains(&id) {
+            address_book.join(MASTER_GROUP, id);
+        } else {
+            address_book.join(group, id);
+        }
+    }
+
+    cfg.validate_mailbox()?;
+    let auth = cfg.authenticator()?;
+    if cfg.insecure() {
+        println!("WARNING: `insecure` is set, peer messages are not authenticated.");
+    }
+
+    let conn: Box<dyn Connection<Addr = String>> = match cfg.tls() {
+        Some(tls) => Box::new(Tls::new(cfg.address(), &tls.cert, &tls.key, &tls.ca)?),
+        None => Box::new(Net::new(cfg.address())?),
+    };
+
+    let limits = cfg.mailbox();
+    let heartbeat = cfg.heartbeat();
+    let detector = FailureDetector::new(
+        Duration::from_millis(heartbeat.interval_ms),
+        heartbeat.suspicion_threshold,
+    );
+    let mail_box = MailBox::new(cfg.id(), conn, address_book, auth, cfg.codec())
+        .with_capacity(
+            limits.inbox_capacity,
+            limits.outbox_capacity,
+            limits.overflow,
+        )
+        .with_failure_detector(detector);
+    match cfg.session_file() {
+        Some(path) => mail_box.with_session_file(SessionFile::open(path)?),
+        None => Ok(mail_box),
+    }
 }
 
-fn start_master(cfg: Config) -> Result<()> {
+fn open_log_backend(cfg: &Config) -> Result<Box<dyn LogBackend>> {
+    Ok(match cfg.log_backend() {
+        LogType::Heap => Box::new(HeapLogBackend::new()),
+        LogType::File(file_name) => Box::new(FileLogBackend::open(&file_name)?),
+    })
+}
+
+fn start_master(cfg: Config, shutdown: Shutdown) -> Result<()> {
+    // 法定人数或窗口的配置不可行时拒绝启动
+    cfg.validate_quorum()?;
+    cfg.validate_window()?;
+
     // 初始化Web-API功能
     let api_endpoint = cfg.api();
     let (tx, rx) = channel();
     // 启动Web-API
+    let api_shutdown = shutdown.clone();
     let api_handler = thread::Builder::new()
         .name("master_api_interface".to_string())
-        .spawn(move || api_server_init(api_endpoint, tx).ok())?;
-
-    // 准备Master的配置
-    let address = cfg.address();
-    let mut address_book = HashMap::new();
-    let mut worker_list = Vec::with_capacity(5);
-    for v in cfg.address_book().values() {
-        worker_list.push(v.clone());
-    }
-    address_book.insert("worker".to_string(), worker_list);
-    let log_type = cfg.log_backend();
+        .spawn(move || api_server_init(api_endpoint, tx, api_shutdown).ok())?;
 
     // 启动master服务
     let service_handler = thread::Builder::new()
         .name("master_interface".to_string())
         .spawn(move || {
-            let logbackend: Box<dyn LogBackend> = match log_type {
-                LogType::Heap => Box::new(HeapLogBackend::new()),
-                LogType::File(file_name) => Box::new(FileLogBackend::new(&file_name)),
-            };
-
-            let master = Master::new(address, address_book, logbackend);
+            // 准备Master的邮箱，地址簿中学习者与其他议长以外的节点均为议员；
+            // 日志无法恢复时拒绝启动
+            let opened = open_log_backend(&cfg)
+                .and_then(|logbackend| Master::new(open_mail_box(&cfg, WORKER_GROUP)?, logbackend));
+            if let Err(e) = &opened {
+                println!("ERROR: can't start the master: {}", e);
+            }
+            if let Ok(master) = opened {
+                let master = master
+                    .with_quorums(cfg.quorum())
+                    .with_window(cfg.window())
+                    .with_witnesses(cfg.witnesses())
+                    .with_protocol(cfg.protocol());
+                // 等待决议的API请求：议题编号 -> 回复，重复提交的命令等待同一个议题
+                let mut waiters = HashMap::new();
+                // 等待确认领导权的查询：读请求编号 -> (日志编号, 回复)
+                let mut readers = HashMap::new();
 
-            if let Ok(master) = master {
-                loop {
-                    if let Ok(cmd) = rx.recv() {
-                        let _ = match cmd {
-                            api::CmdType::Log(log_command) => {
-                                let _ = master.emmit_new_proposal(log_command.clone());
-                                Ok(log_command)
+                while !shutdown.is_triggered() {
+                    // 处理所有排队的API请求
+                    loop {
+                        match rx.try_recv() {
+                            Ok(api::CmdType::Log(log_command, reply)) => {
+                                let proposed = master.emmit_new_proposal(log_command);
+                                await_decision(&mut waiters, proposed, reply);
+                            }
+                            Ok(api::CmdType::Submit(command, reply)) => {
+                                let proposed = master.submit(command);
+                                await_decision(&mut waiters, proposed, reply);
+                            }
+                            Ok(api::CmdType::Reconfigure(change, reply)) => {
+                                let proposed = master.propose_change(change);
+                                await_decision(&mut waiters, proposed, reply);
+                            }
+                            Ok(api::CmdType::Lock(name, op, reply)) => {
+                                let proposed = master.lock(name, op);
+                                await_decision(&mut waiters, proposed, reply);
+                            }
+                            Ok(api::CmdType::Members(reply)) => {
+                                let _ = reply.send(master.members());
                             }
-                            api::CmdType::Query(id) => master.get_log(id),
-                        };
+                            Ok(api::CmdType::Status(reply)) => {
+                                let _ = reply.send(master.status());
+                            }
+                            Ok(api::CmdType::Query(id, consistency, reply)) => {
+                                readers.insert(master.read(consistency), (id, reply));
+                            }
+                            Err(TryRecvError::Empty) => break,
+                            // API服务已退出
+                            Err(TryRecvError::Disconnected) => {
+                                shutdown.trigger();
+                                break;
+                            }
+                        }
+                    }
+                    // 下发议题，收回投票并计票
+                    let _ = master.tick(MASTER_TICK);
+                    // 回复已形成决议或失败的API请求
+                    for (id, outcome) in master.take_outcomes() {
+                        for reply in waiters.remove(&id).unwrap_or_default() {
+                            // 未能生效的锁命令保留 `LockError`，以便API区分冲突
+                            let outcome = match &outcome {
+                                Ok(()) => Ok(id),
+                                Err(e) => match e.downcast_ref::<LockError>() {
+                                    Some(e) => Err(e.clone().into()),
+                                    None => Err(anyhow!("{}", e)),
+                                },
+                            };
+                            let _ = reply.send(outcome);
+                        }
+                    }
+                    // 回复可以安全读取或失败的查询
+                    for (read, outcome) in master.take_reads() {
+                        if let Some((id, reply)) = readers.remove(&read) {
+                            let _ = reply.send(outcome.map(|_| master.get_log(id).ok()));
+                        }
                     }
-                    let _ = master.process_vote();
-
-                    thread::sleep(Duration::from_secs(1));
                 }
+                let _ = master.close();
             }
+            // Master无法启动时，同时停止API服务
+            shutdown.trigger();
         })?;
 
     // 等待API服务结束
@@ -99,28 +287,117 @@ fn start_master(cfg: Config) -> Result<()> {
     Ok(())
 }
 
-fn start_worker(cfg: Config) -> Result<()> {
-    //准备配置
-    let mut address_book = HashMap::new();
-    let mut master = Vec::with_capacity(1);
-    for v in cfg.address_book().values() {
-        master.push(v.clone());
+/// 议题提出后等待决议时再回复，无法提出时立即回复
+fn await_decision(
+    waiters: &mut HashMap<u64, Vec<Sender<Result<u64>>>>,
+    proposed: Result<u64>,
+    reply: Sender<Result<u64>>,
+) {
+    match proposed {
+        Ok(id) => {
+            waiters.entry(id).or_default().push(reply);
+        }
+        Err(e) => {
+            let _ = reply.send(Err(e));
+        }
     }
-    address_book.insert("master".to_string(), master);
+}
 
+/// 启动议员，`witness` 时作为只保存轮次的见证者
+fn start_worker(cfg: Config, shutdown: Shutdown, witness: bool) -> Result<()> {
     // 启动worker服务
     let service_handler = thread::Builder::new()
         .name("worker_service".to_string())
         .spawn(move || {
-            if let Ok(worker) = Worker::new(cfg.address(), address_book) {
-                loop {
-                    let _ = worker.vote();
-                }
-            };
+            //准备邮箱，地址簿中的节点均为议长；承诺与接受的提案保存在日志中，
+            // 无法恢复时拒绝启动
+            let mail_box = open_mail_box(&cfg, MASTER_GROUP)?;
+            let worker = Worker::new(mail_box, open_log_backend(&cfg)?)?.with_witness(witness);
+            while !shutdown.is_triggered() {
+                let _ = worker.tick(POLL_INTERVAL);
+            }
+            Ok(())
         })?;
 
     // 等待worker结束
-    service_handler.join().map_err(|_| anyhow!("ERROR"))
+    service_handler.join().map_err(|_| anyhow!("ERROR"))?
+}
+
+/// 启动学习者，配置了API地址时提供过期读查询，不接受提交与管理命令
+fn start_learner(cfg: Config, shutdown: Shutdown) -> Result<()> {
+    let (tx, rx) = channel();
+    let serving = cfg.configured_api().is_some();
+    let api_handler = match cfg.configured_api() {
+        Some(api_endpoint) => {
+            let api_shutdown = shutdown.clone();
+            Some(
+                thread::Builder::new()
+                    .name("learner_api_interface".to_string())
+                    .spawn(move || api_server_init(api_endpoint, tx, api_shutdown).ok())?,
+            )
+        }
+        None => None,
+    };
+
+    let service_handler = thread::Builder::new()
+        .name("learner_service".to_string())
+        .spawn(move || {
+            // 准备邮箱，地址簿中的节点均为议长；日志无法恢复时拒绝启动
+            let opened = open_log_backend(&cfg).and_then(|logbackend| {
+                Learner::new(open_mail_box(&cfg, MASTER_GROUP)?, logbackend)
+            });
+            if let Err(e) = &opened {
+                println!("ERROR: can't start the learner: {}", e);
+            }
+            if let Ok(learner) = opened {
+                while !shutdown.is_triggered() {
+                    loop {
+                        match rx.try_recv() {
+                            Ok(api::CmdType::Query(id, Consistency::Stale, reply)) => {
+                                let _ = reply.send(Ok(learner.get_log(id).ok()));
+                            }
+                            Ok(api::CmdType::Query(_, consistency, reply)) => {
+                                let _ = reply.send(Err(anyhow!(
+                                    "a learner only serves stale reads, not {:?}",
+                                    consistency
+                                )));
+                            }
+                            Ok(api::CmdType::Log(_, reply))
+                            | Ok(api::CmdType::Submit(_, reply))
+                            | Ok(api::CmdType::Reconfigure(_, reply))
+                            | Ok(api::CmdType::Lock(_, _, reply)) => {
+                                let _ = reply.send(Err(anyhow!("a learner accepts no proposals")));
+                            }
+                            // 成员与集群状态只能向议长查询
+                            Ok(api::CmdType::Members(_)) | Ok(api::CmdType::Status(_)) => {}
+                            Err(TryRecvError::Empty) => break,
+                            // API服务已退出
+                            Err(TryRecvError::Disconnected) => {
+                                if serving {
+                                    shutdown.trigger();
+                                }
+                                break;
+                            }
+                        }
+                    }
+                    let _ = learner.tick(MASTER_TICK);
+                }
+                let _ = learner.close();
+            }
+            // 学习者无法启动时，同时停止API服务
+            shutdown.trigger();
+        })?;
+
+    if let Some(api_handler) = api_handler {
+        api_handler
+            .join()
+            .map_err(|_| anyhow!("Can't finishing thread API-service."))?;
+    }
+    service_handler
+        .join()
+        .map_err(|_| anyhow!("Can't finishing thread Learner-service."))?;
+
+    Ok(())
 }
 
 fn main() -> Result<()> {
@@ -130,20 +407,394 @@ fn main() -> Result<()> {
     let name = args.name;
     let config = args.config;
 
-    let _ = match role {
+    let shutdown = Shutdown::new();
+    shutdown.install_signal_handler()?;
+
+    match role {
         Role::Master => start_master(
             config
-                .map(|config_path| load_config(config_path, Some("master".to_string())).ok())
-                .flatten()
+                .and_then(|config_path| load_config(config_path, Some("master".to_string())).ok())
                 .unwrap_or_default(),
+            shutdown,
         ),
         Role::Worker => start_worker(
             config
-                .map(|config_path| load_config(config_path, name).ok())
-                .flatten()
+                .and_then(|config_path| load_config(config_path, name).ok())
+                .unwrap_or_default(),
+            shutdown,
+            false,
+        ),
+        Role::Witness => start_worker(
+            config
+                .and_then(|config_path| load_config(config_path, name).ok())
+                .unwrap_or_default(),
+            shutdown,
+            true,
+        ),
+        Role::Learner => start_learner(
+            config
+                .and_then(|config_path| load_config(config_path, name).ok())
                 .unwrap_or_default(),
+            shutdown,
         ),
+        Role::AddNode { id, address } => {
+            admin::add_node(&master_api(config), &id, &address).map(|reply| println!("{}", reply))
+        }
+        Role::RemoveNode { id } => {
+            admin::remove_node(&master_api(config), &id).map(|reply| println!("{}", reply))
+        }
+        Role::Members => admin::members(&master_api(config)).map(|reply| println!("{}", reply)),
+        Role::Status => admin::status(&master_api(config)).map(|reply| println!("{}", reply)),
+        Role::Bench {
+            windows,
+            proposals,
+            workers,
+        } => windows.into_iter().try_for_each(|window| {
+            bench::run(window, proposals, workers).map(|report| println!("{}", report))
+        }),
     }?;
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::{Read, Write},
+        net::{TcpListener, TcpStream},
+        thread::{self, JoinHandle},
+        time::{Duration, Instant},
+    };
+
+    use crate::{
+        admin, config::parse_config, shutdown::Shutdown, start_learner, start_master, start_worker,
+    };
+
+    const CLUSTER: &str = "
+master:
+  api: 127.0.0.1:0
+  address: 127.0.0.1:0
+  address_book:
+    worker-1: 127.0.0.1:9
+  secret: test
+worker-1:
+  address: 127.0.0.1:0
+  address_book:
+    master: 127.0.0.1:9
+  secret: test
+";
+
+    #[test]
+    fn cluster_start_stop_test() {
+        for _ in 0..3 {
+            let shutdown = Shutdown::new();
+
+            let master = {
+                let cfg = parse_config(CLUSTER, "master").unwrap();
+                let shutdown = shutdown.clone();
+                thread::spawn(move || start_master(cfg, shutdown))
+            };
+            let worker = {
+                let cfg = parse_config(CLUSTER, "worker-1").unwrap();
+                let shutdown = shutdown.clone();
+                thread::spawn(move || start_worker(cfg, shutdown, false))
+            };
+
+            thread::sleep(Duration::from_millis(300));
+            let start = Instant::now();
+            shutdown.trigger();
+
+            assert!(master.join().unwrap().is_ok());
+            assert!(worker.join().unwrap().is_ok());
+            assert!(
+                start.elapsed() < Duration::from_secs(5),
+                "cluster took {:?} to stop",
+                start.elapsed()
+            );
+        }
+    }
+
+    /// 议长、两名议员（其中一名为见证者）与一名学习者，都监听空闲的端口
+    struct VotingCluster {
+        /// 议长的API地址
+        api: String,
+        /// 学习者的API地址
+        learner_api: String,
+        shutdown: Shutdown,
+        nodes: Vec<JoinHandle<anyhow::Result<()>>>,
+    }
+
+    /// 由系统分配的空闲端口
+    fn free_port() -> u16 {
+        TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port()
+    }
+
+    impl VotingCluster {
+        /// 启动所有节点，等待议长的API服务启动
+        fn start() -> Self {
+            let [api, master, worker_1, worker_2, learner_api, learner_1] =
+                [(); 6].map(|_| format!("127.0.0.1:{}", free_port()));
+            let config = format!(
+                "
+master:
+  api: {api}
+  address: {master}
+  address_book:
+    worker-1: {worker_1}
+    worker-2: {worker_2}
+    learner-1: {learner_1}
+  learners: [learner-1]
+  witnesses: [worker-2]
+  secret: test
+worker-1:
+  address: {worker_1}
+  address_book:
+    master: {master}
+  secret: test
+worker-2:
+  address: {worker_2}
+  address_book:
+    master: {master}
+  secret: test
+learner-1:
+  api: {learner_api}
+  address: {learner_1}
+  address_book:
+    master: {master}
+  secret: test
+"
+            );
+
+            let shutdown = Shutdown::new();
+            let nodes = ["master", "worker-1", "worker-2", "learner-1"]
+                .into_iter()
+                .map(|name| {
+                    let cfg = parse_config(&config, name).unwrap();
+                    let shutdown = shutdown.clone();
+                    thread::spawn(move || match name {
+                        "master" => start_master(cfg, shutdown),
+                        "worker-2" => start_worker(cfg, shutdown, true),
+                        "learner-1" => start_learner(cfg, shutdown),
+                        _ => start_worker(cfg, shutdown, false),
+                    })
+                })
+                .collect();
+            let cluster = Self {
+                api,
+                learner_api,
+                shutdown,
+                nodes,
+            };
+
+            let start = Instant::now();
+            while admin::members(&cluster.api).is_err() {
+                assert!(
+                    start.elapsed() < Duration::from_secs(5),
+                    "API never came up"
+                );
+                thread::sleep(Duration::from_millis(50));
+            }
+            cluster
+        }
+
+        /// 向议长发送一个HTTP请求，返回状态码与响应体
+        fn http(&self, request: &str, body: &[u8]) -> Option<(u16, Vec<u8>)> {
+            http_to(&self.api, request, body)
+        }
+
+        /// 提交一条日志，等待议员投票、决议写入日志，返回其编号
+        fn commit(&self, entry: &[u8]) -> u64 {
+            let start = Instant::now();
+            loop {
+                match self.http(&submit("", entry.len()), entry) {
+                    Some((200, body)) => {
+                        let response: serde_json::Value = serde_json::from_slice(&body).unwrap();
+                        break response["id"].as_u64().unwrap();
+                    }
+                    _ if start.elapsed() > Duration::from_secs(10) => panic!("never committed"),
+                    _ => thread::sleep(Duration::from_millis(50)),
+                }
+            }
+        }
+
+        /// 停止所有节点，每个节点都应正常退出
+        fn stop(mut self) {
+            self.shutdown.trigger();
+            for node in self.nodes.drain(..) {
+                assert!(node.join().unwrap().is_ok());
+            }
+        }
+    }
+
+    impl Drop for VotingCluster {
+        fn drop(&mut self) {
+            // 测试失败时也停止节点
+            self.shutdown.trigger();
+        }
+    }
+
+    fn http_to(api: &str, request: &str, body: &[u8]) -> Option<(u16, Vec<u8>)> {
+        let mut stream = TcpStream::connect(api).ok()?;
+        stream.write_all(request.as_bytes()).ok()?;
+        stream.write_all(body).ok()?;
+
+        let mut response = Vec::new();
+        stream.read_to_end(&mut response).ok()?;
+        let head_end = response.windows(4).position(|w| w == b"\r\n\r\n")?;
+        let status = std::str::from_utf8(&response[9..12]).ok()?.parse().ok()?;
+        Some((status, response[head_end + 4..].to_vec()))
+    }
+
+    /// 提交长为 `len` 的日志的请求头，`params` 为附加的查询参数
+    fn submit(params: &str, len: usize) -> String {
+        format!(
+            "POST /submit{} HTTP/1.1\r\nHost: somepox\r\nContent-Type: application/octet-stream\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+            params, len
+        )
+    }
+
+    /// 读取日志的请求头
+    fn query(id: u64, consistency: Option<&str>) -> String {
+        let consistency = consistency.map_or(String::new(), |c| format!("&consistency={}", c));
+        format!(
+            "GET /query?id={}{} HTTP/1.1\r\nHost: somepox\r\nConnection: close\r\n\r\n",
+            id, consistency
+        )
+    }
+
+    const ENTRY: [u8; 6] = [0x08, 0x96, 0x01, 0xff, b'|', 0x00];
+
+    #[test]
+    fn submitted_log_is_committed_test() {
+        let cluster = VotingCluster::start();
+        let id = cluster.commit(&ENTRY);
+
+        assert_eq!(
+            cluster.http(&query(id, None), &[]),
+            Some((200, ENTRY.to_vec()))
+        );
+        cluster.stop();
+    }
+
+    #[test]
+    fn query_consistency_test() {
+        let cluster = VotingCluster::start();
+        let id = cluster.commit(&ENTRY);
+
+        for consistency in ["linearizable", "lease", "stale"] {
+            let response = cluster.http(&query(id, Some(consistency)), &[]);
+            assert_eq!(response, Some((200, ENTRY.to_vec())), "{}", consistency);
+        }
+        let unknown = cluster.http(&query(id, Some("eventual")), &[]);
+        assert_eq!(unknown.map(|(status, _)| status), Some(400));
+        cluster.stop();
+    }
+
+    #[test]
+    fn session_resubmit_test() {
+        let cluster = VotingCluster::start();
+
+        // 同一会话中重复提交的日志只形成一次决议
+        let command = b"y = 10";
+        let resubmit = submit("?client=alice&seq=1", command.len());
+        let first = cluster.http(&resubmit, command).unwrap();
+        assert_eq!(first.0, 200);
+        assert_eq!(cluster.http(&resubmit, command).unwrap(), first);
+        let partial = cluster.http(&submit("?client=alice", ENTRY.len()), &ENTRY);
+        assert_eq!(partial.map(|(status, _)| status), Some(400));
+        cluster.stop();
+    }
+
+    #[test]
+    fn admin_membership_test() {
+        let cluster = VotingCluster::start();
+        let api = cluster.api.as_str();
+
+        // 经由管理接口加入、再移除一名议员
+        let address = format!("127.0.0.1:{}", free_port());
+        admin::add_node(api, "worker-3", &address).unwrap();
+        assert!(admin::members(api).unwrap().contains("worker-3"));
+        admin::remove_node(api, "worker-3").unwrap();
+        assert!(!admin::members(api).unwrap().contains("worker-3"));
+        assert!(admin::remove_node(api, "worker-3").is_err());
+        cluster.stop();
+    }
+
+    #[test]
+    fn status_reports_roles_test() {
+        let cluster = VotingCluster::start();
+
+        // 两名议员都在发送心跳
+        let start = Instant::now();
+        let status: serde_json::Value = loop {
+            let status: serde_json::Value =
+                serde_json::from_str(&admin::status(&cluster.api).unwrap()).unwrap();
+            if status["live"] == 2 || start.elapsed() > Duration::from_secs(10) {
+                break status;
+            }
+            thread::sleep(Duration::from_millis(50));
+        };
+        assert_eq!(status["live"], 2, "{}", status);
+        assert_eq!(status["senators"][1]["role"], "witness", "{}", status);
+        assert_eq!(status["learners"][0]["role"], "learner", "{}", status);
+        cluster.stop();
+    }
+
+    #[test]
+    fn learner_serves_stale_reads_test() {
+        let cluster = VotingCluster::start();
+        let id = cluster.commit(&ENTRY);
+
+        // 学习者复制了决议，只提供过期读
+        let start = Instant::now();
+        let learned = loop {
+            match http_to(&cluster.learner_api, &query(id, Some("stale")), &[]) {
+                Some((200, body)) => break body,
+                _ if start.elapsed() > Duration::from_secs(10) => panic!("never learned"),
+                _ => thread::sleep(Duration::from_millis(50)),
+            }
+        };
+        assert_eq!(learned, ENTRY);
+        let linearizable = http_to(&cluster.learner_api, &query(id, None), &[]);
+        assert_eq!(linearizable.map(|(status, _)| status), Some(503));
+        cluster.stop();
+    }
+
+    #[test]
+    fn lock_test() {
+        let cluster = VotingCluster::start();
+
+        // 锁的令牌为获取锁的议题编号，被持有时其他客户端无法获取
+        let lock = |action: &str, body: &str| {
+            let request = format!(
+                "POST /locks/db/{} HTTP/1.1\r\nHost: somepox\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+                action,
+                body.len()
+            );
+            cluster
+                .http(&request, body.as_bytes())
+                .map(|(status, body)| {
+                    let token = serde_json::from_slice::<serde_json::Value>(&body)
+                        .map_or(0, |json| json["token"].as_u64().unwrap_or(0));
+                    (status, token)
+                })
+        };
+        let (status, token) = lock("acquire", r#"{"owner": "alice"}"#).unwrap();
+        assert!(status == 200 && token > 0, "acquired with {}", token);
+        assert_eq!(
+            lock("acquire", r#"{"owner": "bob"}"#).map(|(status, _)| status),
+            Some(409)
+        );
+        let alice = format!(r#"{{"owner": "alice", "token": {}}}"#, token);
+        assert_eq!(lock("renew", &alice), Some((200, token)));
+        assert_eq!(lock("release", &alice), Some((200, token)));
+        assert_eq!(lock("release", &alice).map(|(status, _)| status), Some(409));
+        let (status, next) = lock("acquire", r#"{"owner": "bob", "ttl_ms": 500}"#).unwrap();
+        assert!(status == 200 && next > token);
+        cluster.stop();
+    }
+}
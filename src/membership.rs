@@ -0,0 +1,183 @@
+//! ### Membership
+//! The senators of the cluster, changed through the log itself.
+//!
+//! A change is proposed and decided like any other entry, as an entry of
+//! kind `EntryKind::Membership` so that it can be told apart from client
+//! commands. A change decided at slot `i` only takes effect from slot
+//! `i + ALPHA` on, and the master never has more than `ALPHA` slots
+//! undecided at once: every slot is then counted against a membership which
+//! was already known when it was proposed. With several masters the window
+//! counts from the first slot not yet applied, see `mencius`.
+//!
+#![allow(unused)]
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    address_book::NodeId,
+    issue::{Entry, EntryKind},
+};
+
+/// 变更形成决议后，经过多少个编号才生效；也是同时表决中的议题数的上限
+pub const ALPHA: u64 = 64;
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub enum Change {
+    /// 加入新的议员
+    Add { id: NodeId, address: String },
+    /// 移除议员
+    Remove { id: NodeId },
+}
+
+impl Change {
+    /// 写入日志的条目
+    pub fn entry(&self) -> Result<Entry> {
+        let json = serde_json::to_vec(self)?;
+        Ok(Entry::new(EntryKind::Membership, json.into()))
+    }
+
+    /// 条目不是成员变更时返回 `None`
+    pub fn decode(entry: &Entry) -> Option<Change> {
+        (entry.kind == EntryKind::Membership)
+            .then(|| serde_json::from_slice(&entry.content).ok())
+            .flatten()
+    }
+}
+
+/// 初始的议员，以及日志中已形成决议的成员变更
+pub struct Membership {
+    initial: BTreeSet<NodeId>,
+    /// 形成决议的编号 -> 变更
+    changes: BTreeMap<u64, Change>,
+}
+
+impl Membership {
+    pub fn new(senators: impl IntoIterator<Item = NodeId>) -> Self {
+        Self {
+            initial: senators.into_iter().collect(),
+            changes: BTreeMap::new(),
+        }
+    }
+
+    /// 记录在 `slot` 形成决议的变更
+    pub fn record(&mut self, slot: u64, change: Change) {
+        self.changes.insert(slot, change);
+    }
+
+    /// 表决编号为 `slot` 的议题的议员
+    pub fn at(&self, slot: u64) -> BTreeSet<NodeId> {
+        self.replay(|decided| decided.saturating_add(ALPHA) <= slot)
+    }
+
+    /// 表决 `slot` 及之后的编号的各组议员，按生效的先后排列
+    pub fn configs_from(&self, slot: u64) -> Vec<BTreeSet<NodeId>> {
+        let mut configs = vec![self.at(slot)];
+        for decided in self.changes.keys() {
+            let effective = decided.saturating_add(ALPHA);
+            if effective > slot {
+                let config = self.at(effective);
+                if configs.last() != Some(&config) {
+                    configs.push(config);
+                }
+            }
+        }
+        configs
+    }
+
+    /// 所有已形成决议的变更生效后的议员
+    pub fn latest(&self) -> BTreeSet<NodeId> {
+        self.replay(|_| true)
+    }
+
+    /// 检查变更对最新的议员是否有意义
+    pub fn check(&self, change: &Change) -> Result<()> {
+        let latest = self.latest();
+        match change {
+            Change::Add { id, .. } if latest.contains(id) => {
+                Err(anyhow!("`{}` is already a senator", id))
+            }
+            Change::Remove { id } if !latest.contains(id) => {
+                Err(anyhow!("`{}` is not a senator", id))
+            }
+            Change::Remove { .. } if latest.len() == 1 => {
+                Err(anyhow!("can not remove the last senator"))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// 按编号顺序应用满足 `decided` 的变更
+    fn replay<F>(&self, mut decided: F) -> BTreeSet<NodeId>
+    where
+        F: FnMut(u64) -> bool,
+    {
+        let mut senators = self.initial.clone();
+        for (slot, change) in self.changes.iter() {
+            if !decided(*slot) {
+                continue;
+            }
+            match change {
+                Change::Add { id, .. } => senators.insert(id.clone()),
+                Change::Remove { id } => senators.remove(id),
+            };
+        }
+        senators
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::Bytes;
+
+    use super::{Change, Membership, ALPHA};
+    use crate::issue::Entry;
+
+    fn ids(ids: &[&str]) -> Vec<String> {
+        ids.iter().map(|id| id.to_string()).collect()
+    }
+
+    #[test]
+    fn change_round_trip_test() {
+        let change = Change::Add {
+            id: "worker-4".to_string(),
+            address: "127.0.0.1:18004".to_string(),
+        };
+        let entry = change.entry().unwrap();
+        assert_eq!(Change::decode(&entry), Some(change));
+        // 内容相同的客户端日志不是成员变更
+        assert_eq!(Change::decode(&Entry::client(entry.content)), None);
+        assert_eq!(Change::decode(&Entry::client(Bytes::from("x = 1"))), None);
+    }
+
+    #[test]
+    fn changes_take_effect_after_alpha_test() {
+        let mut membership = Membership::new(ids(&["worker-1", "worker-2"]));
+        let add = Change::Add {
+            id: "worker-3".to_string(),
+            address: String::new(),
+        };
+        let remove = Change::Remove {
+            id: "worker-1".to_string(),
+        };
+
+        // 变更可能不按编号顺序形成决议
+        membership.record(12, remove.clone());
+        membership.record(10, add.clone());
+
+        let at = |slot| membership.at(slot).into_iter().collect::<Vec<_>>();
+        assert_eq!(at(10 + ALPHA - 1), ids(&["worker-1", "worker-2"]));
+        assert_eq!(at(10 + ALPHA), ids(&["worker-1", "worker-2", "worker-3"]));
+        assert_eq!(at(12 + ALPHA), ids(&["worker-2", "worker-3"]));
+        assert_eq!(membership.configs_from(11 + ALPHA).len(), 2);
+        assert_eq!(membership.configs_from(12 + ALPHA).len(), 1);
+
+        assert!(membership.check(&add).is_err());
+        assert!(membership.check(&remove).is_err());
+        let remove = |id: &str| Change::Remove { id: id.to_string() };
+        membership.record(13, remove("worker-2"));
+        assert!(membership.check(&remove("worker-3")).is_err(), "last one");
+    }
+}
@@ -0,0 +1,100 @@
+//! ### Mencius
+//! Leaderless multi-master mode, slots are owned in turn by every master.
+//!
+//! Slot `i` belongs to the `(i - 1) % n`-th of the `n` masters sorted by id,
+//! and its owner proposes in it at round 0 without a phase 1, so masters
+//! never compete for a slot and each writer talks to the master next to it.
+//! A decision is sent to the other masters right away. Learning that a peer
+//! decided slot `k`, a master *skips* every slot of its own below `k` it has
+//! not used, deciding a no-op entry (`EntryKind::Noop`) there without a
+//! round of votes: the owner never proposed anything else in those slots.
+//! Every master then applies the whole log in slot order.
+//!
+//! When the log stays stuck at a slot of another master for a while, that
+//! master is taken for down and its missing slots are *revoked*: a peer runs
+//! phase 1 on each of them at a higher round and proposes the value it
+//! recovers, or a no-op. The owner itself revokes a slot of its own whose
+//! proposal was rejected, and, after a restart, every slot of its own it may
+//! have proposed in before.
+//!
+//! A master only proposes slots within `window` of the first slot it has not
+//! applied, so a membership change, whichever master proposed it, is applied
+//! by every master before any slot it governs is proposed.
+//!
+//! A linearizable read skips a fresh slot of the reading master and waits
+//! until the log is applied up to it. Any append already reported applied
+//! to a client sits below that slot, since its slot could only be applied
+//! after every earlier slot of the reading master was decided.
+//!
+#![allow(unused)]
+
+use serde::Deserialize;
+
+use crate::address_book::NodeId;
+
+/// 议长之间分配议题编号的方式
+#[derive(Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Protocol {
+    /// 由一名议长提议所有的编号
+    #[default]
+    Leader,
+    /// 所有议长轮流拥有编号，各自提议
+    Mencius,
+}
+
+/// 议题编号的所有者：编号按议长的编号顺序轮流分配，至少有一名议长
+#[derive(Clone, Debug)]
+pub struct Owners {
+    masters: Vec<NodeId>,
+}
+
+impl Owners {
+    pub fn new(masters: impl IntoIterator<Item = NodeId>) -> Self {
+        let mut masters: Vec<NodeId> = masters.into_iter().collect();
+        masters.sort();
+        masters.dedup();
+        assert!(!masters.is_empty(), "no master owns any slot");
+        Self { masters }
+    }
+
+    pub fn masters(&self) -> &[NodeId] {
+        &self.masters
+    }
+
+    /// 编号 `slot` 的所有者，编号从1开始
+    pub fn owner(&self, slot: u64) -> &NodeId {
+        let n = self.masters.len() as u64;
+        &self.masters[(slot.saturating_sub(1) % n) as usize]
+    }
+
+    /// `master` 拥有的、大于 `after` 的第一个编号；不是议长时返回 `None`
+    pub fn next(&self, master: &str, after: u64) -> Option<u64> {
+        let index = self.masters.iter().position(|id| id == master)? as u64;
+        let n = self.masters.len() as u64;
+        // `master` 拥有 index + 1 + k * n
+        let first = index + 1;
+        if after < first {
+            return Some(first);
+        }
+        Some(first + ((after - first) / n + 1) * n)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Owners;
+
+    #[test]
+    fn slots_are_owned_in_turn_test() {
+        let owners = Owners::new(["m3", "m1", "m2", "m1"].map(String::from));
+        assert_eq!(owners.masters(), ["m1", "m2", "m3"]);
+        let slots: Vec<&str> = (1..=6).map(|slot| owners.owner(slot).as_str()).collect();
+        assert_eq!(slots, ["m1", "m2", "m3", "m1", "m2", "m3"]);
+
+        assert_eq!(owners.next("m2", 0), Some(2));
+        assert_eq!(owners.next("m2", 2), Some(5));
+        assert_eq!(owners.next("m2", 4), Some(5));
+        assert_eq!(owners.next("m1", 4), Some(7));
+        assert_eq!(owners.next("m9", 0), None);
+    }
+}
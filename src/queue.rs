@@ -0,0 +1,207 @@
+//! ### Bounded Queue
+//! A thread-safe FIFO with a fixed capacity, and a policy deciding what
+//! happens to an item pushed while the queue is full.
+//!
+#![allow(unused)]
+
+use std::{
+    collections::VecDeque,
+    sync::{Condvar, Mutex},
+};
+
+use anyhow::{anyhow, Result};
+use serde::Deserialize;
+
+#[derive(Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Overflow {
+    /// 等待队列腾出空间，只应在生产者与消费者处于不同线程时使用；节点的
+    /// 邮箱在同一个线程中收发，不能使用
+    Block,
+    /// 丢弃队列中最早的元素
+    DropOldest,
+    /// 拒绝新的元素
+    #[default]
+    Reject,
+}
+
+/// 队列的深度统计
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct QueueStats {
+    pub depth: usize,
+    pub capacity: usize,
+    /// 出现过的最大深度
+    pub high_watermark: usize,
+    /// 因 `DropOldest` 被丢弃的元素数
+    pub dropped: u64,
+    /// 因 `Reject` 被拒绝的元素数
+    pub rejected: u64,
+}
+
+pub struct BoundedQueue<T> {
+    items: Mutex<VecDeque<T>>,
+    not_full: Condvar,
+    capacity: usize,
+    overflow: Overflow,
+    stats: Mutex<QueueStats>,
+}
+
+impl<T> BoundedQueue<T> {
+    /// `capacity` 至少为1
+    pub fn new(capacity: usize, overflow: Overflow) -> Self {
+        let capacity = capacity.max(1);
+        Self {
+            items: Mutex::new(VecDeque::with_capacity(capacity)),
+            not_full: Condvar::new(),
+            capacity,
+            overflow,
+            stats: Mutex::new(QueueStats {
+                capacity,
+                ..QueueStats::default()
+            }),
+        }
+    }
+
+    pub fn overflow(&self) -> Overflow {
+        self.overflow
+    }
+
+    /// 放入队尾，队列已满时按 `Overflow` 处理
+    pub fn push(&self, item: T) -> Result<()> {
+        let mut items = self.items.lock().map_err(|_| anyhow!("queue poisoned"))?;
+        let mut stats = self.stats.lock().map_err(|_| anyhow!("queue poisoned"))?;
+
+        if items.len() >= self.capacity {
+            match self.overflow {
+                Overflow::Block => {
+                    // 等待期间释放统计的锁，消费者出队时需要更新它
+                    drop(stats);
+                    items = self
+                        .not_full
+                        .wait_while(items, |items| items.len() >= self.capacity)
+                        .map_err(|_| anyhow!("queue poisoned"))?;
+                    stats = self.stats.lock().map_err(|_| anyhow!("queue poisoned"))?;
+                }
+                Overflow::DropOldest => {
+                    items.pop_front();
+                    stats.dropped += 1;
+                }
+                Overflow::Reject => {
+                    stats.rejected += 1;
+                    return Err(anyhow!("queue is full ({} items)", self.capacity));
+                }
+            }
+        }
+
+        items.push_back(item);
+        stats.depth = items.len();
+        stats.high_watermark = stats.high_watermark.max(items.len());
+        Ok(())
+    }
+
+    /// 取出队首
+    pub fn pop(&self) -> Option<T> {
+        let mut items = self.items.lock().ok()?;
+        let item = items.pop_front();
+        if item.is_some() {
+            if let Ok(mut stats) = self.stats.lock() {
+                stats.depth = items.len();
+            }
+            self.not_full.notify_one();
+        }
+        item
+    }
+
+    /// 取出第一个满足 `predicate` 的元素，其余元素保持原有顺序
+    pub fn take<P>(&self, mut predicate: P) -> Option<T>
+    where
+        P: FnMut(&T) -> bool,
+    {
+        let mut items = self.items.lock().ok()?;
+        let position = items.iter().position(&mut predicate)?;
+        let item = items.remove(position);
+        if let Ok(mut stats) = self.stats.lock() {
+            stats.depth = items.len();
+        }
+        self.not_full.notify_one();
+        item
+    }
+
+    pub fn len(&self) -> usize {
+        self.items.lock().map(|items| items.len()).unwrap_or(0)
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.len() >= self.capacity
+    }
+
+    pub fn stats(&self) -> QueueStats {
+        self.stats
+            .lock()
+            .map(|stats| stats.clone())
+            .unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{sync::Arc, thread, time::Duration};
+
+    use super::{BoundedQueue, Overflow};
+
+    #[test]
+    fn overflow_policies_test() {
+        let queue = BoundedQueue::new(2, Overflow::Reject);
+        queue.push(1).unwrap();
+        queue.push(2).unwrap();
+        assert!(queue.push(3).is_err());
+        assert_eq!(queue.pop(), Some(1));
+        assert_eq!(queue.stats().rejected, 1);
+
+        let queue = BoundedQueue::new(2, Overflow::DropOldest);
+        for i in 1..=3 {
+            queue.push(i).unwrap();
+        }
+        assert_eq!(
+            (queue.pop(), queue.pop(), queue.pop()),
+            (Some(2), Some(3), None)
+        );
+
+        let stats = queue.stats();
+        assert_eq!(
+            (stats.depth, stats.high_watermark, stats.dropped),
+            (0, 2, 1)
+        );
+    }
+
+    #[test]
+    fn take_keeps_order_test() {
+        let queue = BoundedQueue::new(4, Overflow::Reject);
+        for i in 1..=4 {
+            queue.push(i).unwrap();
+        }
+        assert_eq!(queue.take(|i| i % 2 == 0), Some(2));
+        assert_eq!(queue.take(|i| *i > 4), None);
+        assert_eq!(queue.stats().depth, 3);
+        assert_eq!(
+            (queue.pop(), queue.pop(), queue.pop()),
+            (Some(1), Some(3), Some(4))
+        );
+    }
+
+    #[test]
+    fn block_until_consumed_test() {
+        let queue = Arc::new(BoundedQueue::new(1, Overflow::Block));
+        queue.push(1).unwrap();
+
+        let producer = {
+            let queue = queue.clone();
+            thread::spawn(move || queue.push(2))
+        };
+        thread::sleep(Duration::from_millis(50));
+        assert_eq!(queue.len(), 1, "the producer waits for room");
+
+        assert_eq!(queue.pop(), Some(1));
+        producer.join().unwrap().unwrap();
+        assert_eq!(queue.pop(), Some(2));
+    }
+}
@@ -0,0 +1,238 @@
+//! ### Quorum
+//! Decide when enough acceptors agree on a proposal.
+//!
+//! Votes are tallied per (ballot, slot) as a set of acceptor ids, so a vote
+//! which is retransmitted or replayed is only ever counted once. Acceptors
+//! may carry different weights, and the quorums of the two phases may differ
+//! as long as they intersect (Flexible Paxos).
+//!
+#![allow(unused)]
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use anyhow::{anyhow, Result};
+use serde::Deserialize;
+
+use crate::{address_book::NodeId, issue::Entry};
+
+/// 法定人数的大小，以票数（议员的权重之和）计
+#[derive(Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Quorum {
+    /// 过半数的票
+    #[default]
+    Majority,
+    /// 固定的票数，至少为1
+    Size(u64),
+}
+
+impl Quorum {
+    /// 共有 `total` 票时，达到法定人数所需的票数
+    pub fn size(&self, total: u64) -> u64 {
+        match self {
+            Quorum::Majority => total / 2 + 1,
+            Quorum::Size(size) => (*size).max(1),
+        }
+    }
+}
+
+/// 两个阶段的法定人数，以及议员的权重 (Flexible Paxos)
+///
+/// 第一阶段用于确认领导权，第二阶段用于表决议题。两个阶段的法定人数之和
+/// 超过总票数时，任意两个不同阶段的法定人数必有交集，见 `validate`。
+#[derive(Deserialize, Clone, Debug, Default, PartialEq, Eq)]
+pub struct Quorums {
+    #[serde(default)]
+    pub phase1: Quorum,
+    #[serde(default)]
+    pub phase2: Quorum,
+    /// 议员的权重，未列出的议员为1
+    #[serde(default)]
+    pub weights: BTreeMap<NodeId, u64>,
+}
+
+impl Quorums {
+    pub fn weight(&self, senator: &str) -> u64 {
+        self.weights.get(senator).copied().unwrap_or(1)
+    }
+
+    /// `voters` 中属于 `senators` 的议员的票数
+    pub fn votes<'a>(
+        &self,
+        senators: &BTreeSet<NodeId>,
+        voters: impl IntoIterator<Item = &'a NodeId>,
+    ) -> u64 {
+        voters
+            .into_iter()
+            .filter(|voter| senators.contains(*voter))
+            .map(|voter| self.weight(voter))
+            .sum()
+    }
+
+    /// `voters` 是否构成 `senators` 的第一阶段法定人数
+    pub fn phase1_reached<'a>(
+        &self,
+        senators: &BTreeSet<NodeId>,
+        voters: impl IntoIterator<Item = &'a NodeId>,
+    ) -> bool {
+        let total = self.votes(senators, senators);
+        self.votes(senators, voters) >= self.phase1.size(total)
+    }
+
+    /// `voters` 是否构成 `senators` 的第二阶段法定人数
+    pub fn phase2_reached<'a>(
+        &self,
+        senators: &BTreeSet<NodeId>,
+        voters: impl IntoIterator<Item = &'a NodeId>,
+    ) -> bool {
+        let total = self.votes(senators, senators);
+        self.votes(senators, voters) >= self.phase2.size(total)
+    }
+
+    /// 检查每个设置了权重的节点都是议员，以免配置中写错了编号
+    pub fn validate_weights(&self, senators: &BTreeSet<NodeId>) -> Result<()> {
+        match self.weights.keys().find(|id| !senators.contains(*id)) {
+            Some(unknown) => Err(anyhow!("weight of `{}`, who is not a senator", unknown)),
+            None => Ok(()),
+        }
+    }
+
+    /// 检查对 `senators` 而言两个阶段的法定人数都能达到，并且必有交集
+    pub fn validate(&self, senators: &BTreeSet<NodeId>) -> Result<()> {
+        let total = self.votes(senators, senators);
+        let (q1, q2) = (self.phase1.size(total), self.phase2.size(total));
+        if q1.max(q2) > total {
+            return Err(anyhow!(
+                "quorums of {} and {} votes can not be reached with {} votes",
+                q1,
+                q2,
+                total
+            ));
+        }
+        if q1 + q2 <= total {
+            return Err(anyhow!(
+                "quorums of {} and {} votes do not intersect with {} votes",
+                q1,
+                q2,
+                total
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// 一个议题在某一轮表决中的计票
+pub struct Tally {
+    entry: Entry,
+    voters: BTreeSet<NodeId>,
+}
+
+impl Tally {
+    pub fn new(entry: Entry) -> Self {
+        Self {
+            entry,
+            voters: BTreeSet::new(),
+        }
+    }
+
+    pub fn entry(&self) -> Entry {
+        self.entry.clone()
+    }
+
+    /// 记录 `voter` 对 `entry` 的赞成票，返回目前不同的赞成者的数量
+    ///
+    /// 同一议员的重复投票不重复计数；种类或内容不一致的投票返回错误
+    pub fn vote(&mut self, voter: &str, entry: &Entry) -> Result<usize> {
+        if *entry != self.entry {
+            return Err(anyhow!("`{}` voted for another content", voter));
+        }
+        if !self.voters.insert(voter.to_string()) {
+            return Err(anyhow!("duplicate vote from `{}`", voter));
+        }
+        Ok(self.voters.len())
+    }
+
+    pub fn voters(&self) -> &BTreeSet<NodeId> {
+        &self.voters
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::{BTreeMap, BTreeSet};
+
+    use bytes::Bytes;
+
+    use super::{Quorum, Quorums, Tally};
+    use crate::issue::{Entry, EntryKind};
+
+    fn senators(ids: &[&str]) -> BTreeSet<String> {
+        ids.iter().map(|id| id.to_string()).collect()
+    }
+
+    #[test]
+    fn quorum_size_test() {
+        let sizes: Vec<u64> = (1..=5).map(|n| Quorum::Majority.size(n)).collect();
+        assert_eq!(sizes, vec![1, 2, 2, 3, 3]);
+        assert_eq!(Quorum::Size(2).size(5), 2);
+        assert_eq!(Quorum::Size(0).size(5), 1);
+    }
+
+    #[test]
+    fn weighted_quorums_test() {
+        let all = senators(&["dc1-a", "dc1-b", "dc2-a", "dc2-b", "dc2-c"]);
+        let quorums = Quorums {
+            phase1: Quorum::Size(5),
+            phase2: Quorum::Size(3),
+            weights: [("dc1-a".to_string(), 2), ("dc1-b".to_string(), 2)].into(),
+        };
+        quorums.validate(&all).unwrap();
+
+        // 第一个数据中心即可完成表决，确认领导权则需要跨数据中心
+        let dc1 = senators(&["dc1-a", "dc1-b"]);
+        assert!(quorums.phase2_reached(&all, &dc1));
+        assert!(!quorums.phase1_reached(&all, &dc1));
+        assert!(quorums.phase1_reached(&all, &senators(&["dc1-a", "dc2-a", "dc2-b", "dc2-c"])));
+        // 不是议员的投票不计入
+        assert!(!quorums.phase2_reached(&all, &senators(&["dc1-a", "stranger"])));
+    }
+
+    #[test]
+    fn validate_quorums_test() {
+        let all = senators(&["w1", "w2", "w3", "w4"]);
+        assert!(Quorums::default().validate(&all).is_ok());
+
+        let flexible = |phase1, phase2| Quorums {
+            phase1: Quorum::Size(phase1),
+            phase2: Quorum::Size(phase2),
+            weights: BTreeMap::new(),
+        };
+        assert!(flexible(3, 2).validate(&all).is_ok());
+        assert!(flexible(2, 2).validate(&all).is_err(), "no intersection");
+        assert!(flexible(5, 1).validate(&all).is_err(), "unreachable");
+
+        let mut weighted = flexible(3, 2);
+        weighted.weights.insert("w1".to_string(), 2);
+        assert!(
+            weighted.validate(&all).is_err(),
+            "no intersection with 5 votes"
+        );
+        weighted.weights.insert("w5".to_string(), 2);
+        assert!(weighted.validate_weights(&all).is_err(), "unknown senator");
+    }
+
+    #[test]
+    fn tally_counts_voters_once_test() {
+        let entry = Entry::client(Bytes::from("x = 1"));
+        let mut tally = Tally::new(entry.clone());
+
+        assert_eq!(tally.vote("worker-1", &entry).unwrap(), 1);
+        assert!(tally.vote("worker-1", &entry).is_err());
+        assert!(tally
+            .vote("worker-2", &Entry::client(Bytes::from("x = 2")))
+            .is_err());
+        let other_kind = Entry::new(EntryKind::Session, entry.content.clone());
+        assert!(tally.vote("worker-2", &other_kind).is_err());
+        assert_eq!(tally.vote("worker-2", &entry).unwrap(), 2);
+        assert_eq!(tally.voters().len(), 2);
+    }
+}
@@ -0,0 +1,108 @@
+//! ### Read
+//! Serve reads from the master's log without returning stale values.
+//!
+//! The leader reports a slot committed only once it applied it, and any slot
+//! an earlier leader committed is recovered in phase 1, at most at the last
+//! slot recovered. A read therefore waits until the leader applied its log up
+//! to that slot, after checking that it is still the leader, i.e. no other
+//! master got a higher ballot promised by a quorum of senators:
+//!
+//! - a *linearizable* read confirms this with a round of `Confirm` messages
+//!   sent after the read arrived (read-index), at the ballot the master
+//!   prepared with. Ballots are unique per master and strictly increase
+//!   across its restarts, so a senator replying with the same ballot promised
+//!   nothing higher to anyone; once a phase-1 quorum does, any quorum of
+//!   another master has to intersect it;
+//! - a *lease* read skips the round while the master holds a lease. Every
+//!   senator confirming a round grants the master a lease of
+//!   `LEASE_DURATION` from when it received the round, and refuses other
+//!   masters until then; the master counts its lease from when it sent the
+//!   round, which is never later. This relies on clocks running at the same
+//!   rate. A restarted senator does not remember the leases it granted, so it
+//!   refuses to prepare or confirm for any master for `LEASE_DURATION` first;
+//! - a *stale* read just reads the local log.
+//!
+#![allow(unused)]
+
+use std::time::Duration;
+
+use serde::Deserialize;
+
+use crate::address_book::NodeId;
+
+/// 议员授予议长的租约的时长
+pub const LEASE_DURATION: Duration = Duration::from_secs(1);
+
+/// 读请求的一致性，见 `?consistency=`
+#[derive(Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Consistency {
+    /// 确认领导权后再读取（默认）
+    #[default]
+    Linearizable,
+    /// 租约有效时直接读取，否则先确认领导权
+    Lease,
+    /// 直接读取本地的日志，可能读到过期的内容
+    Stale,
+}
+
+/// 议员授予议长的租约：到期前只接受持有者的提议与确认
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Lease {
+    pub holder: NodeId,
+    pub expires: Duration,
+}
+
+impl Lease {
+    /// 在 `now` 收到 `holder` 的确认时授予的租约
+    pub fn grant(holder: &str, now: Duration) -> Self {
+        Self {
+            holder: holder.to_string(),
+            expires: now + LEASE_DURATION,
+        }
+    }
+
+    /// 重启后不知道之前授予过谁租约，`LEASE_DURATION` 内拒绝所有议长
+    pub fn after_restart(now: Duration) -> Self {
+        Self {
+            holder: NodeId::new(),
+            expires: now + LEASE_DURATION,
+        }
+    }
+
+    /// 在 `now` 是否应拒绝 `sender`
+    pub fn excludes(&self, sender: &str, now: Duration) -> bool {
+        sender != self.holder && now < self.expires
+    }
+
+    /// 在 `now` 是否应拒绝 `sender` 的提案：重启后的租约不拒绝，
+    /// 提案仍受承诺过的轮次约束，其他议长无法开始新的轮次即可
+    pub fn excludes_proposal(&self, sender: &str, now: Duration) -> bool {
+        !self.holder.is_empty() && self.excludes(sender, now)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::{Consistency, Lease, LEASE_DURATION};
+
+    #[test]
+    fn lease_excludes_others_until_expired_test() {
+        let lease = Lease::grant("master", Duration::from_secs(10));
+        assert!(!lease.excludes("master", Duration::from_secs(10)));
+        assert!(lease.excludes("master-2", Duration::from_secs(10)));
+        assert!(!lease.excludes("master-2", Duration::from_secs(10) + LEASE_DURATION));
+
+        let restarted = Lease::after_restart(Duration::from_secs(10));
+        assert!(restarted.excludes("master", Duration::from_secs(10)));
+        assert!(!restarted.excludes_proposal("master", Duration::from_secs(10)));
+        assert!(!restarted.excludes("master", Duration::from_secs(10) + LEASE_DURATION));
+        assert!(lease.excludes_proposal("master-2", Duration::from_secs(10)));
+
+        let parse = |value: &str| serde_json::from_str::<Consistency>(value).ok();
+        assert_eq!(parse(r#""lease""#), Some(Consistency::Lease));
+        assert_eq!(parse(r#""Lease""#), None);
+    }
+}
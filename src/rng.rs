@@ -0,0 +1,39 @@
+//! ### Seeded Random Number Generator
+//! A SplitMix64 generator: tiny, fast, and stable across platforms and
+//! releases, so a seed always reproduces the same simulation. Also used
+//! for the randomized backoff of proposers.
+//!
+#![allow(unused)]
+
+pub struct Rng {
+    state: u64,
+}
+
+impl Rng {
+    pub fn new(seed: u64) -> Self {
+        Self { state: seed }
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    /// `0..bound` 中的一个数，`bound` 不能为0
+    pub fn below(&mut self, bound: u64) -> u64 {
+        self.next_u64() % bound
+    }
+
+    /// 在 `0..len` 中选取一个下标，`len` 为0时返回 `None`
+    pub fn pick(&mut self, len: usize) -> Option<usize> {
+        (len > 0).then(|| self.below(len as u64) as usize)
+    }
+
+    /// 以 `percent` / 100 的概率返回 `true`
+    pub fn chance(&mut self, percent: u64) -> bool {
+        self.below(100) < percent
+    }
+}
@@ -2,21 +2,124 @@
 
 use std::{
     cell::{Cell, RefCell},
-    collections::HashMap,
+    collections::{BTreeMap, BTreeSet, HashMap},
     sync::Arc,
+    time::{Duration, SystemTime, UNIX_EPOCH},
 };
 
 use anyhow::{anyhow, Result};
+use bytes::Bytes;
+use serde::{Deserialize, Serialize};
 
 use crate::{
-    connection::{Connection, Net},
-    issue::{Issue, IssueType},
+    acceptor::{Acceptor, Prepare, Promise},
+    address_book::{NodeId, Recipient},
+    detector::PeerStatus,
+    issue::{Ballot, Entry, EntryKind, Issue, IssueType},
+    lock::{LockCommand, LockOp, Locks},
     logbackend::{LogBackend, Queryable, Writable},
     mailbox::{Mail, MailBox},
+    membership::{Change, Membership, ALPHA},
+    mencius::{Owners, Protocol},
+    quorum::{Quorums, Tally},
+    read::{Consistency, Lease, LEASE_DURATION},
+    rng::Rng,
+    session::{Command, Sessions},
 };
 
 type Address = String;
-type AddressBook = HashMap<String, Vec<String>>;
+
+/// 所有议员(Worker)所在的组
+pub const WORKER_GROUP: &str = "worker";
+/// 议长(Master)所在的组
+pub const MASTER_GROUP: &str = "master";
+/// 学习者(Learner)所在的组
+pub const LEARNER_GROUP: &str = "learner";
+
+/// 提案默认最长的表决时间，超时后报告失败
+pub const PROPOSAL_TIMEOUT: Duration = Duration::from_secs(3);
+/// 一轮表决等待投票的时间，超时后以更高的轮次重新提议
+const ROUND_TIMEOUT: Duration = Duration::from_millis(500);
+/// 提案被拒绝后，重新提议前随机等待时间的上限：首次为 `BACKOFF_INITIAL`，
+/// 之后每次翻倍，最长为 `BACKOFF_MAX`
+const BACKOFF_INITIAL: Duration = Duration::from_millis(20);
+const BACKOFF_MAX: Duration = Duration::from_secs(1);
+/// Mencius 模式下日志停在其他议长的编号上超过此时间，就收回之后缺少决议的编号
+const REVOKE_TIMEOUT: Duration = Duration::from_secs(1);
+/// 学习者向议长请求缺少的决议的间隔，以及议长一次补发的编号数
+const SYNC_INTERVAL: Duration = Duration::from_millis(200);
+const SYNC_BATCH: u64 = 32;
+/// 尚未形成决议的议题（包括等待提议的议题）的数量上限
+const MAX_PENDING: usize = 4096;
+/// 议长的日志中保存 `Saved` 的位置，议题的编号从1开始
+const ROUND_SLOT: u64 = 0;
+
+/// Master 眼中的集群状态
+#[derive(Serialize, Clone, Debug)]
+pub struct ClusterStatus {
+    pub id: NodeId,
+    /// 使用过或从议员处得知的最高轮次
+    pub ballot: Ballot,
+    /// 在线的议员的数量
+    pub live: usize,
+    pub senators: Vec<NodeStatus>,
+    pub learners: Vec<NodeStatus>,
+}
+
+/// 节点在集群中的角色
+#[derive(Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum NodeRole {
+    /// 参与表决的议员
+    Acceptor,
+    /// 参与表决、但只保存轮次的议员
+    Witness,
+    /// 不参与表决，只复制决议
+    Learner,
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct NodeStatus {
+    pub role: NodeRole,
+    #[serde(flatten)]
+    pub peer: PeerStatus,
+}
+
+/// 一批决议：发送给学习者时，`from..=to` 的编号中除 `entries` 外都没有决议；
+/// 议长之间只看 `entries`
+#[derive(Serialize, Deserialize)]
+struct Resolutions {
+    from: u64,
+    to: u64,
+    entries: Vec<(u64, Entry)>,
+}
+
+/// 议长保存在日志中、重启后仍需要的状态
+#[derive(Serialize, Deserialize, Default)]
+struct Saved {
+    /// 使用过的最高轮数
+    round: u64,
+    /// Mencius 模式下以最低的轮次提议过的自己的最大编号
+    proposed: u64,
+}
+
+/// 日志中编号为 `id` 的客户端提交的内容：会话命令只返回命令的内容，
+/// 空操作、成员变更与锁命令没有客户端提交的内容
+fn read_entry(logbackend: &dyn LogBackend, id: u64) -> Result<Bytes> {
+    if id == ROUND_SLOT {
+        return Err(anyhow!("issue ids start from 1"));
+    }
+    let entry = Entry::decode(&logbackend.query(id)?)?;
+    match entry.kind {
+        EntryKind::Client => Ok(entry.content),
+        EntryKind::Session => Command::decode(&entry)
+            .map(|command| command.content)
+            .ok_or_else(|| anyhow!("issue {} holds a malformed command", id)),
+        EntryKind::Noop => Err(anyhow!("issue {} is skipped", id)),
+        EntryKind::Membership => Err(anyhow!("issue {} is a membership change", id)),
+        EntryKind::Lock => Err(anyhow!("issue {} is a lock command", id)),
+    }
+}
 
 /// Master 负责三个角色
 ///
@@ -26,6 +129,30 @@ type AddressBook = HashMap<String, Vec<String>>;
 /// 3. 将 *表决(Vote)* 结果收回，*唱票(Counting)*
 /// 4. 将投票结果交由 *书记(Secretary)* 记录在案形成最终 *决议(Resolution)*
 ///
+/// 编号连续的至多 `window` 个议题同时表决，各自达到法定人数时形成决议，
+/// 再按编号顺序应用；窗口之外的议题排队等待提议。
+///
+/// 提议之前，议长以新的轮次 *准备(Prepare)* 尚未应用的所有编号：第一阶段
+/// 法定人数的议员 *承诺(Promise)* 后，在每个编号提议承诺中轮次最高的提案，
+/// 没有提案的空缺提议空操作，之后才提议新的议题。轮数先写入日志的编号0，
+/// 重启后不会重复使用同一轮次。
+///
+/// 议员已承诺了更高的轮次而 *拒绝(Reject)* 时，随机等待一段时间后以更高的轮次
+/// 重新准备；一轮表决超时仍未通过时以同一轮次重新提议。超过提案的表决时间
+/// 仍未形成决议则报告失败，但仍继续提议，直到该编号形成决议。
+///
+/// 读取日志前按请求的一致性以完成了第一阶段的轮次 *确认(Confirm)* 自己仍是
+/// 领导者，并等待日志应用到完成第一阶段时恢复的最后一个编号，见 `read`。
+///
+/// 客户端会话中的命令只会应用一次，重复提交时返回原先的结果，见 `submit`。
+///
+/// 锁命令按编号顺序应用到锁表，未能生效的命令报告失败，见 `lock`。
+///
+/// *见证者(Witness)* 只收到议题的轮次，不收到内容；按顺序应用的决议推送给
+/// *学习者(Learner)*，学习者也可以请求补发。
+///
+/// `Protocol::Mencius` 下所有议长轮流拥有编号、各自提议，见 `mencius`。
+///
 /// # 提案者：
 /// 提交 *议案(Proposal)* 至 *议长(President)* ，由 *议长* 添加进待议列表
 ///
@@ -34,148 +161,2720 @@ type AddressBook = HashMap<String, Vec<String>>;
 ///
 ///
 pub struct Master {
-    address: Address,
-    address_book: AddressBook,
     mail_box: MailBox<String, Issue>,
-    vote_table: RefCell<HashMap<Issue, u8>>,
+    /// 表决中的议题：(轮次, 编号) -> 计票
+    vote_table: RefCell<HashMap<(Ballot, u64), Tally>>,
+    /// 尚未形成决议的提案：编号 -> 提案
+    proposals: RefCell<BTreeMap<u64, Proposal>>,
+    /// 已形成决议、等待之前的议题形成决议后再应用：编号 -> 内容
+    committed: RefCell<BTreeMap<u64, Entry>>,
+    /// 已按顺序应用到的编号
+    applied: Cell<u64>,
+    /// 同时表决的议题数的上限
+    window: u64,
+    /// 只保存轮次的议员
+    witnesses: BTreeSet<NodeId>,
+    /// 地址簿中 `LEARNER_GROUP` 组的学习者
+    learners: Vec<NodeId>,
+    /// Mencius 模式下编号的所有者，由一名议长提议所有编号时为 `None`
+    owners: Option<Owners>,
+    /// 等待日志应用到某个编号的读请求：(读请求, 编号, 期限)
+    barriers: RefCell<Vec<(u64, u64, Duration)>>,
+    /// Mencius 模式下等待收回的编号：编号 -> (开始收回的时间, 已收回的次数)
+    revoke_at: RefCell<BTreeMap<u64, (Duration, u32)>>,
+    /// Mencius 模式下进行中的收回：编号 -> (第一阶段, 已收回的次数)
+    revoking: RefCell<BTreeMap<u64, (Preparing, u32)>>,
+    /// 日志停在的第一个缺少决议的其他议长的编号，以及开始的时间
+    stalled: Cell<Option<(u64, Duration)>>,
+    /// 上次向其他议长请求补发的时间
+    synced_at: Cell<Option<Duration>>,
+    /// 已形成决议或失败的提案，等待 `take_outcomes` 取走
+    outcomes: RefCell<Vec<(u64, Result<()>)>>,
+    quorums: Quorums,
+    /// 议员，以及已形成决议的成员变更
+    membership: RefCell<Membership>,
+    /// 每个客户端最新的已形成决议的命令
+    sessions: RefCell<Sessions>,
+    /// 由日志中的锁命令得到的锁表
+    locks: RefCell<Locks>,
+    /// 创建时的时间，UNIX纪元起的毫秒数；加上邮箱的时钟即为锁命令的时间
+    epoch: u64,
+    /// 本议长使用的轮次
+    ballot: RefCell<Ballot>,
+    /// 使用过或从议员处得知的最高轮次
+    highest: RefCell<Ballot>,
+    /// 是否已以 `ballot` 完成第一阶段；Mencius 模式下议长直接提议自己的编号，总是为真
+    prepared: Cell<bool>,
+    /// 进行中的第一阶段
+    preparing: RefCell<Option<Preparing>>,
+    /// 被拒绝后再次准备的时间，以及连续被拒绝的次数
+    prepare_at: Cell<Duration>,
+    rejections: Cell<u32>,
+    /// 本议长的客户端提交、尚未报告结果的议题编号
+    clients: RefCell<BTreeSet<u64>>,
+    proposal_timeout: Duration,
+    rng: RefCell<Rng>,
     counter: Cell<u64>,
+    /// Mencius 模式下以最低的轮次提议过的自己的最大编号，提议前先写入日志
+    proposed: Cell<u64>,
+    /// 等待下一轮确认的读请求：(读请求, 期限)
+    reads: RefCell<Vec<(u64, Duration)>>,
+    /// 进行中的一轮领导权确认
+    round: RefCell<Option<Round>>,
+    /// 可以安全读取或失败的读请求，等待 `take_reads` 取走
+    read_outcomes: RefCell<Vec<(u64, Result<()>)>>,
+    /// 以当前轮次得到的租约到期的时间
+    lease_until: Cell<Duration>,
+    /// 完成第一阶段时恢复的最后一个编号，之前的议长确认过的议题都不在其后，
+    /// 读请求等待日志应用到这里
+    read_index: Cell<u64>,
+    read_counter: Cell<u64>,
+    round_counter: Cell<u64>,
     logbackend: Box<dyn LogBackend>,
 }
 
+/// 一个尚未形成决议的提案
+struct Proposal {
+    entry: Entry,
+    /// 当前表决的轮次
+    ballot: Ballot,
+    /// 以当前轮次提议的次数，为0时仍在排队
+    attempts: u32,
+    /// 到此时间仍未形成决议，就重新提议
+    retry_at: Duration,
+    /// 到此时间仍未形成决议，就报告失败
+    deadline: Duration,
+}
+
+/// 进行中的第一阶段
+struct Preparing {
+    ballot: Ballot,
+    /// 准备的第一个编号，之后的编号都被准备
+    from: u64,
+    /// 表决这些编号的各组议员，承诺的议员须达到每组的第一阶段法定人数
+    configs: Vec<BTreeSet<NodeId>>,
+    promised: BTreeSet<NodeId>,
+    /// 每个编号轮次最高的已接受提案：编号 -> (轮次, 条目)，只有见证者接受时不知道条目
+    recovered: BTreeMap<u64, (Ballot, Option<Entry>)>,
+    sent_at: Duration,
+}
+
+/// 一轮领导权的确认
+struct Round {
+    id: u64,
+    ballot: Ballot,
+    /// 确认的议员
+    senators: BTreeSet<NodeId>,
+    /// 已同意的议员
+    acks: BTreeSet<NodeId>,
+    sent_at: Duration,
+    /// 等待本轮确认的读请求：(读请求, 期限)
+    reads: Vec<(u64, Duration)>,
+}
+
 impl Master {
+    /// 议题编号接续日志中已有的决议，重启后不会重复使用已决议的编号，
+    /// 轮数也接续日志中保存的轮数
+    ///
+    /// 地址簿中 `WORKER_GROUP` 组的节点为初始的议员，再按编号顺序应用日志中
+    /// 第一个空缺之前的决议，依次应用其中的成员变更，并重建会话表与锁表；
+    /// 空缺之后的决议等待空缺形成决议后再应用；日志无法读取时返回错误
     pub fn new(
-        address: Address,
-        address_book: AddressBook,
+        mail_box: MailBox<Address, Issue>,
         log_backend: Box<dyn LogBackend>,
     ) -> Result<Self> {
-        Ok(Self {
-            address: address.clone(),
-            address_book: address_book,
-            mail_box: MailBox::new(Box::new(Net::new(address)?)),
+        let last_id = log_backend.last_id()?.unwrap_or(0);
+        // 无法读出保存的轮数时拒绝启动，以免重复使用用过的轮次
+        let saved: Saved = match log_backend.get(ROUND_SLOT)? {
+            Some(raw) => bincode::deserialize(&raw)?,
+            None => Saved::default(),
+        };
+        let ballot = Ballot::new(saved.round, mail_box.id());
+        let membership = Membership::new(mail_box.address_book().group(WORKER_GROUP));
+        let learners = mail_box.address_book().group(LEARNER_GROUP);
+        let since = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        let seed = since.as_nanos() as u64;
+        let master = Self {
+            mail_box,
             vote_table: RefCell::new(HashMap::new()),
-            counter: Cell::new(0),
+            proposals: RefCell::new(BTreeMap::new()),
+            committed: RefCell::new(BTreeMap::new()),
+            applied: Cell::new(0),
+            window: ALPHA,
+            witnesses: BTreeSet::new(),
+            learners,
+            owners: None,
+            barriers: RefCell::new(Vec::new()),
+            revoke_at: RefCell::new(BTreeMap::new()),
+            revoking: RefCell::new(BTreeMap::new()),
+            stalled: Cell::new(None),
+            synced_at: Cell::new(None),
+            outcomes: RefCell::new(Vec::new()),
+            quorums: Quorums::default(),
+            highest: RefCell::new(ballot.clone()),
+            ballot: RefCell::new(ballot),
+            prepared: Cell::new(false),
+            preparing: RefCell::new(None),
+            prepare_at: Cell::new(Duration::ZERO),
+            rejections: Cell::new(0),
+            clients: RefCell::new(BTreeSet::new()),
+            proposal_timeout: PROPOSAL_TIMEOUT,
+            rng: RefCell::new(Rng::new(seed)),
+            membership: RefCell::new(membership),
+            sessions: RefCell::new(Sessions::new()),
+            locks: RefCell::new(Locks::new()),
+            epoch: since.as_millis() as u64,
+            counter: Cell::new(last_id),
+            proposed: Cell::new(saved.proposed),
+            reads: RefCell::new(Vec::new()),
+            round: RefCell::new(None),
+            read_outcomes: RefCell::new(Vec::new()),
+            lease_until: Cell::new(Duration::ZERO),
+            read_index: Cell::new(0),
+            read_counter: Cell::new(0),
+            round_counter: Cell::new(0),
             logbackend: log_backend,
-        })
+        };
+        for id in 1..=last_id {
+            let Some(raw) = master.logbackend.get(id)? else {
+                continue;
+            };
+            let entry = Entry::decode(&raw)?;
+            if id == master.applied.get() + 1 {
+                let _ = master
+                    .deduplicate(&entry)
+                    .and_then(|_| master.learn(id, &entry));
+                master.applied.set(id);
+            } else {
+                master.committed.borrow_mut().insert(id, entry);
+            }
+        }
+        Ok(master)
+    }
+
+    /// 两个阶段的法定人数与议员的权重，默认均为过半数、权重均为1
+    pub fn with_quorums(mut self, quorums: Quorums) -> Self {
+        self.quorums = quorums;
+        self
+    }
+
+    /// 同时表决的议题数的上限，默认为 `ALPHA`，也不能超过 `ALPHA`
+    pub fn with_window(mut self, window: u64) -> Self {
+        self.window = window.clamp(1, ALPHA);
+        self
+    }
+
+    /// 只保存轮次的议员，默认没有
+    pub fn with_witnesses(mut self, witnesses: impl IntoIterator<Item = NodeId>) -> Self {
+        self.witnesses = witnesses.into_iter().collect();
+        self
+    }
+
+    /// 提案最长的表决时间，默认为 `PROPOSAL_TIMEOUT`
+    pub fn with_proposal_timeout(mut self, timeout: Duration) -> Self {
+        self.proposal_timeout = timeout;
+        self
+    }
+
+    /// 随机退避使用的种子，默认取自当前时间
+    pub fn with_seed(mut self, seed: u64) -> Self {
+        self.rng = RefCell::new(Rng::new(seed));
+        self
+    }
+
+    /// 起始的轮数，默认为日志中保存的轮数，没有时为0
+    pub fn with_ballot(self, round: u64) -> Self {
+        self.ballot.borrow_mut().round = round;
+        self.see(&self.ballot());
+        self
     }
 
-    /// 获取当前在线的议员的数量
+    pub fn mail_box(&self) -> &MailBox<Address, Issue> {
+        &self.mail_box
+    }
+
+    /// 获取当前在线的议员的数量，见 `FailureDetector`
     ///
-    /// **TODO**: 这里获取的是被配置的worker数量，而不是实时在线的数量
+    /// 只用于观察集群的状态，法定人数仍按表决该议题的全部议员计算
     fn senators(&self) -> usize {
-        self.address_book
-            .get("worker")
-            .map(|v| v.len())
-            .unwrap_or(0)
+        self.members()
+            .iter()
+            .filter(|senator| self.mail_box.is_alive(senator))
+            .count()
     }
 
-    /// 提议新的议题
-    pub fn emmit_new_proposal(&self, msg_content: String) -> Result<()> {
-        // 为新的议题生成编号
-        let issue_id = self.counter.get() + 1;
+    /// 集群的状态：所有成员变更生效后的议员与学习者，以及各自的角色与存活情况
+    pub fn status(&self) -> ClusterStatus {
+        let node = |id: &NodeId, role| NodeStatus {
+            role,
+            peer: self.mail_box.peer_status(id),
+        };
+        ClusterStatus {
+            id: self.mail_box.id(),
+            ballot: self.highest.borrow().clone(),
+            live: self.senators(),
+            senators: self
+                .members()
+                .iter()
+                .map(|senator| match self.witnesses.contains(senator) {
+                    true => node(senator, NodeRole::Witness),
+                    false => node(senator, NodeRole::Acceptor),
+                })
+                .collect(),
+            learners: self
+                .learners
+                .iter()
+                .map(|learner| node(learner, NodeRole::Learner))
+                .collect(),
+        }
+    }
 
-        // 生成议题
-        let issue = Issue::new(msg_content, issue_id, IssueType::Proposal);
+    /// 所有已形成决议的成员变更生效后的议员
+    pub fn members(&self) -> Vec<NodeId> {
+        self.membership.borrow().latest().into_iter().collect()
+    }
+
+    /// 议长之间分配编号的方式，默认为 `Protocol::Leader`
+    ///
+    /// Mencius 模式下，地址簿中 `MASTER_GROUP` 组的议长与自己轮流拥有编号，
+    /// 以最低的轮次直接提议自己的新编号，不经过第一阶段。重启前以最低的轮次
+    /// 提议过、仍没有决议的自己的编号都被收回，新的议题从这些编号之后开始
+    pub fn with_protocol(mut self, protocol: Protocol) -> Self {
+        if protocol == Protocol::Leader {
+            self.owners = None;
+            self.prepared.set(false);
+            return self;
+        }
+
+        let id = self.mail_box.id();
+        let mut masters = self.mail_box.address_book().group(MASTER_GROUP);
+        masters.push(id.clone());
+        let owners = Owners::new(masters);
+        let last = self.counter.get().max(self.proposed.get());
+        let now = self.mail_box.now();
+        for slot in self.applied.get() + 1..=last {
+            if owners.owner(slot) == &id && !self.committed.borrow().contains_key(&slot) {
+                self.revoke_at.borrow_mut().insert(slot, (now, 0));
+            }
+        }
+        self.counter.set(last);
+        self.owners = Some(owners);
+        self.prepared.set(self.next_ballot().is_ok());
+        self.advance();
+        self
+    }
+
+    /// 本议长当前使用的轮次
+    fn ballot(&self) -> Ballot {
+        self.ballot.borrow().clone()
+    }
 
-        // 在表决表中记录该议题
-        self.vote_table.borrow_mut().insert(issue.clone(), 0);
+    /// 记录见到的轮次
+    fn see(&self, ballot: &Ballot) {
+        let mut highest = self.highest.borrow_mut();
+        if *ballot > *highest {
+            *highest = ballot.clone();
+        }
+    }
+
+    /// 换用比见过的轮次都高的新轮次，轮数先写入日志，重启后不会重复使用
+    fn next_ballot(&self) -> Result<Ballot> {
+        let round = self.ballot.borrow().round.max(self.highest.borrow().round) + 1;
+        self.save(round, self.proposed.get())?;
+        let ballot = Ballot::new(round, self.mail_box.id());
+        *self.ballot.borrow_mut() = ballot.clone();
+        self.see(&ballot);
+        Ok(ballot)
+    }
+
+    fn save(&self, round: u64, proposed: u64) -> Result<()> {
+        let saved = Saved { round, proposed };
+        self.logbackend
+            .write(ROUND_SLOT, Bytes::from(bincode::serialize(&saved)?))
+    }
+
+    /// Mencius 模式下的其他议长
+    fn peers(&self) -> Vec<NodeId> {
+        let id = self.mail_box.id();
+        self.owners.as_ref().map_or(Vec::new(), |owners| {
+            owners
+                .masters()
+                .iter()
+                .filter(|master| **master != id)
+                .cloned()
+                .collect()
+        })
+    }
+
+    /// 新议题的编号：Mencius 模式下为自己拥有的下一个编号
+    fn next_slot(&self) -> u64 {
+        let counter = self.counter.get();
+        self.owners
+            .as_ref()
+            .and_then(|owners| owners.next(&self.mail_box.id(), counter))
+            .unwrap_or(counter + 1)
+    }
+
+    /// 提议成员变更，返回议题编号；变更形成决议 `ALPHA` 个编号后生效
+    ///
+    /// 变更后两个阶段的法定人数必须仍有交集
+    pub fn propose_change(&self, change: Change) -> Result<u64> {
+        let membership = self.membership.borrow();
+        membership.check(&change)?;
+        let mut senators = membership.latest();
+        match &change {
+            Change::Add { id, .. } => senators.insert(id.clone()),
+            Change::Remove { id } => senators.remove(id),
+        };
+        self.quorums.validate(&senators)?;
+        drop(membership);
+
+        self.emmit(change.entry()?)
+    }
+
+    /// 按在 `slot` 形成决议的条目更新议员、会话表或锁表，同一命令的重复决议不计入；
+    /// 锁命令未能生效时返回原因
+    fn learn(&self, slot: u64, entry: &Entry) -> Result<()> {
+        if let Some(change) = Change::decode(entry) {
+            self.apply(slot, change);
+        } else if let Some(command) = Command::decode(entry) {
+            self.sessions.borrow_mut().record(slot, &command);
+        } else if let Some(command) = LockCommand::decode(entry) {
+            self.locks.borrow_mut().apply(slot, &command)?;
+        }
+        Ok(())
+    }
+
+    /// 会话命令已在更早的编号形成决议时返回原因，该编号的决议不再应用：
+    /// 不同的议长可能各自提议了客户端重复提交的同一命令，每名议长按同样的顺序
+    /// 应用日志，跳过的决议都相同；日志中仍保留形成的决议
+    fn deduplicate(&self, entry: &Entry) -> Result<()> {
+        let Some(command) = Command::decode(entry) else {
+            return Ok(());
+        };
+        match self
+            .sessions
+            .borrow()
+            .lookup(&command.client, command.seq)?
+        {
+            None => Ok(()),
+            Some(decided) => Err(anyhow!(
+                "command {} of `{}` is already decided at issue {}",
+                command.seq,
+                command.client,
+                decided
+            )),
+        }
+    }
 
-        // 将议题准备下发至议员投票
-        // TODO: 错误处理，这里发生错误会被丢弃
-        self.address_book.get("worker").map(|addr| {
-            let worker_address = addr.join(",");
-            let mail = Mail::new(self.address.clone(), vec![worker_address], issue);
-            self.mail_box.put_mail(mail);
+    /// 应用在 `slot` 形成决议的成员变更：新议员的地址加入地址簿，
+    /// 被移除的议员离开 `WORKER_GROUP` 组，但保留地址
+    ///
+    /// 领导者模式下，之前的第一阶段没有准备变更生效后的议员，须重新准备，
+    /// 之前的租约也不再有效
+    fn apply(&self, slot: u64, change: Change) {
+        if self.owners.is_none() {
+            self.prepared.set(false);
+            *self.preparing.borrow_mut() = None;
+            self.lease_until.set(Duration::ZERO);
+        }
+        let _ = self.mail_box.update_address_book(|book| match &change {
+            Change::Add { id, address } => {
+                book.insert(id.clone(), address.clone());
+                book.join(WORKER_GROUP, id.clone());
+            }
+            Change::Remove { id } => book.leave(WORKER_GROUP, id),
         });
+        self.membership.borrow_mut().record(slot, change);
+    }
+
+    /// 提交客户端会话中的命令，返回命令所在的议题编号
+    ///
+    /// 重复提交已形成决议的命令时不再提议，立即报告原先的编号形成了决议；
+    /// 命令仍在表决中时返回其编号，等待同一个结果
+    pub fn submit(&self, command: Command) -> Result<u64> {
+        if let Some(slot) = self
+            .sessions
+            .borrow()
+            .lookup(&command.client, command.seq)?
+        {
+            self.outcomes.borrow_mut().push((slot, Ok(())));
+            return Ok(slot);
+        }
+
+        let proposals = self.proposals.borrow();
+        let committed = self.committed.borrow();
+        let pending = proposals
+            .iter()
+            .map(|(id, proposal)| (id, &proposal.entry))
+            .chain(committed.iter())
+            .find_map(|(id, entry)| {
+                Command::decode(entry)
+                    .filter(|c| c.client == command.client && c.seq == command.seq)
+                    .map(|_| *id)
+            });
+        drop((proposals, committed));
+        match pending {
+            Some(slot) => Ok(slot),
+            None => self.emmit(command.entry()?),
+        }
+    }
+
+    /// 提议对锁 `name` 的命令，返回议题编号：获取锁时即为锁的令牌
+    ///
+    /// 命令带有议长当前的时间，按编号顺序应用后才知道是否生效，
+    /// 未能生效时 `take_outcomes` 报告 `LockError`
+    pub fn lock(&self, name: String, op: LockOp) -> Result<u64> {
+        let at = self.epoch + self.mail_box.now().as_millis() as u64;
+        self.emmit(LockCommand::new(name, op, at).entry()?)
+    }
+
+    /// 提议客户端提交的日志，返回议题编号；窗口已满、或尚未完成第一阶段时议题
+    /// 排队等待提议
+    ///
+    /// 议题按编号顺序应用后，或失败后，结果由 `take_outcomes` 取得
+    pub fn emmit_new_proposal(&self, msg_content: Bytes) -> Result<u64> {
+        self.emmit(Entry::client(msg_content))
+    }
+
+    /// 提议条目，同 `emmit_new_proposal`
+    fn emmit(&self, entry: Entry) -> Result<u64> {
+        // 为新的议题生成编号
+        let issue_id = self.next_slot();
+
+        let pending = self.proposals.borrow().len();
+        if pending >= MAX_PENDING {
+            return Err(anyhow!("too many issues pending: {}", pending));
+        }
+
+        let mut proposal = self.queued(entry);
+        if self.prepared.get() && self.in_window(issue_id) {
+            self.propose(issue_id, &mut proposal)?;
+        }
+        self.proposals.borrow_mut().insert(issue_id, proposal);
+        self.clients.borrow_mut().insert(issue_id);
 
         // 更新议题编号
         self.counter.set(issue_id);
+        Ok(issue_id)
+    }
+
+    /// 议题是否在窗口之内，见 `window_start`
+    fn in_window(&self, issue_id: u64) -> bool {
+        let first = self.proposals.borrow().keys().next().copied();
+        issue_id < self.window_start(first.unwrap_or(issue_id)) + self.window
+    }
+
+    /// 窗口的第一个编号：领导者模式下为第一个尚未形成决议的议题 `first`；
+    /// Mencius 模式下其他议长的议题不在 `proposals` 中，为第一个尚未应用的编号
+    ///
+    /// `window` 不超过 `ALPHA`，成员变更生效前之前的议题都已形成决议；Mencius
+    /// 模式下每名议长都已应用了变更，不论变更由哪名议长提议
+    fn window_start(&self, first: u64) -> u64 {
+        match self.owners {
+            Some(_) => self.applied.get() + 1,
+            None => first,
+        }
+    }
+
+    /// 排队等待提议的议题
+    fn queued(&self, entry: Entry) -> Proposal {
+        let now = self.mail_box.now();
+        let ballot = match self.owners {
+            Some(_) => self.owner_ballot(),
+            None => self.ballot(),
+        };
+        Proposal {
+            entry,
+            ballot,
+            attempts: 0,
+            retry_at: now,
+            deadline: now + self.proposal_timeout,
+        }
+    }
+
+    /// Mencius 模式下议长在自己的新编号中使用的轮次，低于其他议长收回时使用的
+    /// 所有轮次；议长在每个编号只以该轮次提议一次，之后都要经过第一阶段
+    fn owner_ballot(&self) -> Ballot {
+        Ballot::new(0, self.mail_box.id())
+    }
+
+    /// 以当前的轮次将议题下发至所有议员表决；Mencius 模式下以提案自己的轮次
+    fn propose(&self, issue_id: u64, proposal: &mut Proposal) -> Result<()> {
+        let ballot = match self.owners {
+            Some(_) => proposal.ballot.clone(),
+            None => self.ballot(),
+        };
+        // 重启后要收回以最低的轮次提议过的编号，见 `with_protocol`
+        if ballot == self.owner_ballot() && issue_id > self.proposed.get() {
+            self.save(self.ballot().round, issue_id)?;
+            self.proposed.set(issue_id);
+        }
+        // 生成议题
+        let kind = proposal.entry.kind;
+        let issue = Issue::new(
+            proposal.entry.content.clone(),
+            issue_id,
+            IssueType::Proposal,
+        )
+        .with_kind(kind)
+        .with_ballot(ballot.clone())
+        .with_sender(self.mail_box.id());
+
+        // 将议题准备下发至表决该编号的所有议员，见证者只需要轮次与条目的种类
+        let (witnesses, acceptors): (Vec<_>, Vec<_>) = self
+            .membership
+            .borrow()
+            .at(issue_id)
+            .into_iter()
+            .partition(|senator| self.witnesses.contains(senator));
+        let blank = Issue::new(Bytes::new(), issue_id, IssueType::Proposal)
+            .with_kind(kind)
+            .with_ballot(ballot.clone())
+            .with_sender(self.mail_box.id());
+        for (senators, issue) in [(acceptors, issue), (witnesses, blank)] {
+            if !senators.is_empty() {
+                let to = senators.into_iter().map(Recipient::Node).collect();
+                self.mail_box
+                    .put_mail(Mail::new(self.mail_box.id(), to, issue))?;
+            }
+        }
+
+        // 在表决表中记录该议题，之前轮次的投票不再计入，同一轮次的投票仍然有效
+        let mut vote_table = self.vote_table.borrow_mut();
+        if proposal.ballot != ballot {
+            vote_table.remove(&(proposal.ballot.clone(), issue_id));
+        }
+        vote_table
+            .entry((ballot.clone(), issue_id))
+            .or_insert_with(|| Tally::new(proposal.entry.clone()));
+
+        proposal.ballot = ballot;
+        proposal.attempts += 1;
+        proposal.retry_at = self.mail_box.now() + ROUND_TIMEOUT;
         Ok(())
     }
 
-    /// 当收到投票时，为对应议案进行计票，如果票数过半，就生成议案交由书记记录
+    /// 尚未完成第一阶段、且有议题等待提议或读请求等待确认时，以新的轮次准备
+    /// 从第一个尚未应用的编号起的所有编号，发送给表决这些编号的所有议员；
+    /// 上一次准备超时仍未完成时重新准备
+    fn prepare(&self) {
+        let now = self.mail_box.now();
+        let sent_at = self.preparing.borrow().as_ref().map(|p| p.sent_at);
+        let idle = self.proposals.borrow().is_empty() && self.reads.borrow().is_empty();
+        if self.prepared.get()
+            || idle
+            || now < self.prepare_at.get()
+            || sent_at.is_some_and(|at| now < at + ROUND_TIMEOUT)
+        {
+            return;
+        }
+        *self.preparing.borrow_mut() = None;
+
+        let Ok(ballot) = self.next_ballot() else {
+            return;
+        };
+        let from = self.applied.get() + 1;
+        let configs = self.membership.borrow().configs_from(from);
+        let senators: BTreeSet<NodeId> = configs.iter().flatten().cloned().collect();
+        let Ok(content) = bincode::serialize(&Prepare { to: u64::MAX }) else {
+            return;
+        };
+        let issue = Issue::new(content.into(), from, IssueType::Prepare)
+            .with_ballot(ballot.clone())
+            .with_sender(self.mail_box.id());
+        let to = senators.into_iter().map(Recipient::Node).collect();
+        if self
+            .mail_box
+            .put_mail(Mail::new(self.mail_box.id(), to, issue))
+            .is_err()
+        {
+            return;
+        }
+
+        *self.preparing.borrow_mut() = Some(Preparing {
+            ballot,
+            from,
+            configs,
+            promised: BTreeSet::new(),
+            recovered: BTreeMap::new(),
+            sent_at: now,
+        });
+    }
+
+    /// 记录 `voter` 的承诺：承诺的议员达到每组议员的第一阶段法定人数，并且知道
+    /// 每个编号轮次最高的提案的内容时，完成第一阶段，见 `lead`
     ///
-    /// 当投票未过半，返回Error("not enough votes")
-    pub fn process_vote(&self) -> Result<()> {
-        let half_of_voters = (self.senators() / 2) as u8;
-
-        if let Ok(issue) = self.mail_box.get_mail().map(|mail| mail.body()) {
-            match self.vote_table.borrow().get(&issue) {
-                // 此决议正在表决中
-                Some(cnt) => {
-                    // 表决通过了
-                    if cnt + 1 > half_of_voters {
-                        self.vote_table.borrow_mut().remove(&issue);
-                        self.logbackend
-                            .write(issue.id().into(), issue.content().into())
-                    }
-                    // 表决进行中
-                    else {
-                        self.vote_table.borrow_mut().insert(issue, cnt + 1);
-                        Err(anyhow!("not enough votes"))
+    /// 轮次最高的提案只有见证者接受过时，等待更多的议员承诺；所有议员都承诺后
+    /// 仍不知道内容的，该提案不可能形成过决议
+    ///
+    /// Mencius 模式下为收回一个编号的承诺，见 `revoked`
+    fn promise(&self, voter: &str, issue: Issue) -> Result<()> {
+        if self.owners.is_some() {
+            return self.revocation_promise(voter, issue);
+        }
+        let mut current = self.preparing.borrow_mut();
+        let Some(preparing) = current
+            .as_mut()
+            .filter(|preparing| preparing.ballot == issue.ballot())
+        else {
+            return Err(anyhow!("ballot {} is not being prepared", issue.ballot()));
+        };
+        if !self.gather(preparing, voter, &issue)? {
+            return Err(anyhow!("not enough promises"));
+        }
+
+        let preparing = current.take().unwrap();
+        drop(current);
+        self.lead(preparing);
+        Ok(())
+    }
+
+    /// 记录 `voter` 对 `preparing` 的承诺，返回第一阶段是否完成
+    fn gather(&self, preparing: &mut Preparing, voter: &str, issue: &Issue) -> Result<bool> {
+        if !preparing
+            .configs
+            .iter()
+            .any(|config| config.contains(voter))
+        {
+            return Err(anyhow!(
+                "`{}` is not a senator of ballot {}",
+                voter,
+                issue.ballot()
+            ));
+        }
+
+        let promise: Promise = bincode::deserialize(&issue.content())?;
+        let witness = self.witnesses.contains(voter);
+        for accepted in promise.accepted {
+            if accepted.slot < preparing.from {
+                continue;
+            }
+            let known = match preparing.recovered.get(&accepted.slot) {
+                Some((ballot, entry)) => {
+                    *ballot > accepted.ballot || (*ballot == accepted.ballot && entry.is_some())
+                }
+                None => false,
+            };
+            if !known {
+                let entry = (!witness).then_some(accepted.entry);
+                preparing
+                    .recovered
+                    .insert(accepted.slot, (accepted.ballot, entry));
+            }
+        }
+        preparing.promised.insert(voter.to_string());
+
+        let promised = &preparing.promised;
+        let everyone = preparing
+            .configs
+            .iter()
+            .flatten()
+            .all(|senator| promised.contains(senator));
+        let quorum = preparing
+            .configs
+            .iter()
+            .all(|config| self.quorums.phase1_reached(config, promised));
+        let known = preparing
+            .recovered
+            .values()
+            .all(|(_, entry)| entry.is_some());
+        Ok(everyone || quorum && known)
+    }
+
+    /// 记录 `voter` 对收回编号的承诺，完成第一阶段时见 `revoked`
+    fn revocation_promise(&self, voter: &str, issue: Issue) -> Result<()> {
+        let slot = issue.id();
+        let mut revoking = self.revoking.borrow_mut();
+        let Some((preparing, _)) = revoking
+            .get_mut(&slot)
+            .filter(|(preparing, _)| preparing.ballot == issue.ballot())
+        else {
+            return Err(anyhow!(
+                "issue {} of ballot {} is not being revoked",
+                slot,
+                issue.ballot()
+            ));
+        };
+        if !self.gather(preparing, voter, &issue)? {
+            return Err(anyhow!("not enough promises"));
+        }
+
+        let (preparing, _) = revoking.remove(&slot).unwrap();
+        drop(revoking);
+        self.revoked(slot, preparing);
+        Ok(())
+    }
+
+    /// 完成收回 `slot` 的第一阶段：以收回的轮次提议承诺中轮次最高的提案，没有时
+    /// 提议空操作；本议长的客户端提交的议题被取代时报告失败
+    fn revoked(&self, slot: u64, preparing: Preparing) {
+        if slot <= self.applied.get() || self.committed.borrow().contains_key(&slot) {
+            return;
+        }
+        let entry = preparing
+            .recovered
+            .get(&slot)
+            .and_then(|(_, entry)| entry.clone())
+            .unwrap_or_else(Entry::noop);
+        let mut proposals = self.proposals.borrow_mut();
+        let proposal = proposals
+            .entry(slot)
+            .or_insert_with(|| self.queued(entry.clone()));
+        if proposal.entry != entry {
+            if self.clients.borrow_mut().remove(&slot) {
+                let e = anyhow!("issue {} is taken by an earlier proposal", slot);
+                self.outcomes.borrow_mut().push((slot, Err(e)));
+            }
+            proposal.entry = entry;
+        }
+        self.vote_table
+            .borrow_mut()
+            .remove(&(proposal.ballot.clone(), slot));
+        proposal.ballot = preparing.ballot;
+        let _ = self.propose(slot, proposal);
+    }
+
+    /// 完成第一阶段：在每个尚未形成决议的编号提议承诺中轮次最高的提案，空缺
+    /// 提议空操作；本议长的客户端提交的议题被取代时报告失败。之后所有议题都以
+    /// 新的轮次重新提议
+    fn lead(&self, preparing: Preparing) {
+        self.prepared.set(true);
+        self.rejections.set(0);
+
+        let recovered = preparing
+            .recovered
+            .keys()
+            .next_back()
+            .map_or(0, |slot| *slot);
+        self.read_index.set(recovered.max(self.applied.get()));
+        let last = recovered.max(self.counter.get());
+        let committed = self.committed.borrow();
+        let mut proposals = self.proposals.borrow_mut();
+        for slot in self.applied.get() + 1..=last {
+            if committed.contains_key(&slot) {
+                continue;
+            }
+            let recovered = preparing
+                .recovered
+                .get(&slot)
+                .map(|(_, entry)| entry.clone().unwrap_or_else(Entry::noop));
+            match (proposals.get_mut(&slot), recovered) {
+                (Some(proposal), Some(entry)) if proposal.entry != entry => {
+                    if self.clients.borrow_mut().remove(&slot) {
+                        let e = anyhow!("issue {} is taken by an earlier proposal", slot);
+                        self.outcomes.borrow_mut().push((slot, Err(e)));
                     }
+                    proposal.entry = entry;
+                }
+                (Some(_), _) => {}
+                (None, entry) => {
+                    let entry = entry.unwrap_or_else(Entry::noop);
+                    proposals.insert(slot, self.queued(entry));
                 }
-                // 此决议已完成表决，或未有此决议的提案
-                None => Err(anyhow!("this proposal is either not emitted or finished")),
             }
-        } else {
-            Err(anyhow!("Error"))
         }
+
+        let now = self.mail_box.now();
+        for proposal in proposals.values_mut() {
+            proposal.attempts = 0;
+            proposal.retry_at = now;
+        }
+        self.vote_table.borrow_mut().clear();
+        self.counter.set(last);
+        drop((committed, proposals));
+        self.drive();
     }
 
-    pub fn get_log(&self, id: u64) -> Result<String> {
-        let query_result = self.logbackend.query(id)?;
-        Ok(String::from_utf8(query_result.into())?)
+    /// 向议员发送心跳，收发一轮邮件，处理收到的所有投票、拒绝与确认，再重新
+    /// 提议或报告到期的提案，并为等待中的读请求发起确认；
+    /// 没有新邮件时最多等待 `timeout`
+    pub fn tick(&self, timeout: Duration) -> Result<()> {
+        self.mail_box
+            .heartbeat(&[self.members(), self.learners.clone()].concat())?;
+        self.mail_box.pump(timeout)?;
+        while let Ok(mail) = self.mail_box.get_mail() {
+            let _ = self.handle(&mail.sender(), mail.body());
+        }
+        self.drive();
+        self.expire_round();
+        self.confirm();
+        self.pass_barriers();
+        self.catch_up();
+        Ok(())
     }
-}
 
-/// 议员：
-/// 1. 对 *议长(President)* 下发的 *议题(Proposal)* 进行投票
-/// 2. 如果当前议题的 *编号(id)* 大于已处理的 *编号(id)* ，同意该提案；否则拒绝
-/// 3. 回复投票结果至 *议长(President)*
-pub struct Worker {
-    address: Address,
-    address_book: AddressBook,
-    mail_box: MailBox<String, Issue>,
-    last_proposal_id: u64,
-}
+    /// 以 `consistency` 读取日志，返回读请求的编号
+    ///
+    /// 可以安全读取、或无法确认领导权时，结果由 `take_reads` 取得，之后再由
+    /// `get_log` 读取。过期读请求立即可读；线性一致的读请求等待下一轮确认，
+    /// 租约有效时租约读请求不必等待确认；之后都等待日志应用到完成第一阶段时
+    /// 恢复的最后一个编号：本议长确认过的议题都已应用，之前的议长确认过的议题
+    /// 都不在其后
+    pub fn read(&self, consistency: Consistency) -> u64 {
+        let id = self.read_counter.get() + 1;
+        self.read_counter.set(id);
 
-impl Worker {
-    pub fn new(address: Address, address_book: AddressBook) -> Result<Self> {
-        Ok(Self {
-            address: address.clone(),
-            address_book: address_book,
-            mail_box: MailBox::new(Box::new(Net::new(address)?)),
-            last_proposal_id: 0,
-        })
+        let now = self.mail_box.now();
+        let leased = self.prepared.get() && now < self.lease_until.get();
+        match consistency {
+            Consistency::Stale => self.read_outcomes.borrow_mut().push((id, Ok(()))),
+            _ if self.owners.is_some() => self.barrier(id),
+            Consistency::Lease if leased => self.await_applied(id, self.read_index.get()),
+            _ => {
+                let deadline = now + self.proposal_timeout;
+                self.reads.borrow_mut().push((id, deadline));
+                self.confirm();
+            }
+        }
+        id
     }
 
-    pub fn vote(&self) -> Result<()> {
-        if let Ok(issue) = self.mail_box.get_mail().map(|mail| mail.body()) {
-            if issue.id() > self.last_proposal_id {
-                self.address_book
-                    .get("master")
-                    .map(|addr| {
-                        let worker_address = addr.join(",");
-                        let mail = Mail::new(
-                            self.address.clone(),
-                            vec![worker_address],
-                            Issue::new(issue.content(), issue.id(), IssueType::Vote),
-                        );
-                        self.mail_box.put_mail(mail).ok()
-                    })
-                    .flatten()
-                    .ok_or(anyhow!(
-                        "Can not Send Vote to Master. Check address_book setting."
-                    ))
+    /// 日志应用到 `slot` 后读请求可以安全读取
+    fn await_applied(&self, read: u64, slot: u64) {
+        let deadline = self.mail_box.now() + self.proposal_timeout;
+        self.barriers.borrow_mut().push((read, slot, deadline));
+        self.pass_barriers();
+    }
+
+    /// Mencius 模式下的读请求跳过自己的下一个编号，日志应用到该编号后可以安全读取
+    fn barrier(&self, read: u64) {
+        let slot = self.next_slot();
+        self.counter.set(slot);
+        match self.decide(slot, Entry::noop()) {
+            Ok(()) => self.await_applied(read, slot),
+            Err(e) => self.read_outcomes.borrow_mut().push((read, Err(e))),
+        }
+    }
+
+    /// 日志已应用到等待的编号的读请求可以安全读取，到期仍未应用的失败
+    fn pass_barriers(&self) {
+        let (applied, now) = (self.applied.get(), self.mail_box.now());
+        let mut outcomes = self.read_outcomes.borrow_mut();
+        self.barriers.borrow_mut().retain(|(read, slot, deadline)| {
+            if *slot <= applied {
+                outcomes.push((*read, Ok(())));
+            } else if now >= *deadline {
+                let e = anyhow!("the log is not applied up to issue {}", slot);
+                outcomes.push((*read, Err(e)));
             } else {
-                Err(anyhow!(
-                    "received expire issue {}, last issue is {}, drop.",
-                    issue.id(),
-                    self.last_proposal_id
-                ))
+                return true;
             }
-        } else {
-            Err(anyhow!("Error"))
+            false
+        });
+    }
+
+    /// 取走自上次调用以来可以安全读取或失败的读请求的编号与结果
+    pub fn take_reads(&self) -> Vec<(u64, Result<()>)> {
+        self.read_outcomes.take()
+    }
+
+    /// 完成了第一阶段、且没有进行中的确认时，以准备时的轮次向表决下一个议题的
+    /// 议员发起新的一轮确认，之前到达的读请求都等待这一轮
+    fn confirm(&self) {
+        if !self.prepared.get() || self.round.borrow().is_some() || self.reads.borrow().is_empty() {
+            return;
+        }
+
+        let id = self.round_counter.get() + 1;
+        self.round_counter.set(id);
+        let ballot = self.ballot();
+        let senators = self.membership.borrow().at(self.counter.get() + 1);
+        let issue = Issue::new(Bytes::new(), id, IssueType::Confirm)
+            .with_ballot(ballot.clone())
+            .with_sender(self.mail_box.id());
+        let mail = Mail::new(
+            self.mail_box.id(),
+            senators.iter().cloned().map(Recipient::Node).collect(),
+            issue,
+        );
+        let sent_at = self.mail_box.now();
+        let reads = self.reads.take();
+        if let Err(e) = self.mail_box.put_mail(mail) {
+            self.finish_reads(reads.into_iter().map(|(read, _)| read), Err(e));
+            return;
+        }
+
+        *self.round.borrow_mut() = Some(Round {
+            id,
+            ballot,
+            senators,
+            acks: BTreeSet::new(),
+            sent_at,
+            reads,
+        });
+    }
+
+    /// 处理议员对确认的回复：回复的轮次与本轮相同即为同意。同意的议员达到第一阶段
+    /// 的法定人数时，从发出确认时起获得租约，本轮的读请求等待日志应用到完成第一
+    /// 阶段时恢复的最后一个编号；议员已承诺了更高的轮次时，放弃当前的轮次，
+    /// 本轮的读请求失败
+    fn confirmed(&self, voter: &str, issue: Issue) -> Result<()> {
+        let mut current = self.round.borrow_mut();
+        let Some(round) = current.as_mut().filter(|round| round.id == issue.id()) else {
+            return Err(anyhow!(
+                "round {} is either not started or finished",
+                issue.id()
+            ));
+        };
+        if !round.senators.contains(voter) {
+            return Err(anyhow!(
+                "`{}` is not a senator of round {}",
+                voter,
+                round.id
+            ));
+        }
+
+        if issue.ballot() > round.ballot {
+            let round = current.take().unwrap();
+            drop(current);
+            self.see(&issue.ballot());
+            if round.ballot == self.ballot() {
+                self.preempted();
+            }
+            let e = anyhow!("`{}` has promised ballot {}", voter, issue.ballot());
+            self.finish_reads(round.reads.into_iter().map(|(read, _)| read), Err(e));
+            return Ok(());
+        }
+        if issue.ballot() != round.ballot {
+            return Err(anyhow!("`{}` confirmed ballot {}", voter, issue.ballot()));
+        }
+
+        round.acks.insert(voter.to_string());
+        if !self.quorums.phase1_reached(&round.senators, &round.acks) {
+            return Err(anyhow!("not enough confirmations"));
+        }
+        let round = current.take().unwrap();
+        drop(current);
+        // 确认期间换了轮次时，租约属于之前的轮次
+        if self.prepared.get() && round.ballot == self.ballot() {
+            self.lease_until.set(round.sent_at + LEASE_DURATION);
+        }
+        let slot = self.read_index.get();
+        self.barriers.borrow_mut().extend(
+            round
+                .reads
+                .into_iter()
+                .map(|(read, deadline)| (read, slot, deadline)),
+        );
+        self.pass_barriers();
+        Ok(())
+    }
+
+    /// 一轮确认超时仍未完成时，本轮的读请求失败；等待确认的读请求到期时失败
+    fn expire_round(&self) {
+        let now = self.mail_box.now();
+        let mut current = self.round.borrow_mut();
+        if current
+            .as_ref()
+            .is_some_and(|round| now >= round.sent_at + ROUND_TIMEOUT)
+        {
+            let round = current.take().unwrap();
+            let e = anyhow!("leadership is not confirmed in round {}", round.id);
+            self.finish_reads(round.reads.into_iter().map(|(read, _)| read), Err(e));
+        }
+
+        let mut expired = Vec::new();
+        self.reads.borrow_mut().retain(|&(read, deadline)| {
+            if now < deadline {
+                return true;
+            }
+            expired.push(read);
+            false
+        });
+        let e = anyhow!("not prepared to confirm the leadership");
+        self.finish_reads(expired, Err(e));
+    }
+
+    fn finish_reads(&self, reads: impl IntoIterator<Item = u64>, outcome: Result<()>) {
+        let mut outcomes = self.read_outcomes.borrow_mut();
+        for id in reads {
+            let outcome = match &outcome {
+                Ok(()) => Ok(()),
+                Err(e) => Err(anyhow!("{}", e)),
+            };
+            outcomes.push((id, outcome));
+        }
+    }
+
+    /// 从收件箱取出一张投票或拒绝并处理，见 `count_vote` 与 `reject`
+    pub fn process_vote(&self) -> Result<()> {
+        let mail = self.mail_box.get_mail()?;
+        self.handle(&mail.sender(), mail.body())
+    }
+
+    /// 取走自上次调用以来形成决议或失败的议题的编号与结果
+    pub fn take_outcomes(&self) -> Vec<(u64, Result<()>)> {
+        self.outcomes.take()
+    }
+
+    /// 处理议员的回复、学习者或其他议长的补发请求，以及其他议长的决议；
+    /// 不是表决该议题的议员的投票被丢弃
+    fn handle(&self, voter: &str, issue: Issue) -> Result<()> {
+        match issue.issue_type() {
+            IssueType::Confirmed => return self.confirmed(voter, issue),
+            IssueType::Promise => return self.promise(voter, issue),
+            IssueType::Reject => return self.reject(issue),
+            IssueType::Resolution if issue.content().is_empty() => {
+                return self.sync(voter, issue.id())
+            }
+            IssueType::Resolution => return self.resolve(voter, issue),
+            _ => {}
+        }
+        if !self.membership.borrow().at(issue.id()).contains(voter) {
+            return Err(anyhow!(
+                "`{}` is not a senator of issue {}",
+                voter,
+                issue.id()
+            ));
+        }
+
+        match issue.issue_type() {
+            IssueType::Vote => self.count_vote(voter, issue),
+            other => Err(anyhow!("expect a vote, got {:?}", other)),
+        }
+    }
+
+    /// 为 `voter` 的投票计票，不同的议员的赞成票达到第二阶段的法定人数时，
+    /// 就生成议案交由书记记录
+    ///
+    /// 当投票未达到法定人数，返回Error("not enough votes")；以下投票不计入：
+    /// 1. 同一议员对同一轮次、同一议题的重复投票
+    /// 2. 已完成表决、或不在表决中的议题与轮次的投票
+    /// 3. 内容与议题不一致的投票
+    fn count_vote(&self, voter: &str, issue: Issue) -> Result<()> {
+        let senators = self.membership.borrow().at(issue.id());
+
+        let key = (issue.ballot(), issue.id());
+        let mut vote_table = self.vote_table.borrow_mut();
+        // 此决议已完成表决，或未有此轮次的提案
+        let Some(tally) = vote_table.get_mut(&key) else {
+            return Err(anyhow!(
+                "issue {} of ballot {} is either not emitted or finished",
+                issue.id(),
+                issue.ballot()
+            ));
+        };
+
+        // 表决进行中，见证者的投票不带议题的内容
+        let entry = match self.witnesses.contains(voter) {
+            true => tally.entry(),
+            false => issue.entry(),
+        };
+        tally.vote(voter, &entry)?;
+        if !self.quorums.phase2_reached(&senators, tally.voters()) {
+            return Err(anyhow!("not enough votes"));
+        }
+
+        // 表决通过了
+        let entry = tally.entry();
+        vote_table.remove(&key);
+        self.proposals.borrow_mut().remove(&issue.id());
+        drop(vote_table);
+        let written = self.decide(issue.id(), entry);
+        if let Err(e) = &written {
+            let e = anyhow!("failed to record issue {}: {}", issue.id(), e);
+            self.outcomes.borrow_mut().push((issue.id(), Err(e)));
+        }
+        self.advance();
+        written
+    }
+
+    /// 由书记记录在 `slot` 形成的决议，等待按顺序应用；
+    /// Mencius 模式下同时发送给其他议长
+    fn decide(&self, slot: u64, entry: Entry) -> Result<()> {
+        self.logbackend.write(slot, entry.encode()?)?;
+        self.committed.borrow_mut().insert(slot, entry.clone());
+        if self.owners.is_some() {
+            let resolutions = Resolutions {
+                from: slot,
+                to: slot,
+                entries: vec![(slot, entry)],
+            };
+            let _ = self.publish(&self.peers(), resolutions);
+        }
+        Ok(())
+    }
+
+    /// 记录其他议长形成的决议，包括收回自己的编号形成的决议，并跳过自己更早的
+    /// 未使用的编号
+    fn resolve(&self, peer: &str, issue: Issue) -> Result<()> {
+        let Some(owners) = self.owners.as_ref() else {
+            return Err(anyhow!("resolutions from `{}` in the leader mode", peer));
+        };
+        if !self.peers().iter().any(|id| id == peer) {
+            return Err(anyhow!("`{}` is not a master", peer));
+        }
+
+        let resolutions: Resolutions = bincode::deserialize(&issue.content())?;
+        for (slot, entry) in resolutions.entries {
+            let known = slot <= self.applied.get()
+                || self.committed.borrow().contains_key(&slot)
+                || self.logbackend.query(slot).is_ok();
+            if !known {
+                self.settle(slot, &entry);
+                self.logbackend.write(slot, entry.encode()?)?;
+                self.committed.borrow_mut().insert(slot, entry);
+            }
+            self.skip_to(slot);
+        }
+        self.advance();
+        Ok(())
+    }
+
+    /// 其他议长在 `slot` 形成了决议：放弃自己在该编号的提案与收回，
+    /// 本议长的客户端提交的议题被取代时报告失败
+    fn settle(&self, slot: u64, entry: &Entry) {
+        self.revoke_at.borrow_mut().remove(&slot);
+        self.revoking.borrow_mut().remove(&slot);
+        let Some(proposal) = self.proposals.borrow_mut().remove(&slot) else {
+            return;
+        };
+        self.vote_table
+            .borrow_mut()
+            .remove(&(proposal.ballot, slot));
+        if proposal.entry != *entry && self.clients.borrow_mut().remove(&slot) {
+            let e = anyhow!("issue {} is taken by an earlier proposal", slot);
+            self.outcomes.borrow_mut().push((slot, Err(e)));
+        }
+    }
+
+    /// Mencius 模式下日志停在其他议长的同一个编号上超过 `REVOKE_TIMEOUT` 时，
+    /// 收回之后窗口内缺少决议的编号；到时间的编号以新的轮次向表决该编号的议员
+    /// 准备，超时仍未完成的以同一轮次重新准备
+    fn revoke(&self) {
+        let Some(owners) = self.owners.as_ref() else {
+            return;
+        };
+        let now = self.mail_box.now();
+        let first = self.applied.get() + 1;
+        let end = first + self.window;
+        let last = self.counter.get().min(end - 1);
+        let missing = |slot: &u64| {
+            !self.committed.borrow().contains_key(slot)
+                && !self.proposals.borrow().contains_key(slot)
+                && !self.revoking.borrow().contains_key(slot)
+        };
+
+        let stuck = first <= last && owners.owner(first) != &self.mail_box.id() && missing(&first);
+        match self.stalled.get() {
+            Some((slot, since)) if stuck && slot == first => {
+                if now >= since + REVOKE_TIMEOUT {
+                    let mut revoke_at = self.revoke_at.borrow_mut();
+                    for slot in (first..=last).filter(missing) {
+                        revoke_at.entry(slot).or_insert((now, 0));
+                    }
+                    self.stalled.set(Some((first, now)));
+                }
+            }
+            _ => self.stalled.set(stuck.then_some((first, now))),
+        }
+
+        // 承诺可能只是来得晚，以同一轮次重新准备，已得到的承诺仍然有效
+        for (slot, (preparing, _)) in self.revoking.borrow_mut().iter_mut() {
+            if now >= preparing.sent_at + ROUND_TIMEOUT
+                && self
+                    .send_prepare(*slot, &preparing.ballot, &preparing.configs[0])
+                    .is_ok()
+            {
+                preparing.sent_at = now;
+            }
+        }
+
+        let mut revoke_at = self.revoke_at.borrow_mut();
+        revoke_at.retain(|slot, _| *slot >= first && !self.committed.borrow().contains_key(slot));
+        let due: Vec<(u64, u32)> = revoke_at
+            .iter()
+            .filter(|(slot, (at, _))| **slot < end && now >= *at)
+            .map(|(slot, (_, attempts))| (*slot, *attempts))
+            .collect();
+        if due.is_empty() {
+            return;
+        }
+        let Ok(ballot) = self.next_ballot() else {
+            return;
+        };
+
+        for (slot, attempts) in due {
+            let senators = self.membership.borrow().at(slot);
+            if self.send_prepare(slot, &ballot, &senators).is_err() {
+                continue;
+            }
+            revoke_at.remove(&slot);
+            let preparing = Preparing {
+                ballot: ballot.clone(),
+                from: slot,
+                configs: vec![senators],
+                promised: BTreeSet::new(),
+                recovered: BTreeMap::new(),
+                sent_at: now,
+            };
+            self.revoking
+                .borrow_mut()
+                .insert(slot, (preparing, attempts + 1));
+        }
+    }
+
+    /// 以 `ballot` 向 `senators` 准备编号 `slot`
+    fn send_prepare(&self, slot: u64, ballot: &Ballot, senators: &BTreeSet<NodeId>) -> Result<()> {
+        let content = bincode::serialize(&Prepare { to: slot })?;
+        let issue = Issue::new(content.into(), slot, IssueType::Prepare)
+            .with_ballot(ballot.clone())
+            .with_sender(self.mail_box.id());
+        let to = senators.iter().cloned().map(Recipient::Node).collect();
+        self.mail_box
+            .put_mail(Mail::new(self.mail_box.id(), to, issue))
+    }
+
+    /// 跳过自己拥有的、小于 `slot` 的未使用的编号
+    fn skip_to(&self, slot: u64) {
+        loop {
+            let next = self.next_slot();
+            if next >= slot {
+                break;
+            }
+            self.counter.set(next);
+            let _ = self.decide(next, Entry::noop());
+        }
+        self.counter.set(self.counter.get().max(slot));
+    }
+
+    /// Mencius 模式下日志有缺少决议的编号时，每隔 `SYNC_INTERVAL` 向其他议长
+    /// 请求补发
+    fn catch_up(&self) {
+        let now = self.mail_box.now();
+        if self.owners.is_none()
+            || self.applied.get() >= self.counter.get()
+            || self
+                .synced_at
+                .get()
+                .is_some_and(|at| now < at + SYNC_INTERVAL)
+        {
+            return;
+        }
+
+        self.synced_at.set(Some(now));
+        let request = Issue::new(Bytes::new(), self.applied.get() + 1, IssueType::Resolution)
+            .with_sender(self.mail_box.id());
+        let to = self.peers().into_iter().map(Recipient::Node).collect();
+        let _ = self
+            .mail_box
+            .put_mail(Mail::new(self.mail_box.id(), to, request));
+    }
+
+    /// 按编号顺序应用已形成决议的议题，直到第一个尚未形成决议的议题，
+    /// 再推送给所有学习者；本议长的客户端提交的议题报告结果
+    fn advance(&self) {
+        let from = self.applied.get() + 1;
+        let mut entries = Vec::new();
+        loop {
+            let next = self.applied.get() + 1;
+            if next > self.counter.get() || self.proposals.borrow().contains_key(&next) {
+                break;
+            }
+            let Some(entry) = self.committed.borrow_mut().remove(&next) else {
+                break;
+            };
+            let outcome = self
+                .deduplicate(&entry)
+                .and_then(|_| self.learn(next, &entry));
+            if self.clients.borrow_mut().remove(&next) {
+                self.outcomes.borrow_mut().push((next, outcome));
+            }
+            entries.push((next, entry));
+            self.applied.set(next);
+        }
+
+        let to = self.applied.get();
+        if to >= from {
+            let _ = self.publish(&self.learners, Resolutions { from, to, entries });
+        }
+    }
+
+    /// 学习者请求从 `from` 起的决议：补发至多 `SYNC_BATCH` 个已应用的编号；
+    /// 其他议长请求时补发已记录的决议，不必已经应用
+    fn sync(&self, requester: &str, from: u64) -> Result<()> {
+        let last = if self.learners.iter().any(|id| id == requester) {
+            self.applied.get()
+        } else if self.peers().iter().any(|id| id == requester) {
+            self.counter.get()
+        } else {
+            return Err(anyhow!("`{}` is neither a learner nor a master", requester));
+        };
+        let to = last.min(from.saturating_add(SYNC_BATCH - 1));
+        if from == 0 || to < from {
+            return Ok(());
+        }
+
+        let entries = (from..=to)
+            .filter_map(|slot| {
+                let raw = self.logbackend.query(slot).ok()?;
+                Entry::decode(&raw).ok().map(|entry| (slot, entry))
+            })
+            .collect();
+        self.publish(&[requester.to_string()], Resolutions { from, to, entries })
+    }
+
+    fn publish(&self, nodes: &[NodeId], resolutions: Resolutions) -> Result<()> {
+        if nodes.is_empty() {
+            return Ok(());
+        }
+        let content = Bytes::from(bincode::serialize(&resolutions)?);
+        let issue = Issue::new(content, resolutions.from, IssueType::Resolution)
+            .with_sender(self.mail_box.id());
+        let to = nodes.iter().cloned().map(Recipient::Node).collect();
+        self.mail_box
+            .put_mail(Mail::new(self.mail_box.id(), to, issue))
+    }
+
+    /// 议员已承诺了比本议长更高的轮次，拒绝了准备或议题：领导者模式下放弃当前的
+    /// 轮次，随机等待一段时间后以更高的轮次重新准备；Mencius 模式下随机等待一段
+    /// 时间后以更高的轮次重新收回该编号
+    fn reject(&self, issue: Issue) -> Result<()> {
+        self.see(&issue.ballot());
+        if self.owners.is_some() {
+            return self.revoke_later(issue);
+        }
+        if issue.ballot() <= self.ballot() {
+            return Ok(());
+        }
+
+        self.preempted();
+        self.vote_table.borrow_mut().clear();
+        Ok(())
+    }
+
+    /// Mencius 模式下编号的提案或收回被拒绝时，放弃该轮次，随机等待一段时间后
+    /// 重新收回
+    fn revoke_later(&self, issue: Issue) -> Result<()> {
+        let slot = issue.id();
+        let now = self.mail_box.now();
+        let mut revoking = self.revoking.borrow_mut();
+        if let Some((preparing, attempts)) = revoking.get(&slot) {
+            if preparing.ballot >= issue.ballot() {
+                return Ok(());
+            }
+            let attempts = *attempts;
+            revoking.remove(&slot);
+            let at = now + self.backoff(attempts);
+            self.revoke_at.borrow_mut().insert(slot, (at, attempts));
+            return Ok(());
+        }
+
+        let mut proposals = self.proposals.borrow_mut();
+        let Some(proposal) = proposals
+            .get_mut(&slot)
+            .filter(|proposal| proposal.ballot < issue.ballot())
+        else {
+            return Err(anyhow!("issue {} is either not emitted or finished", slot));
+        };
+        self.vote_table
+            .borrow_mut()
+            .remove(&(proposal.ballot.clone(), slot));
+        let at = now + self.backoff(proposal.attempts);
+        self.revoke_at.borrow_mut().entry(slot).or_insert((at, 0));
+        Ok(())
+    }
+
+    /// 领导者模式下其他议长得到了更高的轮次：放弃当前的轮次与租约，
+    /// 随机等待一段时间后以更高的轮次重新准备
+    fn preempted(&self) {
+        if !self.prepared.get() && self.preparing.borrow().is_none() {
+            return;
+        }
+        self.prepared.set(false);
+        *self.preparing.borrow_mut() = None;
+        self.lease_until.set(Duration::ZERO);
+        let rejections = self.rejections.get() + 1;
+        self.rejections.set(rejections);
+        self.prepare_at
+            .set(self.mail_box.now() + self.backoff(rejections));
+    }
+
+    /// 第 `attempts` 次提议被拒绝后，重新提议前随机等待的时间
+    fn backoff(&self, attempts: u32) -> Duration {
+        let factor = 1u32
+            .checked_shl(attempts.saturating_sub(1))
+            .unwrap_or(u32::MAX);
+        let limit = BACKOFF_INITIAL.saturating_mul(factor).min(BACKOFF_MAX);
+        let millis = self.rng.borrow_mut().below(limit.as_millis() as u64 + 1);
+        Duration::from_millis(millis)
+    }
+
+    /// 需要时开始第一阶段或收回编号；完成第一阶段后提议进入窗口的议题，重新提议
+    /// 到期的议题，并报告超时的议题失败，但仍继续提议直到形成决议
+    fn drive(&self) {
+        self.prepare();
+        self.revoke();
+        let now = self.mail_box.now();
+        let prepared = self.prepared.get();
+        let mut proposals = self.proposals.borrow_mut();
+        let window_end = proposals
+            .keys()
+            .next()
+            .map_or(0, |first| self.window_start(*first) + self.window);
+        // 等待收回的编号先完成第一阶段
+        let revoking: BTreeSet<u64> = self
+            .revoke_at
+            .borrow()
+            .keys()
+            .chain(self.revoking.borrow().keys())
+            .copied()
+            .collect();
+
+        let mut expired = Vec::new();
+        for (id, proposal) in proposals.iter_mut() {
+            if now >= proposal.deadline {
+                expired.push((*id, proposal.attempts));
+            }
+            if !prepared || revoking.contains(id) {
+                continue;
+            }
+            if proposal.attempts == 0 {
+                if *id < window_end {
+                    let _ = self.propose(*id, proposal);
+                }
+            } else if now >= proposal.retry_at {
+                let _ = self.propose(*id, proposal);
+            }
+        }
+
+        for (id, attempts) in expired {
+            if self.clients.borrow_mut().remove(&id) {
+                let e = anyhow!("issue {} is not decided after {} attempts", id, attempts);
+                self.outcomes.borrow_mut().push((id, Err(e)));
+            }
+        }
+        drop(proposals);
+        self.advance();
+    }
+
+    /// 读取日志，会话命令只返回命令的内容
+    pub fn get_log(&self, id: u64) -> Result<Bytes> {
+        read_entry(&*self.logbackend, id)
+    }
+
+    /// 停止前将已记录的决议落盘
+    pub fn close(&self) -> Result<()> {
+        self.logbackend.flush()
+    }
+}
+
+/// 议员：
+/// 1. 对 *议长(President)* 下发的 *议题(Proposal)* 进行投票
+/// 2. 如果议题的 *轮次(ballot)* 不低于该编号承诺过的最高轮次，接受该提案并承诺该轮次；
+///    否则拒绝，并告知承诺过的最高轮次
+/// 3. 议长 *准备(Prepare)* 一段编号时，若轮次不低于这些编号承诺过的最高轮次，
+///    承诺该轮次，并回复这些编号中接受过的提案；否则拒绝
+/// 4. 回复投票结果至提出议题的 *议长(President)*
+/// 5. 议长 *确认(Confirm)* 领导权时，若轮次高于承诺过的最高轮次，或就是该议长
+///    承诺过的最高轮次，授予议长租约；租约到期前拒绝其他议长的准备、提案与确认；
+///    重启后先在一个租约的时长内拒绝所有议长的准备与确认
+///
+/// 承诺与接受的提案先写入日志再回复，见 `acceptor`。作为 *见证者(Witness)* 时
+/// 只保存轮次，投票不带议题的内容。
+pub struct Worker {
+    mail_box: MailBox<String, Issue>,
+    witness: bool,
+    /// 承诺过的轮次与接受过的提案
+    acceptor: RefCell<Acceptor>,
+    /// 授予议长的租约
+    lease: RefCell<Option<Lease>>,
+}
+
+impl Worker {
+    /// 从日志中恢复承诺过的轮次与接受过的提案；恢复了之前的状态时，
+    /// 不知道重启前授予过谁租约，先等待租约的时长
+    pub fn new(
+        mail_box: MailBox<Address, Issue>,
+        log_backend: Box<dyn LogBackend>,
+    ) -> Result<Self> {
+        let acceptor = Acceptor::open(log_backend)?;
+        let lease = acceptor
+            .restored()
+            .then(|| Lease::after_restart(mail_box.now()));
+        Ok(Self {
+            mail_box,
+            witness: false,
+            acceptor: RefCell::new(acceptor),
+            lease: RefCell::new(lease),
+        })
+    }
+
+    /// 是否作为见证者，默认不是
+    pub fn with_witness(mut self, witness: bool) -> Self {
+        self.witness = witness;
+        self
+    }
+
+    pub fn mail_box(&self) -> &MailBox<Address, Issue> {
+        &self.mail_box
+    }
+
+    /// 向议长发送心跳，收发一轮邮件，并对收到的所有议题投票；
+    /// 没有新邮件时最多等待 `timeout`
+    pub fn tick(&self, timeout: Duration) -> Result<()> {
+        let masters = self.mail_box.address_book().group(MASTER_GROUP);
+        self.mail_box.heartbeat(&masters)?;
+        self.mail_box.pump(timeout)?;
+        while let Ok(mail) = self.mail_box.get_mail() {
+            let _ = self.answer(mail);
+        }
+        Ok(())
+    }
+
+    /// 从收件箱取出一个议题并投票，见 `answer`
+    pub fn vote(&self) -> Result<()> {
+        let mail = self.mail_box.get_mail()?;
+        self.answer(mail)
+    }
+
+    /// 向提出议题的议长回复赞成票或者拒绝，向准备的议长回复承诺或者拒绝，
+    /// 向确认领导权的议长回复承诺过的最高轮次；其他议长持有租约时，
+    /// 不回复准备与确认；未能写入日志时不回复
+    fn answer(&self, mail: Mail<Issue>) -> Result<()> {
+        let issue = mail.body();
+        let sender = mail.sender();
+        let now = self.mail_box.now();
+        let lease = self.lease.borrow().clone();
+        let leased = lease.as_ref().is_some_and(|l| l.excludes(&sender, now));
+        let proposal_leased = lease
+            .as_ref()
+            .is_some_and(|l| l.excludes_proposal(&sender, now));
+
+        let (id, ballot) = (issue.id(), issue.ballot());
+        let mut acceptor = self.acceptor.borrow_mut();
+        let reply = match issue.issue_type() {
+            IssueType::Proposal => {
+                let entry = match self.witness {
+                    true => Entry::new(issue.kind(), Bytes::new()),
+                    false => issue.entry(),
+                };
+                if !proposal_leased && acceptor.accept(&ballot, id, entry.clone())? {
+                    Issue::new(entry.content, id, IssueType::Vote)
+                        .with_kind(entry.kind)
+                        .with_ballot(ballot)
+                } else {
+                    Issue::new(Bytes::new(), id, IssueType::Reject)
+                        .with_ballot(acceptor.promised(id, id))
+                }
+            }
+            IssueType::Prepare | IssueType::Confirm if leased => {
+                return Err(anyhow!("the lease is held by another master"));
+            }
+            IssueType::Prepare => {
+                let prepare: Prepare = bincode::deserialize(&issue.content())?;
+                match acceptor.prepare(&ballot, id, prepare.to)? {
+                    Some(promise) => {
+                        let content = Bytes::from(bincode::serialize(&promise)?);
+                        Issue::new(content, id, IssueType::Promise).with_ballot(ballot)
+                    }
+                    None => Issue::new(Bytes::new(), id, IssueType::Reject)
+                        .with_ballot(acceptor.promised(id, prepare.to)),
+                }
+            }
+            IssueType::Confirm => {
+                // 不同议长的轮次不会相同，其他议长的轮次必须更高；轮次相同时
+                // 只能是承诺过该轮次的议长本人
+                let highest = acceptor.highest();
+                if ballot > highest || ballot == highest && ballot.node == sender {
+                    *self.lease.borrow_mut() = Some(Lease::grant(&sender, now));
+                }
+                Issue::new(Bytes::new(), id, IssueType::Confirmed).with_ballot(ballot.max(highest))
+            }
+            other => return Err(anyhow!("expect a proposal, got {:?}", other)),
+        };
+
+        let mail = Mail::new(
+            self.mail_box.id(),
+            vec![Recipient::Node(sender)],
+            reply.with_sender(self.mail_box.id()),
+        );
+        self.mail_box.put_mail(mail)
+    }
+}
+
+/// 学习者：不参与表决，只复制议长推送的决议
+///
+/// 按编号顺序写入日志；收到的决议与已有的日志之间有空缺时丢弃，每隔
+/// `SYNC_INTERVAL` 向议长请求从下一个编号起的决议。
+pub struct Learner {
+    mail_box: MailBox<String, Issue>,
+    logbackend: Box<dyn LogBackend>,
+    /// 下一个需要的编号
+    next: Cell<u64>,
+    /// 上次请求补发的时间
+    synced_at: Cell<Option<Duration>>,
+}
+
+impl Learner {
+    /// 从日志中已有的决议之后继续学习；日志无法读取时返回错误
+    pub fn new(
+        mail_box: MailBox<Address, Issue>,
+        log_backend: Box<dyn LogBackend>,
+    ) -> Result<Self> {
+        let last_id = log_backend.last_id()?.unwrap_or(0);
+        Ok(Self {
+            mail_box,
+            logbackend: log_backend,
+            next: Cell::new(last_id + 1),
+            synced_at: Cell::new(None),
+        })
+    }
+
+    /// 向议长发送心跳并按时请求补发，收发一轮邮件，写入收到的决议；
+    /// 没有新邮件时最多等待 `timeout`
+    pub fn tick(&self, timeout: Duration) -> Result<()> {
+        let masters = self.mail_box.address_book().group(MASTER_GROUP);
+        self.mail_box.heartbeat(&masters)?;
+
+        let now = self.mail_box.now();
+        let due = self
+            .synced_at
+            .get()
+            .is_none_or(|at| now >= at + SYNC_INTERVAL);
+        if due && !masters.is_empty() {
+            self.synced_at.set(Some(now));
+            let request = Issue::new(Bytes::new(), self.next.get(), IssueType::Resolution)
+                .with_sender(self.mail_box.id());
+            let to = masters.into_iter().map(Recipient::Node).collect();
+            self.mail_box
+                .put_mail(Mail::new(self.mail_box.id(), to, request))?;
+        }
+
+        self.mail_box.pump(timeout)?;
+        while let Ok(mail) = self.mail_box.get_mail() {
+            let _ = self.learn(mail.body());
+        }
+        Ok(())
+    }
+
+    fn learn(&self, issue: Issue) -> Result<()> {
+        if issue.issue_type() != IssueType::Resolution {
+            return Err(anyhow!("expect resolutions, got {:?}", issue.issue_type()));
+        }
+        let resolutions: Resolutions = bincode::deserialize(&issue.content())?;
+        let next = self.next.get();
+        if resolutions.from > next || resolutions.to < next {
+            return Ok(());
+        }
+
+        for (slot, entry) in resolutions.entries {
+            if slot >= next {
+                self.logbackend.write(slot, entry.encode()?)?;
+            }
+        }
+        self.next.set(resolutions.to + 1);
+        Ok(())
+    }
+
+    /// 已学习到的最后一个编号
+    pub fn learned(&self) -> u64 {
+        self.next.get() - 1
+    }
+
+    /// 读取日志，会话命令只返回命令的内容
+    pub fn get_log(&self, id: u64) -> Result<Bytes> {
+        read_entry(&*self.logbackend, id)
+    }
+
+    /// 停止前将已记录的决议落盘
+    pub fn close(&self) -> Result<()> {
+        self.logbackend.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{rc::Rc, sync::Arc, time::Duration};
+
+    use bytes::Bytes;
+
+    use super::{
+        Learner, Master, NodeRole, Worker, BACKOFF_INITIAL, LEARNER_GROUP, MASTER_GROUP,
+        REVOKE_TIMEOUT, ROUND_TIMEOUT, SYNC_INTERVAL, WORKER_GROUP,
+    };
+    use crate::{
+        acceptor::{Prepare, Promise},
+        address_book::{AddressBook, Recipient},
+        auth::Authenticator,
+        clock::ManualClock,
+        codec::Codec,
+        connection::Switch,
+        issue::{Ballot, Entry, Issue, IssueType},
+        lock::{LockError, LockOp},
+        logbackend::{FileLogBackend, HeapLogBackend, LogBackend, Queryable, Writable},
+        mailbox::{Mail, MailBox},
+        membership::{Change, ALPHA},
+        mencius::Protocol,
+        quorum::{Quorum, Quorums},
+        read::{Consistency, LEASE_DURATION},
+        session::Command,
+    };
+
+    fn master(switch: &Switch, clock: &ManualClock) -> Master {
+        master_with_log(switch, clock, HeapLogBackend::new())
+    }
+
+    fn master_with_log(
+        switch: &Switch,
+        clock: &ManualClock,
+        log: impl LogBackend + 'static,
+    ) -> Master {
+        open_master(switch, clock, log).unwrap().with_seed(7)
+    }
+
+    fn open_master(
+        switch: &Switch,
+        clock: &ManualClock,
+        log: impl LogBackend + 'static,
+    ) -> anyhow::Result<Master> {
+        let mut book = AddressBook::new();
+        for id in ["master", "worker-1", "worker-2", "worker-3"] {
+            book.insert(id.to_string(), id.to_string());
+        }
+        for id in ["worker-1", "worker-2", "worker-3"] {
+            book.join(WORKER_GROUP, id.to_string());
+        }
+        let mail_box = MailBox::new(
+            "master".to_string(),
+            Box::new(switch.connect("master").unwrap()),
+            book,
+            Authenticator::new(b"secret"),
+            Codec::Binary,
+        )
+        .with_clock(Arc::new(clock.clone()));
+        Master::new(mail_box, Box::new(log))
+    }
+
+    /// 三名议员都承诺议长的新轮次，完成第一阶段；`tick` 先发出准备
+    fn lead(master: &Master) {
+        if master.prepared.get() {
+            return;
+        }
+        master.tick(Duration::ZERO).unwrap();
+        let promise = Bytes::from(bincode::serialize(&Promise::default()).unwrap());
+        for voter in ["worker-1", "worker-2", "worker-3"] {
+            let issue =
+                Issue::new(promise.clone(), 1, IssueType::Promise).with_ballot(master.ballot());
+            let _ = master.handle(voter, issue);
+        }
+        assert!(master.prepared.get());
+    }
+
+    /// 客户端提交的日志写入日志的内容
+    fn client_entry(content: &str) -> Bytes {
+        Entry::client(Bytes::from(content.to_string()))
+            .encode()
+            .unwrap()
+    }
+
+    /// 议长当前轮次的投票
+    fn vote(master: &Master, content: &str, id: u64) -> Issue {
+        Issue::new(Bytes::from(content.to_string()), id, IssueType::Vote)
+            .with_ballot(master.ballot())
+    }
+
+    #[test]
+    fn count_distinct_voters_test() {
+        let switch = Switch::new();
+        let master = master(&switch, &ManualClock::new());
+        let id = master.emmit_new_proposal(Bytes::from("x = 1")).unwrap();
+        lead(&master);
+        let vote = |content, id| vote(&master, content, id);
+
+        assert!(master.handle("worker-1", vote("x = 1", id)).is_err());
+        // 重复的投票不计入
+        assert!(master.handle("worker-1", vote("x = 1", id)).is_err());
+        assert!(master.get_log(id).is_err());
+        // 内容不一致、轮次不一致、或不是议员的投票不计入
+        assert!(master.handle("worker-2", vote("x = 2", id)).is_err());
+        let other_ballot = vote("x = 1", id).with_ballot(Ballot::new(9, "master".to_string()));
+        assert!(master.handle("worker-2", other_ballot).is_err());
+        assert!(master.handle("stranger", vote("x = 1", id)).is_err());
+        assert!(master.get_log(id).is_err());
+
+        master.handle("worker-2", vote("x = 1", id)).unwrap();
+        assert_eq!(master.get_log(id).unwrap(), Bytes::from("x = 1"));
+        // 表决完成后迟到的投票
+        assert!(master.handle("worker-3", vote("x = 1", id)).is_err());
+    }
+
+    #[test]
+    fn configured_quorum_test() {
+        let switch = Switch::new();
+        let quorums = Quorums {
+            phase1: Quorum::Size(2),
+            phase2: Quorum::Size(3),
+            weights: [("worker-3".to_string(), 2)].into(),
+        };
+        let master = master(&switch, &ManualClock::new()).with_quorums(quorums);
+        let id = master.emmit_new_proposal(Bytes::from("x = 1")).unwrap();
+        lead(&master);
+
+        // worker-3 有两票
+        assert!(master
+            .handle("worker-1", vote(&master, "x = 1", id))
+            .is_err());
+        master
+            .handle("worker-3", vote(&master, "x = 1", id))
+            .unwrap();
+        assert!(master.get_log(id).is_ok());
+
+        // 移除 worker-3 后剩两票，达不到第二阶段的法定人数
+        let remove = |id: &str| Change::Remove { id: id.to_string() };
+        assert!(master.propose_change(remove("worker-3")).is_err());
+        assert!(master.propose_change(remove("worker-1")).is_ok());
+    }
+
+    #[test]
+    fn retry_with_higher_ballot_after_reject_test() {
+        let (switch, clock) = (Switch::new(), ManualClock::new());
+        let master = master(&switch, &clock);
+        let id = master.emmit_new_proposal(Bytes::from("x = 1")).unwrap();
+        lead(&master);
+        let first = master.ballot();
+
+        // worker-1 已承诺了另一名议长的轮次5，本轮的投票不再计入
+        let promised = Ballot::new(5, "master-2".to_string());
+        let reject = Issue::new(Bytes::new(), id, IssueType::Reject).with_ballot(promised);
+        master.handle("worker-1", reject).unwrap();
+        for voter in ["worker-2", "worker-3"] {
+            let vote = vote(&master, "x = 1", id).with_ballot(first.clone());
+            assert!(master.handle(voter, vote).is_err());
+        }
+        assert!(!master.prepared.get());
+
+        // 随机退避后以轮次6重新准备，再重新提议
+        clock.advance(BACKOFF_INITIAL);
+        lead(&master);
+        assert_eq!(master.ballot(), Ballot::new(6, "master".to_string()));
+        assert!(master
+            .vote_table
+            .borrow()
+            .contains_key(&(master.ballot(), id)));
+
+        assert!(master
+            .handle("worker-2", vote(&master, "x = 1", id))
+            .is_err());
+        master
+            .handle("worker-3", vote(&master, "x = 1", id))
+            .unwrap();
+        let outcomes = master.take_outcomes();
+        assert_eq!(outcomes.len(), 1);
+        assert!(outcomes[0].0 == id && outcomes[0].1.is_ok());
+    }
+
+    #[test]
+    fn proposal_timeout_test() {
+        let (switch, clock) = (Switch::new(), ManualClock::new());
+        let master = master(&switch, &clock).with_proposal_timeout(Duration::from_secs(1));
+        let id = master.emmit_new_proposal(Bytes::from("x = 1")).unwrap();
+        lead(&master);
+
+        // 一轮表决超时，以同一轮次重新提议
+        clock.advance(Duration::from_millis(600));
+        master.tick(Duration::ZERO).unwrap();
+        assert_eq!(master.proposals.borrow()[&id].attempts, 2);
+        assert!(master
+            .vote_table
+            .borrow()
+            .contains_key(&(master.ballot(), id)));
+        assert!(master.take_outcomes().is_empty());
+
+        // 超过提案的表决时间，报告一次失败
+        clock.advance(Duration::from_millis(500));
+        master.tick(Duration::ZERO).unwrap();
+        let outcomes = master.take_outcomes();
+        assert!(outcomes.len() == 1 && outcomes[0].0 == id && outcomes[0].1.is_err());
+        master.tick(Duration::ZERO).unwrap();
+        assert!(master.take_outcomes().is_empty());
+
+        // 该编号仍继续表决，直到形成决议
+        for voter in ["worker-1", "worker-2"] {
+            let _ = master.handle(voter, vote(&master, "x = 1", id));
+        }
+        assert_eq!(master.get_log(id).unwrap(), Bytes::from("x = 1"));
+        assert!(master.take_outcomes().is_empty());
+    }
+
+    #[test]
+    fn worker_rejects_lower_ballot_test() {
+        let switch = Switch::new();
+        let mut book = AddressBook::new();
+        for id in ["master", "worker-1"] {
+            book.insert(id.to_string(), id.to_string());
+        }
+        let open = |id: &str| -> MailBox<String, Issue> {
+            MailBox::new(
+                id.to_string(),
+                Box::new(switch.connect(id).unwrap()),
+                book.clone(),
+                Authenticator::new(b"secret"),
+                Codec::Binary,
+            )
+        };
+        let master = open("master");
+        let worker = Worker::new(open("worker-1"), Box::new(HeapLogBackend::new())).unwrap();
+
+        let ballot = |round| Ballot::new(round, "master".to_string());
+        let prepare = Bytes::from(bincode::serialize(&Prepare { to: u64::MAX }).unwrap());
+        let issues = [
+            Issue::new(Bytes::from("x"), 1, IssueType::Proposal).with_ballot(ballot(5)),
+            Issue::new(Bytes::from("y"), 1, IssueType::Proposal).with_ballot(ballot(3)),
+            Issue::new(prepare.clone(), 1, IssueType::Prepare).with_ballot(ballot(4)),
+            Issue::new(prepare, 1, IssueType::Prepare).with_ballot(ballot(6)),
+        ];
+        for issue in issues {
+            let to = vec![Recipient::Node("worker-1".to_string())];
+            master.put_mail(Mail::new(master.id(), to, issue)).unwrap();
+        }
+        master.flush().unwrap();
+        worker.tick(Duration::from_secs(1)).unwrap();
+        worker.mail_box().flush().unwrap();
+
+        let replies: Vec<Issue> = (0..4)
+            .map(|_| master.recv_timeout(Duration::from_secs(1)).unwrap().body())
+            .collect();
+        let summary: Vec<(IssueType, u64, u64)> = replies
+            .iter()
+            .map(|issue| (issue.issue_type(), issue.id(), issue.ballot().round))
+            .collect();
+        assert_eq!(
+            summary,
+            vec![
+                (IssueType::Vote, 1, 5),
+                (IssueType::Reject, 1, 5),
+                (IssueType::Reject, 1, 5),
+                (IssueType::Promise, 1, 6),
+            ]
+        );
+        // 承诺带有接受过的提案
+        let promise: Promise = bincode::deserialize(&replies[3].content()).unwrap();
+        assert_eq!(promise.accepted.len(), 1);
+        assert_eq!(promise.accepted[0].ballot, ballot(5));
+        assert_eq!(promise.accepted[0].entry, Entry::client(Bytes::from("x")));
+    }
+
+    #[test]
+    fn membership_change_through_log_test() {
+        let switch = Switch::new();
+        let master = master(&switch, &ManualClock::new());
+        let add = |id: &str| Change::Add {
+            id: id.to_string(),
+            address: id.to_string(),
+        };
+        assert!(master.propose_change(add("worker-1")).is_err());
+
+        let id = master.propose_change(add("worker-4")).unwrap();
+        lead(&master);
+        let entry = add("worker-4").entry().unwrap();
+        let change = Issue::new(entry.content, id, IssueType::Vote)
+            .with_kind(entry.kind)
+            .with_ballot(master.ballot());
+        assert!(master.handle("worker-1", change.clone()).is_err());
+        master.handle("worker-2", change).unwrap();
+        assert_eq!(master.members().len(), 4);
+        // 成员变更不是客户端日志，不能读取
+        assert!(master.get_log(id).is_err());
+        assert!(master
+            .mail_box()
+            .address_book()
+            .resolve("worker-4")
+            .is_some());
+
+        // 在 `ALPHA` 个编号之后才生效，之前的议题仍由三名议员表决，过半数为2；
+        // 变更生效后的议员也要承诺议长的轮次
+        let next = master.emmit_new_proposal(Bytes::from("x = 1")).unwrap();
+        assert!(!master.prepared.get());
+        lead(&master);
+        let vote = |content, id| vote(&master, content, id);
+        assert!(master.handle("worker-4", vote("x = 1", next)).is_err());
+        assert!(master.handle("worker-1", vote("x = 1", next)).is_err());
+        master.handle("worker-2", vote("x = 1", next)).unwrap();
+        let membership = master.membership.borrow();
+        assert_eq!(membership.at(id + ALPHA - 1).len(), 3);
+        assert_eq!(membership.at(id + ALPHA).len(), 4);
+    }
+
+    #[test]
+    fn replay_membership_on_restart_test() {
+        let log = HeapLogBackend::new();
+        log.write(1, client_entry("x = 1")).unwrap();
+        let remove = Change::Remove {
+            id: "worker-3".to_string(),
+        };
+        log.write(2, remove.entry().unwrap().encode().unwrap())
+            .unwrap();
+
+        let switch = Switch::new();
+        let master = master_with_log(&switch, &ManualClock::new(), log);
+        assert_eq!(master.members(), vec!["worker-1", "worker-2"]);
+        assert_eq!(
+            master.mail_box().address_book().group(WORKER_GROUP).len(),
+            2
+        );
+    }
+
+    #[test]
+    fn pipeline_window_test() {
+        let switch = Switch::new();
+        let master = master(&switch, &ManualClock::new()).with_window(2);
+        for value in ["x = 1", "x = 2", "x = 3"] {
+            master.emmit_new_proposal(Bytes::from(value)).unwrap();
+        }
+        // 完成第一阶段之前所有议题排队，之后窗口之外的议题排队等待提议
+        assert!(master.vote_table.borrow().is_empty());
+        lead(&master);
+        assert_eq!(master.vote_table.borrow().len(), 2);
+        let third = (master.ballot(), 3);
+
+        // 编号2先形成决议，等编号1形成决议后才按顺序应用
+        for voter in ["worker-1", "worker-2"] {
+            let _ = master.handle(voter, vote(&master, "x = 2", 2));
+        }
+        assert_eq!(master.get_log(2).unwrap(), Bytes::from("x = 2"));
+        assert!(master.take_outcomes().is_empty());
+        master.tick(Duration::ZERO).unwrap();
+        assert!(!master.vote_table.borrow().contains_key(&third));
+
+        for voter in ["worker-1", "worker-2"] {
+            let _ = master.handle(voter, vote(&master, "x = 1", 1));
+        }
+        let applied: Vec<u64> = master.take_outcomes().iter().map(|(id, _)| *id).collect();
+        assert_eq!(applied, vec![1, 2]);
+        master.tick(Duration::ZERO).unwrap();
+        assert!(master.vote_table.borrow().contains_key(&third));
+    }
+
+    #[test]
+    fn resubmitted_command_is_decided_once_test() {
+        let switch = Switch::new();
+        let master = master(&switch, &ManualClock::new());
+        let command = |seq| Command::new("alice".to_string(), seq, Bytes::from("x = 1"));
+
+        // 表决中重复提交，等待同一个议题
+        let id = master.submit(command(1)).unwrap();
+        assert_eq!(master.submit(command(1)).unwrap(), id);
+        assert_eq!(master.proposals.borrow().len(), 1);
+        lead(&master);
+        let vote = |entry: Entry, id| {
+            Issue::new(entry.content, id, IssueType::Vote)
+                .with_kind(entry.kind)
+                .with_ballot(master.ballot())
+        };
+
+        for voter in ["worker-1", "worker-2"] {
+            let _ = master.handle(voter, vote(command(1).entry().unwrap(), id));
+        }
+        assert_eq!(master.get_log(id).unwrap(), Bytes::from("x = 1"));
+        assert_eq!(master.take_outcomes().len(), 1);
+
+        // 形成决议后重复提交，立即返回原先的编号
+        assert_eq!(master.submit(command(1)).unwrap(), id);
+        let outcomes = master.take_outcomes();
+        assert!(outcomes[0].0 == id && outcomes[0].1.is_ok());
+        assert!(master.proposals.borrow().is_empty());
+
+        let next = master.submit(command(2)).unwrap();
+        assert_eq!(next, id + 1);
+        for voter in ["worker-1", "worker-2"] {
+            let _ = master.handle(voter, vote(command(2).entry().unwrap(), next));
+        }
+        assert!(master.submit(command(1)).is_err());
+
+        // 重启后由日志重建会话表
+        let log = HeapLogBackend::new();
+        log.write(1, command(1).entry().unwrap().encode().unwrap())
+            .unwrap();
+        let restarted = master_with_log(&Switch::new(), &ManualClock::new(), log);
+        assert_eq!(restarted.submit(command(1)).unwrap(), 1);
+        assert!(restarted.proposals.borrow().is_empty());
+    }
+
+    #[test]
+    fn lock_survives_restart_test() {
+        let (switch, clock) = (Switch::new(), ManualClock::new());
+        let master = master(&switch, &clock);
+        let decide = |master: &Master, op| {
+            let id = master.lock("db".to_string(), op).unwrap();
+            lead(master);
+            let entry = master.proposals.borrow()[&id].entry.clone();
+            for voter in ["worker-1", "worker-2"] {
+                let vote =
+                    Issue::new(entry.content.clone(), id, IssueType::Vote).with_kind(entry.kind);
+                let _ = master.handle(voter, vote.with_ballot(master.ballot()));
+            }
+            let (slot, outcome) = master.take_outcomes().pop().unwrap();
+            assert_eq!(slot, id);
+            (id, outcome)
+        };
+        let acquire = |owner: &str| LockOp::Acquire {
+            owner: owner.to_string(),
+            ttl_ms: 1000,
+        };
+
+        // 令牌为获取锁的议题编号
+        let (token, outcome) = decide(&master, acquire("alice"));
+        assert!(outcome.is_ok());
+        let (_, outcome) = decide(&master, acquire("bob"));
+        assert!(outcome.unwrap_err().is::<LockError>());
+        // 锁命令不是客户端日志，不能读取
+        assert!(master.get_log(token).is_err());
+
+        // 重启后由日志重建锁表，锁仍由原来的令牌持有
+        let log = HeapLogBackend::new();
+        for id in 1..=master.counter.get() {
+            log.write(id, master.logbackend.query(id).unwrap()).unwrap();
+        }
+        let restarted = master_with_log(&Switch::new(), &clock, log);
+        assert_eq!(restarted.locks.borrow().holder("db").unwrap().token, token);
+        let (_, outcome) = decide(&restarted, acquire("bob"));
+        assert!(outcome.is_err());
+
+        // 过期后可以被其他客户端获取，原来的令牌不能再续期
+        clock.advance(Duration::from_millis(1000));
+        let (next, outcome) = decide(&restarted, acquire("bob"));
+        assert!(outcome.is_ok() && next > token);
+        let renew = LockOp::Renew {
+            owner: "alice".to_string(),
+            token,
+            ttl_ms: 1000,
+        };
+        assert!(decide(&restarted, renew).1.is_err());
+    }
+
+    #[test]
+    fn master_restarts_from_a_log_file_test() {
+        let path = std::env::temp_dir().join(format!("somepox-master-{}.log", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let file_name = path.to_str().unwrap();
+        let clock = ManualClock::new();
+
+        let master = master_with_log(
+            &Switch::new(),
+            &clock,
+            FileLogBackend::open(file_name).unwrap(),
+        );
+        let id = master.emmit_new_proposal(Bytes::from("x = 1")).unwrap();
+        lead(&master);
+        for voter in ["worker-1", "worker-2"] {
+            let _ = master.handle(voter, vote(&master, "x = 1", id));
+        }
+        assert_eq!(master.get_log(id).unwrap(), Bytes::from("x = 1"));
+        let ballot = master.ballot();
+        assert!(ballot.round > 0);
+        master.close().unwrap();
+        drop(master);
+
+        // 重启后接续文件中的决议与轮数
+        let restarted = master_with_log(
+            &Switch::new(),
+            &clock,
+            FileLogBackend::open(file_name).unwrap(),
+        );
+        assert_eq!(restarted.get_log(id).unwrap(), Bytes::from("x = 1"));
+        assert_eq!(restarted.counter.get(), id);
+        assert_eq!(restarted.ballot(), ballot);
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn refuse_to_start_with_a_corrupt_round_test() {
+        // 读不出保存的轮数时不能从0开始，以免重复使用用过的轮次
+        let log = HeapLogBackend::new();
+        log.write(0, Bytes::from_static(b"\x01")).unwrap();
+        assert!(open_master(&Switch::new(), &ManualClock::new(), log).is_err());
+    }
+
+    /// 两名议长与三名议员，日志中都已有编号1的决议
+    fn cluster(switch: &Switch, clock: &ManualClock) -> (Master, Master, Vec<Worker>) {
+        let workers = ["worker-1", "worker-2", "worker-3"];
+        let mut book = AddressBook::new();
+        for id in ["master", "master-2"] {
+            book.insert(id.to_string(), id.to_string());
+            book.join(MASTER_GROUP, id.to_string());
+        }
+        for id in workers {
+            book.insert(id.to_string(), id.to_string());
+            book.join(WORKER_GROUP, id.to_string());
+        }
+        let open = |id: &str| {
+            MailBox::new(
+                id.to_string(),
+                Box::new(switch.connect(id).unwrap()),
+                book.clone(),
+                Authenticator::new(b"secret"),
+                Codec::Binary,
+            )
+            .with_clock(Arc::new(clock.clone()))
+        };
+        let open_master = |id: &str| {
+            let log = HeapLogBackend::new();
+            log.write(1, client_entry("x = 1")).unwrap();
+            Master::new(open(id), Box::new(log)).unwrap().with_seed(7)
+        };
+
+        let first = open_master("master");
+        let second = open_master("master-2").with_ballot(1);
+        let workers = workers
+            .into_iter()
+            .map(|id| Worker::new(open(id), Box::new(HeapLogBackend::new())).unwrap())
+            .collect();
+        (first, second, workers)
+    }
+
+    fn run(masters: &[&Master], workers: &[Worker]) {
+        for _ in 0..10 {
+            for master in masters {
+                master.tick(Duration::ZERO).unwrap();
+            }
+            for worker in workers {
+                worker.tick(Duration::ZERO).unwrap();
+            }
+        }
+    }
+
+    fn cut_off(switch: &Switch, id: &str) {
+        for worker in ["worker-1", "worker-2", "worker-3"] {
+            switch.cut(id, worker);
+        }
+    }
+
+    #[test]
+    fn no_stale_linearizable_read_under_partition_test() {
+        let (switch, clock) = (Switch::new(), ManualClock::new());
+        let (first, second, workers) = cluster(&switch, &clock);
+
+        let read = first.read(Consistency::Linearizable);
+        assert!(first.take_reads().is_empty(), "waits for a round");
+        run(&[&first], &workers);
+        assert_eq!(first.take_reads()[0].0, read);
+        assert!(first.take_reads().is_empty());
+
+        // 第一名议长被隔离，租约到期后第二名议长写入编号2
+        cut_off(&switch, "master");
+        clock.advance(LEASE_DURATION);
+        let id = second.emmit_new_proposal(Bytes::from("x = 2")).unwrap();
+        run(&[&first, &second], &workers);
+        assert_eq!(second.get_log(id).unwrap(), Bytes::from("x = 2"));
+
+        // 过期读取读不到编号2，线性一致的读取无法确认领导权
+        first.read(Consistency::Stale);
+        assert!(first.take_reads()[0].1.is_ok());
+        assert!(first.get_log(id).is_err());
+        first.read(Consistency::Linearizable);
+        // 租约读请求等待下一轮确认
+        first.read(Consistency::Lease);
+        for _ in 0..2 {
+            run(&[&first], &workers);
+            clock.advance(ROUND_TIMEOUT);
+        }
+        run(&[&first], &workers);
+        let reads = first.take_reads();
+        assert_eq!(reads.len(), 2);
+        assert!(reads.iter().all(|(_, outcome)| outcome.is_err()));
+
+        // 恢复通信后，议员已承诺了更高的轮次
+        switch.heal();
+        first.read(Consistency::Linearizable);
+        run(&[&first], &workers);
+        let reads = first.take_reads();
+        assert!(reads[0].1.is_err());
+        assert_eq!(
+            first.status().ballot,
+            Ballot::new(2, "master-2".to_string())
+        );
+
+        // 第二名议长可以线性一致地读取
+        second.read(Consistency::Linearizable);
+        run(&[&second], &workers);
+        assert!(second.take_reads()[0].1.is_ok());
+    }
+
+    #[test]
+    fn lease_excludes_other_masters_test() {
+        let (switch, clock) = (Switch::new(), ManualClock::new());
+        let (first, second, workers) = cluster(&switch, &clock);
+
+        first.read(Consistency::Lease);
+        run(&[&first], &workers);
+        assert!(first.take_reads()[0].1.is_ok());
+
+        // 租约有效时直接读取；议员拒绝第二名议长的提案
+        cut_off(&switch, "master");
+        let id = second.emmit_new_proposal(Bytes::from("x = 2")).unwrap();
+        run(&[&second], &workers);
+        assert!(second.get_log(id).is_err());
+        first.read(Consistency::Lease);
+        assert!(first.take_reads()[0].1.is_ok());
+
+        // 租约到期后第二名议长重新提议，第一名议长不能再直接读取
+        clock.advance(LEASE_DURATION);
+        run(&[&first, &second], &workers);
+        assert_eq!(second.get_log(id).unwrap(), Bytes::from("x = 2"));
+        first.read(Consistency::Lease);
+        assert!(first.take_reads().is_empty());
+        clock.advance(ROUND_TIMEOUT);
+        run(&[&first], &workers);
+        assert!(first.take_reads()[0].1.is_err());
+    }
+
+    /// 在同一份日志上重启议员
+    fn restart(
+        switch: &Switch,
+        clock: &ManualClock,
+        worker: Worker,
+        log: &Rc<HeapLogBackend>,
+    ) -> Worker {
+        let (id, book) = (worker.mail_box().id(), worker.mail_box().address_book());
+        drop(worker);
+        let mail_box = MailBox::new(
+            id.clone(),
+            Box::new(switch.connect(&id).unwrap()),
+            book,
+            Authenticator::new(b"secret"),
+            Codec::Binary,
+        )
+        .with_clock(Arc::new(clock.clone()));
+        Worker::new(mail_box, Box::new(log.clone())).unwrap()
+    }
+
+    #[test]
+    fn restarted_worker_waits_out_leases_test() {
+        let (switch, clock) = (Switch::new(), ManualClock::new());
+        let (first, second, workers) = cluster(&switch, &clock);
+        let logs: Vec<_> = (0..3).map(|_| Rc::new(HeapLogBackend::new())).collect();
+        let restart_all = |workers: Vec<Worker>| -> Vec<Worker> {
+            workers
+                .into_iter()
+                .zip(logs.iter())
+                .map(|(worker, log)| restart(&switch, &clock, worker, log))
+                .collect()
+        };
+        let workers = restart_all(workers);
+
+        first.read(Consistency::Lease);
+        run(&[&first], &workers);
+        assert!(first.take_reads()[0].1.is_ok());
+
+        // 议员重启后忘记了授予第一名议长的租约，仍在租约的时长内拒绝第二名议长
+        let workers = restart_all(workers);
+        cut_off(&switch, "master");
+        let id = second.emmit_new_proposal(Bytes::from("x = 2")).unwrap();
+        run(&[&second], &workers);
+        assert!(second.get_log(id).is_err());
+        first.read(Consistency::Lease);
+        assert!(first.take_reads()[0].1.is_ok());
+
+        clock.advance(LEASE_DURATION);
+        run(&[&second], &workers);
+        assert_eq!(second.get_log(id).unwrap(), Bytes::from("x = 2"));
+    }
+
+    #[test]
+    fn recover_accepted_values_test() {
+        let (switch, clock) = (Switch::new(), ManualClock::new());
+        let (first, second, workers) = cluster(&switch, &clock);
+
+        // 第一名议长的编号2形成了决议，编号3只有 worker-1 接受
+        first.emmit_new_proposal(Bytes::from("x = 2")).unwrap();
+        run(&[&first], &workers);
+        assert_eq!(first.get_log(2).unwrap(), Bytes::from("x = 2"));
+        for worker in ["worker-2", "worker-3"] {
+            switch.cut("master", worker);
+        }
+        first.emmit_new_proposal(Bytes::from("x = 3")).unwrap();
+        run(&[&first], &workers);
+        assert!(first.get_log(3).is_err());
+
+        // 第二名议长不知道编号2的决议，准备时从承诺中恢复，自己的议题被取代
+        let id = second.emmit_new_proposal(Bytes::from("y = 2")).unwrap();
+        assert_eq!(id, 2);
+        run(&[&second], &workers);
+        assert_eq!(second.get_log(2).unwrap(), Bytes::from("x = 2"));
+        assert_eq!(second.get_log(3).unwrap(), Bytes::from("x = 3"));
+        let outcomes = second.take_outcomes();
+        assert!(outcomes.len() == 1 && outcomes[0].0 == id && outcomes[0].1.is_err());
+
+        // 第一名议长恢复通信后被拒绝，以更高的轮次重新准备，仍得到相同的决议
+        switch.heal();
+        clock.advance(ROUND_TIMEOUT);
+        run(&[&first], &workers);
+        clock.advance(BACKOFF_INITIAL * 2);
+        run(&[&first], &workers);
+        assert_eq!(first.get_log(3).unwrap(), Bytes::from("x = 3"));
+        assert!(first.ballot() > second.ballot());
+    }
+
+    /// 一名议长、两名议员、一名见证者与一名学习者
+    fn replicas(switch: &Switch, clock: &ManualClock) -> (Master, Vec<Worker>, Learner) {
+        let mut book = AddressBook::new();
+        for id in ["master", "worker-1", "worker-2", "witness", "learner"] {
+            book.insert(id.to_string(), id.to_string());
+        }
+        book.join(MASTER_GROUP, "master".to_string());
+        for id in ["worker-1", "worker-2", "witness"] {
+            book.join(WORKER_GROUP, id.to_string());
+        }
+        book.join(LEARNER_GROUP, "learner".to_string());
+        let open = |id: &str| {
+            MailBox::new(
+                id.to_string(),
+                Box::new(switch.connect(id).unwrap()),
+                book.clone(),
+                Authenticator::new(b"secret"),
+                Codec::Binary,
+            )
+            .with_clock(Arc::new(clock.clone()))
+        };
+
+        let master = Master::new(open("master"), Box::new(HeapLogBackend::new()))
+            .unwrap()
+            .with_seed(7)
+            .with_witnesses(["witness".to_string()]);
+        let workers = ["worker-1", "worker-2", "witness"]
+            .into_iter()
+            .map(|id| {
+                Worker::new(open(id), Box::new(HeapLogBackend::new()))
+                    .unwrap()
+                    .with_witness(id == "witness")
+            })
+            .collect();
+        let learner = Learner::new(open("learner"), Box::new(HeapLogBackend::new())).unwrap();
+        (master, workers, learner)
+    }
+
+    #[test]
+    fn witness_votes_without_content_test() {
+        let (switch, clock) = (Switch::new(), ManualClock::new());
+        let (master, workers, _) = replicas(&switch, &clock);
+
+        // 一名议员与见证者的投票即可形成决议，见证者的投票不带内容
+        switch.cut("master", "worker-2");
+        let id = master.emmit_new_proposal(Bytes::from("x = 1")).unwrap();
+        run(&[&master], &workers);
+        assert_eq!(master.get_log(id).unwrap(), Bytes::from("x = 1"));
+
+        let status = master.status();
+        let roles: Vec<_> = status.senators.iter().map(|node| node.role).collect();
+        assert!(roles.contains(&NodeRole::Witness));
+        assert_eq!(status.learners[0].role, NodeRole::Learner);
+    }
+
+    #[test]
+    fn learner_replicates_resolutions_test() {
+        let (switch, clock) = (Switch::new(), ManualClock::new());
+        let (master, workers, learner) = replicas(&switch, &clock);
+
+        // 学习者断开时形成的决议，重新连接后请求补发
+        switch.cut("master", "learner");
+        for value in ["x = 1", "x = 2"] {
+            master.emmit_new_proposal(Bytes::from(value)).unwrap();
+        }
+        run(&[&master], &workers);
+        learner.tick(Duration::ZERO).unwrap();
+        assert_eq!(learner.learned(), 0);
+
+        switch.heal();
+        clock.advance(SYNC_INTERVAL);
+        for _ in 0..3 {
+            learner.tick(Duration::ZERO).unwrap();
+            master.tick(Duration::ZERO).unwrap();
+        }
+        learner.tick(Duration::ZERO).unwrap();
+        assert_eq!(learner.learned(), 2);
+        assert_eq!(learner.get_log(2).unwrap(), Bytes::from("x = 2"));
+
+        // 之后的决议直接推送给学习者
+        master.emmit_new_proposal(Bytes::from("x = 3")).unwrap();
+        run(&[&master], &workers);
+        learner.tick(Duration::ZERO).unwrap();
+        assert_eq!(learner.learned(), 3);
+        assert_eq!(learner.get_log(3).unwrap(), Bytes::from("x = 3"));
+
+        // 不是学习者的节点不能请求补发
+        assert!(master.sync("worker-1", 1).is_err());
+    }
+
+    #[test]
+    fn mencius_masters_share_slots_test() {
+        let (switch, clock) = (Switch::new(), ManualClock::new());
+        let (first, second, workers) = cluster(&switch, &clock);
+        let (first, second) = (
+            first.with_protocol(Protocol::Mencius),
+            second.with_protocol(Protocol::Mencius),
+        );
+
+        let settle = || {
+            run(&[&first, &second], &workers);
+            clock.advance(ROUND_TIMEOUT);
+            run(&[&first, &second], &workers);
+            run(&[&first, &second], &workers);
+        };
+
+        // 两名议长轮流拥有编号，编号1已有决议
+        let id = first.emmit_new_proposal(Bytes::from("x = 3")).unwrap();
+        assert_eq!(id, 3);
+        assert_eq!(second.emmit_new_proposal(Bytes::from("y = 2")).unwrap(), 2);
+        settle();
+        for master in [&first, &second] {
+            assert_eq!(master.get_log(2).unwrap(), Bytes::from("y = 2"));
+            assert_eq!(master.get_log(3).unwrap(), Bytes::from("x = 3"));
+        }
+        assert_eq!(first.take_outcomes().len(), 1);
+
+        // 空闲的议长跳过自己的编号，之后的编号才能应用
+        assert_eq!(first.emmit_new_proposal(Bytes::from("x = 5")).unwrap(), 5);
+        settle();
+        assert!(second.get_log(4).is_err());
+        let applied: Vec<u64> = first.take_outcomes().iter().map(|(id, _)| *id).collect();
+        assert_eq!(applied, vec![5]);
+
+        // 与另一名议长断开时，决议无法应用，读请求也要等待
+        switch.cut("master", "master-2");
+        assert_eq!(first.emmit_new_proposal(Bytes::from("x = 7")).unwrap(), 7);
+        let read = first.read(Consistency::Linearizable);
+        settle();
+        assert_eq!(first.get_log(7).unwrap(), Bytes::from("x = 7"));
+        assert!(first.take_outcomes().is_empty() && first.take_reads().is_empty());
+
+        switch.heal();
+        settle();
+        assert_eq!(first.take_outcomes().len(), 1);
+        let reads = first.take_reads();
+        assert!(reads[0].0 == read && reads[0].1.is_ok());
+        assert_eq!(second.get_log(7).unwrap(), Bytes::from("x = 7"));
+    }
+
+    #[test]
+    fn mencius_revokes_slots_of_a_down_master_test() {
+        let (switch, clock) = (Switch::new(), ManualClock::new());
+        let (first, second, workers) = cluster(&switch, &clock);
+        let (first, second) = (
+            first.with_protocol(Protocol::Mencius),
+            second.with_protocol(Protocol::Mencius),
+        );
+
+        // 另一名议长停止运行，日志停在它的编号2上
+        assert_eq!(first.emmit_new_proposal(Bytes::from("x = 3")).unwrap(), 3);
+        run(&[&first], &workers);
+        assert!(first.take_outcomes().is_empty());
+
+        // 超时后收回编号2，填入空操作
+        clock.advance(REVOKE_TIMEOUT);
+        run(&[&first], &workers);
+        let outcomes = first.take_outcomes();
+        assert!(outcomes.len() == 1 && outcomes[0].1.is_ok());
+        assert!(first.get_log(2).is_err());
+        assert_eq!(first.get_log(3).unwrap(), Bytes::from("x = 3"));
+
+        // 恢复后，议长在被收回的编号中的提案失败
+        assert_eq!(second.emmit_new_proposal(Bytes::from("y = 2")).unwrap(), 2);
+        run(&[&first, &second], &workers);
+        let outcomes = second.take_outcomes();
+        assert!(outcomes.len() == 1 && outcomes[0].0 == 2 && outcomes[0].1.is_err());
+        assert!(second.get_log(2).is_err());
+        assert_eq!(second.get_log(3).unwrap(), Bytes::from("x = 3"));
+    }
+
+    #[test]
+    fn mencius_window_starts_at_the_first_unapplied_slot_test() {
+        let (switch, clock) = (Switch::new(), ManualClock::new());
+        let (first, second, workers) = cluster(&switch, &clock);
+        let (first, second) = (
+            first.with_window(2).with_protocol(Protocol::Mencius),
+            second.with_protocol(Protocol::Mencius),
+        );
+        let proposed = |slot| first.vote_table.borrow().keys().any(|(_, id)| *id == slot);
+
+        // 编号2尚未形成决议，编号5不在窗口之内：之前的成员变更可能还不知道
+        assert_eq!(first.emmit_new_proposal(Bytes::from("x = 3")).unwrap(), 3);
+        assert_eq!(first.emmit_new_proposal(Bytes::from("x = 5")).unwrap(), 5);
+        assert!(proposed(3) && !proposed(5));
+
+        assert_eq!(second.emmit_new_proposal(Bytes::from("y = 2")).unwrap(), 2);
+        run(&[&first, &second], &workers);
+        for master in [&first, &second] {
+            assert_eq!(master.get_log(2).unwrap(), Bytes::from("y = 2"));
+            assert_eq!(master.get_log(5).unwrap(), Bytes::from("x = 5"));
+        }
+    }
+
+    #[test]
+    fn mencius_dedups_commands_across_masters_test() {
+        let (switch, clock) = (Switch::new(), ManualClock::new());
+        let (first, second, workers) = cluster(&switch, &clock);
+        let (first, second) = (
+            first.with_protocol(Protocol::Mencius),
+            second.with_protocol(Protocol::Mencius),
+        );
+
+        // 客户端向两名议长提交了同一命令，两个编号都形成了决议
+        let command = Command::new("alice".to_string(), 1, Bytes::from("x = 1"));
+        assert_eq!(first.submit(command.clone()).unwrap(), 3);
+        assert_eq!(second.submit(command.clone()).unwrap(), 2);
+        run(&[&first, &second], &workers);
+
+        // 先应用的编号生效，之后的编号不再应用，日志中仍保留形成的决议
+        let outcomes = second.take_outcomes();
+        assert!(outcomes.len() == 1 && outcomes[0].0 == 2 && outcomes[0].1.is_ok());
+        let outcomes = first.take_outcomes();
+        let e = outcomes[0].1.as_ref().unwrap_err().to_string();
+        assert!(outcomes[0].0 == 3 && e.contains("issue 2"), "{}", e);
+        for master in [&first, &second] {
+            assert_eq!(master.get_log(2).unwrap(), Bytes::from("x = 1"));
+            let decided = Entry::decode(&master.logbackend.query(3).unwrap()).unwrap();
+            assert_eq!(decided, command.entry().unwrap());
+        }
+        assert_eq!(first.submit(command).unwrap(), 2);
+    }
+
+    /// 在同一份日志上重启议长
+    fn restart_master(
+        switch: &Switch,
+        clock: &ManualClock,
+        master: Master,
+        log: &Rc<HeapLogBackend>,
+    ) -> Master {
+        let (id, book) = (master.mail_box().id(), master.mail_box().address_book());
+        drop(master);
+        let mail_box = MailBox::new(
+            id.clone(),
+            Box::new(switch.connect(&id).unwrap()),
+            book,
+            Authenticator::new(b"secret"),
+            Codec::Binary,
+        )
+        .with_clock(Arc::new(clock.clone()));
+        Master::new(mail_box, Box::new(log.clone()))
+            .unwrap()
+            .with_seed(7)
+    }
+
+    #[test]
+    fn mencius_restart_revokes_proposed_slots_test() {
+        let (switch, clock) = (Switch::new(), ManualClock::new());
+        let (first, second, workers) = cluster(&switch, &clock);
+        let log = Rc::new(HeapLogBackend::new());
+        log.write(1, client_entry("x = 1")).unwrap();
+        let first = restart_master(&switch, &clock, first, &log).with_protocol(Protocol::Mencius);
+        let second = second.with_protocol(Protocol::Mencius);
+
+        // 议员接受了编号3的提案，议长还没收到投票就重启了
+        assert_eq!(first.emmit_new_proposal(Bytes::from("x = 3")).unwrap(), 3);
+        first.tick(Duration::ZERO).unwrap();
+        run(&[], &workers);
+        let first = restart_master(&switch, &clock, first, &log).with_protocol(Protocol::Mencius);
+
+        // 重启后收回编号3，恢复接受过的提案，新的议题不会再用编号3
+        assert_eq!(first.emmit_new_proposal(Bytes::from("x = 5")).unwrap(), 5);
+        run(&[&first, &second], &workers);
+        for master in [&first, &second] {
+            assert_eq!(master.get_log(3).unwrap(), Bytes::from("x = 3"));
+            assert_eq!(master.get_log(5).unwrap(), Bytes::from("x = 5"));
         }
     }
 }
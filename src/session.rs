@@ -0,0 +1,122 @@
+//! ### Session
+//! Apply every client command exactly once.
+//!
+//! A client tags each command with its id and a sequence number which grows
+//! with every new command, and retries a command with the same number. The
+//! command is written into the log as an entry of kind `EntryKind::Session`,
+//! and the session table, rebuilt from the log on restart, remembers the
+//! latest sequence number of every client and the slot it was decided at: a
+//! retry is answered with that slot instead of being appended again. A retry
+//! sent to another master may still be decided a second time; the log keeps
+//! both decisions, and every master alike skips the later one when applying
+//! it.
+//!
+#![allow(unused)]
+
+use std::collections::BTreeMap;
+
+use anyhow::{anyhow, Result};
+use bytes::Bytes;
+use serde::{Deserialize, Serialize};
+
+use crate::issue::{Entry, EntryKind};
+
+pub type ClientId = String;
+
+/// 客户端在会话中提交的命令
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct Command {
+    pub client: ClientId,
+    pub seq: u64,
+    pub content: Bytes,
+}
+
+impl Command {
+    pub fn new(client: ClientId, seq: u64, content: Bytes) -> Self {
+        Self {
+            client,
+            seq,
+            content,
+        }
+    }
+
+    /// 写入日志的条目
+    pub fn entry(&self) -> Result<Entry> {
+        let raw = bincode::serialize(self)?;
+        Ok(Entry::new(EntryKind::Session, raw.into()))
+    }
+
+    /// 条目不是会话命令时返回 `None`
+    pub fn decode(entry: &Entry) -> Option<Command> {
+        (entry.kind == EntryKind::Session)
+            .then(|| bincode::deserialize(&entry.content).ok())
+            .flatten()
+    }
+}
+
+/// 每个客户端最新的命令
+#[derive(Default)]
+pub struct Sessions {
+    /// 客户端 -> (序号, 形成决议的编号)
+    latest: BTreeMap<ClientId, (u64, u64)>,
+}
+
+impl Sessions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 记录在 `slot` 形成决议的命令；不比最新的序号新的命令不记录，返回 `false`
+    pub fn record(&mut self, slot: u64, command: &Command) -> bool {
+        match self.latest.get(&command.client) {
+            Some((seq, _)) if *seq >= command.seq => false,
+            _ => {
+                self.latest
+                    .insert(command.client.clone(), (command.seq, slot));
+                true
+            }
+        }
+    }
+
+    /// 命令已形成决议时返回其编号，新的命令返回 `None`；
+    /// 比最新的序号更早的命令的结果已不再保留，返回错误
+    pub fn lookup(&self, client: &str, seq: u64) -> Result<Option<u64>> {
+        match self.latest.get(client) {
+            Some((latest, slot)) if *latest == seq => Ok(Some(*slot)),
+            Some((latest, _)) if *latest > seq => Err(anyhow!(
+                "command {} of `{}` is older than the latest command {}",
+                seq,
+                client,
+                latest
+            )),
+            _ => Ok(None),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::Bytes;
+
+    use super::{Command, Sessions};
+    use crate::issue::Entry;
+
+    #[test]
+    fn session_table_test() {
+        let command = |seq| Command::new("alice".to_string(), seq, Bytes::from("x = 1"));
+        let entry = command(1).entry().unwrap();
+        assert_eq!(Command::decode(&entry), Some(command(1)));
+        assert_eq!(Command::decode(&Entry::client(entry.content)), None);
+
+        let mut sessions = Sessions::new();
+        assert_eq!(sessions.lookup("alice", 1).unwrap(), None);
+        assert!(sessions.record(3, &command(1)));
+        assert_eq!(sessions.lookup("alice", 1).unwrap(), Some(3));
+
+        assert!(sessions.record(5, &command(2)));
+        assert!(!sessions.record(6, &command(2)), "decided twice");
+        assert_eq!(sessions.lookup("alice", 2).unwrap(), Some(5));
+        assert!(sessions.lookup("alice", 1).is_err());
+        assert_eq!(sessions.lookup("bob", 1).unwrap(), None);
+    }
+}
@@ -0,0 +1,87 @@
+//! ### Shutdown
+//! A cloneable stop signal shared by every thread of a node.
+//!
+//! Long running loops check `is_triggered`, or sleep with `wait_timeout`
+//! so that they wake up as soon as the signal is triggered, either
+//! programmatically or by SIGINT / SIGTERM.
+//!
+#![allow(unused)]
+
+use std::{
+    sync::{Arc, Condvar, Mutex},
+    time::Duration,
+};
+
+use anyhow::{anyhow, Result};
+
+#[derive(Clone, Default)]
+pub struct Shutdown {
+    state: Arc<(Mutex<bool>, Condvar)>,
+}
+
+impl Shutdown {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 通知持有该信号的所有线程停止
+    pub fn trigger(&self) {
+        let (triggered, cond) = &*self.state;
+        if let Ok(mut triggered) = triggered.lock() {
+            *triggered = true;
+            cond.notify_all();
+        }
+    }
+
+    pub fn is_triggered(&self) -> bool {
+        self.state.0.lock().map(|t| *t).unwrap_or(true)
+    }
+
+    /// 休眠 `timeout`，期间信号被触发时提前醒来
+    ///
+    /// 返回信号是否已被触发
+    pub fn wait_timeout(&self, timeout: Duration) -> bool {
+        let (triggered, cond) = &*self.state;
+        match triggered.lock() {
+            Ok(guard) => cond
+                .wait_timeout_while(guard, timeout, |triggered| !*triggered)
+                .map(|(triggered, _)| *triggered)
+                .unwrap_or(true),
+            Err(_) => true,
+        }
+    }
+
+    /// 收到 SIGINT 或 SIGTERM 时触发该信号，每个进程只能安装一次
+    pub fn install_signal_handler(&self) -> Result<()> {
+        let shutdown = self.clone();
+        ctrlc::set_handler(move || shutdown.trigger())
+            .map_err(|e| anyhow!("Can't install signal handler: {}", e))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        thread,
+        time::{Duration, Instant},
+    };
+
+    use super::Shutdown;
+
+    #[test]
+    fn trigger_wakes_up_waiters() {
+        let shutdown = Shutdown::new();
+        assert!(!shutdown.wait_timeout(Duration::from_millis(10)));
+
+        let waiter = {
+            let shutdown = shutdown.clone();
+            thread::spawn(move || shutdown.wait_timeout(Duration::from_secs(30)))
+        };
+
+        let start = Instant::now();
+        shutdown.trigger();
+        assert!(waiter.join().unwrap());
+        assert!(start.elapsed() < Duration::from_secs(5));
+        assert!(shutdown.is_triggered());
+    }
+}
@@ -0,0 +1,359 @@
+//! ### Operation History and Checkers
+//! Record what clients invoked and what they observed, then check the
+//! history for linearizability against a sequential `Model`, and the
+//! recorded decisions for Paxos agreement.
+//!
+//! Linearizability is checked with the Wing & Gong search (with the
+//! memoization from Lowe's improvement): repeatedly pick an operation which
+//! may take effect next, apply it to the model, and backtrack whenever the
+//! model disagrees with what the client observed. Operations which never
+//! returned (the client crashed or gave up) may take effect at any point
+//! after their invocation, or not at all.
+//!
+//! The search is exponential in the number of pending operations, but
+//! linearizability is local: a history over independent objects (such as
+//! the slots of a log) is linearizable iff the sub-history of each object
+//! is, so `check_linearizable_by` checks every object on its own.
+//!
+#![allow(unused)]
+
+use std::{
+    collections::{BTreeMap, HashSet},
+    fmt::Debug,
+    hash::Hash,
+};
+
+use anyhow::{anyhow, Result};
+use bytes::Bytes;
+
+/// 检查历史时所依据的顺序规约
+pub trait Model: Clone + Eq + Hash {
+    type Op: Debug;
+    type Ret: Debug;
+
+    /// 在此状态上执行 `op`，`ret` 为客户端观察到的结果，操作未返回时为 `None`；
+    /// 返回之后的状态，从此状态不可能观察到该结果时返回 `None`
+    fn step(&self, op: &Self::Op, ret: Option<&Self::Ret>) -> Option<Self>;
+}
+
+#[derive(Debug)]
+pub struct Operation<Op, Ret> {
+    pub client: usize,
+    pub op: Op,
+    pub invoke: u64,
+    pub ret: Option<(u64, Ret)>,
+}
+
+/// 并发的操作历史，事件按逻辑时钟排序
+pub struct History<Op, Ret> {
+    ops: Vec<Operation<Op, Ret>>,
+    tick: u64,
+}
+
+impl<Op, Ret> Default for History<Op, Ret> {
+    fn default() -> Self {
+        Self {
+            ops: Vec::new(),
+            tick: 0,
+        }
+    }
+}
+
+impl<Op, Ret> History<Op, Ret> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 记录调用了 `op`，返回用于记录其结果的句柄
+    pub fn invoke(&mut self, client: usize, op: Op) -> usize {
+        self.tick += 1;
+        self.ops.push(Operation {
+            client,
+            op,
+            invoke: self.tick,
+            ret: None,
+        });
+        self.ops.len() - 1
+    }
+
+    /// 记录操作 `handle` 返回了 `ret`
+    pub fn complete(&mut self, handle: usize, ret: Ret) {
+        self.tick += 1;
+        if let Some(operation) = self.ops.get_mut(handle) {
+            operation.ret = Some((self.tick, ret));
+        }
+    }
+
+    pub fn ops(&self) -> &[Operation<Op, Ret>] {
+        &self.ops
+    }
+}
+
+impl<Op: Clone, Ret: Clone> History<Op, Ret> {
+    /// 按 `key` 给出的每个操作作用的对象拆分历史
+    pub fn split_by<K: Ord>(&self, key: impl Fn(&Op) -> K) -> BTreeMap<K, History<Op, Ret>> {
+        let mut parts: BTreeMap<K, History<Op, Ret>> = BTreeMap::new();
+        for operation in self.ops.iter() {
+            let part = parts.entry(key(&operation.op)).or_default();
+            part.ops.push(Operation {
+                client: operation.client,
+                op: operation.op.clone(),
+                invoke: operation.invoke,
+                ret: operation.ret.clone(),
+            });
+            part.tick = self.tick;
+        }
+        parts
+    }
+}
+
+/// 检查 `history` 相对于模型 `init` 是否线性一致
+pub fn check_linearizable<M: Model>(init: &M, history: &History<M::Op, M::Ret>) -> Result<()> {
+    let ops = history.ops();
+    let mut done = vec![false; ops.len()];
+    let mut seen = HashSet::new();
+
+    if search(ops, init, &mut done, &mut seen) {
+        Ok(())
+    } else {
+        Err(anyhow!("history is not linearizable: {:?}", ops))
+    }
+}
+
+/// 分别检查 `history` 中的每个对象，`key` 给出操作作用的对象，
+/// 每个对象都从状态 `init` 开始
+pub fn check_linearizable_by<M, K>(
+    init: &M,
+    history: &History<M::Op, M::Ret>,
+    key: impl Fn(&M::Op) -> K,
+) -> Result<()>
+where
+    M: Model,
+    M::Op: Clone,
+    M::Ret: Clone,
+    K: Ord + Debug,
+{
+    for (object, part) in history.split_by(key) {
+        check_linearizable(init, &part).map_err(|e| anyhow!("object {:?}: {}", object, e))?;
+    }
+    Ok(())
+}
+
+fn search<M: Model>(
+    ops: &[Operation<M::Op, M::Ret>],
+    state: &M,
+    done: &mut Vec<bool>,
+    seen: &mut HashSet<(Vec<bool>, M)>,
+) -> bool {
+    // 所有已返回的操作都已线性化
+    let horizon = ops
+        .iter()
+        .zip(done.iter())
+        .filter(|(_, done)| !**done)
+        .filter_map(|(op, _)| op.ret.as_ref().map(|(at, _)| *at))
+        .min();
+    let Some(horizon) = horizon else {
+        return true;
+    };
+
+    if !seen.insert((done.clone(), state.clone())) {
+        return false;
+    }
+
+    // 先尝试已返回的操作，再尝试未返回的操作
+    let mut candidates: Vec<usize> = (0..ops.len())
+        .filter(|i| !done[*i] && ops[*i].invoke < horizon)
+        .collect();
+    candidates.sort_by_key(|i| ops[*i].ret.is_none());
+
+    for i in candidates {
+        let ret = ops[i].ret.as_ref().map(|(_, ret)| ret);
+        if let Some(next) = state.step(&ops[i].op, ret) {
+            done[i] = true;
+            if search(ops, &next, done, seen) {
+                return true;
+            }
+            done[i] = false;
+        }
+    }
+    false
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum LogOp {
+    /// 为日志的编号 `slot` 提议 `value`
+    Append { slot: u64, value: String },
+    /// 读取日志的编号 `slot` 形成决议的值
+    Read { slot: u64 },
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum LogRet {
+    Ok,
+    Value(Option<String>),
+}
+
+/// 把复制的日志看作编号到值的映射，每个编号至多写入一次
+#[derive(Clone, Default, PartialEq, Eq, Hash)]
+pub struct LogModel {
+    slots: BTreeMap<u64, String>,
+}
+
+impl Model for LogModel {
+    type Op = LogOp;
+    type Ret = LogRet;
+
+    fn step(&self, op: &LogOp, ret: Option<&LogRet>) -> Option<Self> {
+        match (op, ret) {
+            (LogOp::Append { slot, value }, None | Some(LogRet::Ok)) => {
+                match self.slots.get(slot) {
+                    Some(decided) => (decided == value).then(|| self.clone()),
+                    None => {
+                        let mut next = self.clone();
+                        next.slots.insert(*slot, value.clone());
+                        Some(next)
+                    }
+                }
+            }
+            (LogOp::Read { slot }, Some(LogRet::Value(value))) => {
+                (self.slots.get(slot) == value.as_ref()).then(|| self.clone())
+            }
+            (LogOp::Read { .. }, None) => Some(self.clone()),
+            _ => None,
+        }
+    }
+}
+
+impl LogOp {
+    pub fn slot(&self) -> u64 {
+        match self {
+            LogOp::Append { slot, .. } | LogOp::Read { slot } => *slot,
+        }
+    }
+}
+
+/// 节点作为决议写入日志的值
+#[derive(Clone, Debug)]
+pub struct Decision {
+    pub node: String,
+    pub slot: u64,
+    pub value: Bytes,
+}
+
+/// Paxos 的一致性：同一编号不会形成两个不同的决议
+pub fn check_agreement(decisions: &[Decision]) -> Result<()> {
+    let mut decided: BTreeMap<u64, &Decision> = BTreeMap::new();
+
+    for decision in decisions {
+        match decided.get(&decision.slot) {
+            Some(first) if first.value != decision.value => {
+                return Err(anyhow!(
+                    "slot {} decided twice: {:?} by `{}`, then {:?} by `{}`",
+                    decision.slot,
+                    first.value,
+                    first.node,
+                    decision.value,
+                    decision.node
+                ))
+            }
+            Some(_) => {}
+            None => {
+                decided.insert(decision.slot, decision);
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        check_agreement, check_linearizable, check_linearizable_by, Decision, History, LogModel,
+        LogOp, LogRet,
+    };
+
+    fn append(slot: u64, value: &str) -> LogOp {
+        LogOp::Append {
+            slot,
+            value: value.to_string(),
+        }
+    }
+
+    fn value(value: Option<&str>) -> LogRet {
+        LogRet::Value(value.map(|v| v.to_string()))
+    }
+
+    #[test]
+    fn concurrent_read_may_see_either_state() {
+        let mut history = History::new();
+        let w = history.invoke(0, append(1, "a"));
+        let r1 = history.invoke(1, LogOp::Read { slot: 1 });
+        history.complete(r1, value(Some("a")));
+        let r2 = history.invoke(2, LogOp::Read { slot: 1 });
+        history.complete(r2, value(Some("a")));
+        history.complete(w, LogRet::Ok);
+
+        assert!(check_linearizable(&LogModel::default(), &history).is_ok());
+    }
+
+    #[test]
+    fn pending_append_may_explain_a_read() {
+        let mut history = History::new();
+        history.invoke(0, append(1, "a"));
+        let r = history.invoke(1, LogOp::Read { slot: 1 });
+        history.complete(r, value(Some("a")));
+
+        assert!(check_linearizable(&LogModel::default(), &history).is_ok());
+    }
+
+    #[test]
+    fn stale_read_is_rejected() {
+        let mut history = History::new();
+        let w = history.invoke(0, append(1, "a"));
+        history.complete(w, LogRet::Ok);
+        let r = history.invoke(1, LogOp::Read { slot: 1 });
+        history.complete(r, value(None));
+
+        assert!(check_linearizable(&LogModel::default(), &history).is_err());
+    }
+
+    #[test]
+    fn read_going_back_in_time_is_rejected() {
+        let mut history = History::new();
+        history.invoke(0, append(1, "a"));
+        let r1 = history.invoke(1, LogOp::Read { slot: 1 });
+        history.complete(r1, value(Some("a")));
+        let r2 = history.invoke(1, LogOp::Read { slot: 1 });
+        history.complete(r2, value(None));
+
+        assert!(check_linearizable(&LogModel::default(), &history).is_err());
+    }
+
+    #[test]
+    fn slots_are_checked_apart() {
+        let mut history = History::new();
+        // 许多从未返回的追加，各在不同的编号上
+        for slot in 1..=40 {
+            history.invoke(0, append(slot, "a"));
+        }
+        let r = history.invoke(1, LogOp::Read { slot: 7 });
+        history.complete(r, value(Some("a")));
+        let r = history.invoke(1, LogOp::Read { slot: 7 });
+        history.complete(r, value(None));
+
+        let err = check_linearizable_by(&LogModel::default(), &history, LogOp::slot).unwrap_err();
+        assert!(err.to_string().starts_with("object 7"));
+    }
+
+    #[test]
+    fn conflicting_decisions_are_rejected() {
+        let decision = |node: &str, slot, value: &'static str| Decision {
+            node: node.to_string(),
+            slot,
+            value: value.into(),
+        };
+
+        assert!(check_agreement(&[decision("a", 1, "x"), decision("b", 1, "x")]).is_ok());
+        assert!(check_agreement(&[decision("a", 1, "x"), decision("b", 1, "y")]).is_err());
+    }
+}
@@ -0,0 +1,616 @@
+//! # Deterministic Cluster Simulator
+//! Run a whole cluster inside a single thread, driven by a seed.
+//!
+//! Every node talks through a `Channel` plugged into a manual `Switch`, and
+//! time only moves through a `ManualClock`, so the simulator alone decides
+//! which packet arrives next, which node runs, when clients submit or read,
+//! and when nodes crash, restart or get partitioned. All of these choices
+//! come from one seeded `Rng`: running the same seed twice replays exactly
+//! the same execution.
+//!
+//! While running, the simulator records the client history and every value
+//! a node decides, which are then checked for linearizability and Paxos
+//! agreement. By default three masters compete for the lead and clients
+//! talk to any of them; the masters share the slots when running
+//! `Protocol::Mencius`. A failing seed can be replayed with
+//!
+//!     SOMEPOX_SIM_SEED=<seed> cargo test sim::
+//!
+mod history;
+
+pub use crate::rng::Rng;
+pub use history::{
+    check_agreement, check_linearizable_by, Decision, History, LogModel, LogOp, LogRet, Model,
+};
+
+use std::{cell::RefCell, collections::BTreeMap, rc::Rc, sync::Arc, time::Duration};
+
+use anyhow::{anyhow, Result};
+use bytes::Bytes;
+
+use crate::{
+    address_book::AddressBook,
+    auth::Authenticator,
+    clock::{Clock, ManualClock},
+    codec::Codec,
+    connection::Switch,
+    logbackend::{HeapLogBackend, LogBackend, Queryable, Writable},
+    mailbox::MailBox,
+    mencius::Protocol,
+    read::Consistency,
+    roles::{Master, Worker, MASTER_GROUP, WORKER_GROUP},
+};
+
+const MASTER: &str = "master";
+const SECRET: &[u8] = b"simulated-cluster";
+
+/// 一次模拟的参数，各种概率均为每一步的百分比
+#[derive(Clone)]
+pub struct SimConfig {
+    pub masters: usize,
+    pub protocol: Protocol,
+    pub workers: usize,
+    pub steps: usize,
+    pub max_down: usize,
+    pub crash_rate: u64,
+    pub restart_rate: u64,
+    pub partition_rate: u64,
+    pub heal_rate: u64,
+    pub drop_rate: u64,
+    pub submit_rate: u64,
+    pub read_rate: u64,
+}
+
+impl Default for SimConfig {
+    fn default() -> Self {
+        Self {
+            masters: 3,
+            protocol: Protocol::Leader,
+            workers: 3,
+            steps: 2000,
+            max_down: 1,
+            crash_rate: 1,
+            restart_rate: 5,
+            partition_rate: 1,
+            heal_rate: 3,
+            drop_rate: 2,
+            submit_rate: 5,
+            read_rate: 5,
+        }
+    }
+}
+
+/// 崩溃后仍然保留的日志；议长写入议题编号的每次写入都记录为一个决议，
+/// 议员的日志只保存承诺与接受的提案，不记录决议
+#[derive(Clone)]
+struct DurableLog {
+    node: String,
+    store: Rc<HeapLogBackend>,
+    decisions: Option<Rc<RefCell<Vec<Decision>>>>,
+}
+
+impl Writable for DurableLog {
+    fn write(&self, id: u64, data: Bytes) -> Result<()> {
+        self.store.write(id, data.clone())?;
+        // 议长的日志的编号0保存轮数
+        if let Some(decisions) = self.decisions.as_ref().filter(|_| id > 0) {
+            decisions.borrow_mut().push(Decision {
+                node: self.node.clone(),
+                slot: id,
+                value: data,
+            });
+        }
+        Ok(())
+    }
+}
+
+impl Queryable for DurableLog {
+    fn query(&self, id: u64) -> Result<Bytes> {
+        self.store.query(id)
+    }
+
+    fn get(&self, id: u64) -> Result<Option<Bytes>> {
+        self.store.get(id)
+    }
+
+    fn last_id(&self) -> Result<Option<u64>> {
+        self.store.last_id()
+    }
+}
+
+impl LogBackend for DurableLog {}
+
+// 节点在整个模拟中都存放在同一个表中，大小无关紧要
+#[allow(clippy::large_enum_variant)]
+enum Node {
+    Master(Master),
+    Worker(Worker),
+}
+
+impl Node {
+    /// 节点事件循环的一轮：发出发件箱中的邮件，取出并处理所有收到的邮件
+    fn step(&self) {
+        let _ = match self {
+            Node::Master(master) => master.tick(Duration::ZERO),
+            Node::Worker(worker) => worker.tick(Duration::ZERO),
+        };
+    }
+}
+
+/// 提交的值均为文本，便于阅读历史
+fn utf8(value: Bytes) -> String {
+    String::from_utf8_lossy(&value).into_owned()
+}
+
+/// 等待议长报告已应用的追加
+struct PendingAppend {
+    master: String,
+    handle: usize,
+    slot: u64,
+    value: String,
+}
+
+/// 等待议长告知可以安全读取的读请求
+struct PendingRead {
+    master: String,
+    handle: usize,
+    read: u64,
+    slot: u64,
+}
+
+pub struct Simulator {
+    seed: u64,
+    config: SimConfig,
+    rng: Rng,
+    clock: ManualClock,
+    switch: Switch,
+    names: Vec<String>,
+    nodes: BTreeMap<String, Node>,
+    logs: BTreeMap<String, DurableLog>,
+    decisions: Rc<RefCell<Vec<Decision>>>,
+    history: History<LogOp, LogRet>,
+    pending: Vec<PendingAppend>,
+    reads: Vec<PendingRead>,
+    issued: u64,
+    trace: Vec<String>,
+}
+
+impl Simulator {
+    pub fn new(seed: u64, config: SimConfig) -> Self {
+        let mut names = vec![MASTER.to_string()];
+        names.extend((2..=config.masters).map(|i| format!("{}-{}", MASTER, i)));
+        names.extend((1..=config.workers).map(|i| format!("worker-{}", i)));
+
+        let decisions = Rc::new(RefCell::new(Vec::new()));
+        let logs = names
+            .iter()
+            .map(|name| {
+                let log = DurableLog {
+                    node: name.clone(),
+                    store: Rc::new(HeapLogBackend::new()),
+                    decisions: is_master(name).then(|| decisions.clone()),
+                };
+                (name.clone(), log)
+            })
+            .collect();
+
+        let mut sim = Self {
+            seed,
+            config,
+            rng: Rng::new(seed),
+            clock: ManualClock::new(),
+            switch: Switch::manual(),
+            names: names.clone(),
+            nodes: BTreeMap::new(),
+            logs,
+            decisions,
+            history: History::new(),
+            pending: Vec::new(),
+            reads: Vec::new(),
+            issued: 0,
+            trace: Vec::new(),
+        };
+        for name in names.iter() {
+            sim.start(name);
+        }
+        sim
+    }
+
+    pub fn trace(&self) -> &[String] {
+        &self.trace
+    }
+
+    pub fn history(&self) -> &History<LogOp, LogRet> {
+        &self.history
+    }
+
+    /// 运行配置的步数
+    pub fn run(&mut self) {
+        for _ in 0..self.config.steps {
+            self.step();
+        }
+    }
+
+    /// 检查记录的历史与决议
+    pub fn check(&self) -> Result<()> {
+        check_agreement(&self.decisions.borrow())
+            .and_then(|_| check_linearizable_by(&LogModel::default(), &self.history, LogOp::slot))
+            .map_err(|e| {
+                anyhow!(
+                    "seed {} failed: {}\nlast events:\n{}\nreplay with SOMEPOX_SIM_SEED={}",
+                    self.seed,
+                    e,
+                    self.trace[self.trace.len().saturating_sub(20)..].join("\n"),
+                    self.seed
+                )
+            })
+    }
+
+    fn log(&mut self, event: String) {
+        let now = self.clock.now().as_millis();
+        self.trace.push(format!("[{:>6}ms] {}", now, event));
+    }
+
+    fn start(&mut self, name: &str) {
+        let Ok(conn) = self.switch.connect(name) else {
+            return;
+        };
+
+        // 节点的地址即为节点的编号
+        let mut address_book = AddressBook::new();
+        for other in self.names.iter() {
+            address_book.insert(other.clone(), other.clone());
+            let group = if is_master(other) {
+                MASTER_GROUP
+            } else {
+                WORKER_GROUP
+            };
+            address_book.join(group, other.clone());
+        }
+
+        let mail_box = MailBox::new(
+            name.to_string(),
+            Box::new(conn),
+            address_book,
+            Authenticator::new(SECRET),
+            Codec::Binary,
+        )
+        .with_clock(Arc::new(self.clock.clone()));
+        let log = Box::new(self.logs[name].clone());
+        let node = if is_master(name) {
+            let seed = self.rng.next_u64();
+            // 较小的窗口使提案在窗口之外排队
+            let window = 1 + self.rng.below(8);
+            match Master::new(mail_box, log) {
+                Ok(master) => Node::Master(
+                    master
+                        .with_seed(seed)
+                        .with_window(window)
+                        .with_protocol(self.config.protocol),
+                ),
+                Err(_) => return,
+            }
+        } else {
+            match Worker::new(mail_box, log) {
+                Ok(worker) => Node::Worker(worker),
+                Err(_) => return,
+            }
+        };
+        self.nodes.insert(name.to_string(), node);
+    }
+
+    /// 在线的议长
+    fn masters(&self) -> Vec<String> {
+        self.nodes
+            .keys()
+            .filter(|name| is_master(name))
+            .cloned()
+            .collect()
+    }
+
+    /// 客户端访问的一名在线的议长
+    fn pick_master(&mut self) -> Option<String> {
+        let masters = self.masters();
+        self.rng
+            .pick(masters.len())
+            .map(|index| masters[index].clone())
+    }
+
+    fn down(&self) -> Vec<String> {
+        self.names
+            .iter()
+            .filter(|name| !self.nodes.contains_key(*name))
+            .cloned()
+            .collect()
+    }
+
+    fn step(&mut self) {
+        let elapsed = 1 + self.rng.below(5);
+        self.clock.advance(Duration::from_millis(elapsed));
+
+        let config = self.config.clone();
+        let roll = self.rng.below(100);
+
+        if self.rng.chance(config.crash_rate) && self.down().len() < config.max_down {
+            self.crash();
+        } else if self.rng.chance(config.restart_rate) {
+            self.restart();
+        } else if self.rng.chance(config.partition_rate) {
+            self.partition();
+        } else if self.rng.chance(config.heal_rate) {
+            self.switch.heal();
+            self.log("heal all links".to_string());
+        } else if self.rng.chance(config.submit_rate) {
+            self.submit();
+        } else if self.rng.chance(config.read_rate) {
+            self.read();
+        } else if self.rng.chance(config.drop_rate) {
+            if let Some(index) = self.rng.pick(self.switch.in_flight()) {
+                if let Some(packet) = self.switch.drop_packet(index) {
+                    self.log(format!("drop {} -> {}", packet.from, packet.to));
+                }
+            }
+        } else if roll < 50 && self.switch.in_flight() > 0 {
+            self.deliver();
+        } else {
+            self.run_node();
+        }
+
+        self.observe();
+    }
+
+    fn crash(&mut self) {
+        let up: Vec<String> = self.nodes.keys().cloned().collect();
+        if let Some(index) = self.rng.pick(up.len()) {
+            self.nodes.remove(&up[index]);
+            self.log(format!("crash {}", up[index]));
+            // 进行中的读请求随议长一起丢失，永远不会返回
+            self.reads.retain(|read| read.master != up[index]);
+        }
+    }
+
+    fn restart(&mut self) {
+        let down = self.down();
+        if let Some(index) = self.rng.pick(down.len()) {
+            self.start(&down[index]);
+            self.log(format!("restart {}", down[index]));
+        }
+    }
+
+    fn partition(&mut self) {
+        if let Some(index) = self.rng.pick(self.names.len()) {
+            let isolated = self.names[index].clone();
+            for other in self.names.iter().filter(|name| **name != isolated) {
+                self.switch.cut(&isolated, other);
+            }
+            self.log(format!("isolate {}", isolated));
+        }
+    }
+
+    fn deliver(&mut self) {
+        if let Some(index) = self.rng.pick(self.switch.in_flight()) {
+            if let Some((packet, arrived)) = self.switch.deliver(index) {
+                self.log(format!(
+                    "deliver {} -> {} ({} bytes){}",
+                    packet.from,
+                    packet.to,
+                    packet.data.len(),
+                    if arrived { "" } else { " lost" }
+                ));
+            }
+        }
+    }
+
+    fn run_node(&mut self) {
+        let up: Vec<String> = self.nodes.keys().cloned().collect();
+        if let Some(index) = self.rng.pick(up.len()) {
+            self.nodes[&up[index]].step();
+            self.log(format!("step {}", up[index]));
+        }
+    }
+
+    fn submit(&mut self) {
+        let Some(name) = self.pick_master() else {
+            return;
+        };
+        let Some(Node::Master(master)) = self.nodes.get(&name) else {
+            return;
+        };
+
+        let value = format!("v{}", self.rng.below(1_000_000));
+        if let Ok(slot) = master.emmit_new_proposal(Bytes::from(value.clone())) {
+            self.issued = self.issued.max(slot);
+            let handle = self.history.invoke(
+                0,
+                LogOp::Append {
+                    slot,
+                    value: value.clone(),
+                },
+            );
+            self.pending.push(PendingAppend {
+                master: name.clone(),
+                handle,
+                slot,
+                value: value.clone(),
+            });
+            self.log(format!("submit {} to {} -> slot {}", value, name, slot));
+        }
+    }
+
+    fn read(&mut self) {
+        if self.issued == 0 {
+            return;
+        }
+        let Some(name) = self.pick_master() else {
+            return;
+        };
+        let Some(Node::Master(master)) = self.nodes.get(&name) else {
+            return;
+        };
+
+        let slot = 1 + self.rng.below(self.issued);
+        let consistency = if self.rng.chance(50) {
+            Consistency::Linearizable
+        } else {
+            Consistency::Lease
+        };
+        let read = master.read(consistency);
+        let handle = self.history.invoke(1, LogOp::Read { slot });
+        self.reads.push(PendingRead {
+            master: name.clone(),
+            handle,
+            read,
+            slot,
+        });
+        self.log(format!(
+            "read slot {} from {} ({:?})",
+            slot, name, consistency
+        ));
+    }
+
+    /// 完成议长报告已应用的追加，以及议长告知可以安全读取的读请求
+    fn observe(&mut self) {
+        for name in self.masters() {
+            self.observe_master(&name);
+        }
+    }
+
+    fn observe_master(&mut self, name: &str) {
+        let Some(Node::Master(master)) = self.nodes.get(name) else {
+            return;
+        };
+
+        // 失败的追加在历史中仍未返回：对客户端而言，它可能生效了，也可能没有
+        let (mut applied, mut failed) = (Vec::new(), Vec::new());
+        for (slot, outcome) in master.take_outcomes() {
+            match outcome {
+                Ok(()) => applied.push(slot),
+                Err(_) => failed.push(slot),
+            }
+        }
+
+        let mut served = Vec::new();
+        for (read, outcome) in master.take_reads() {
+            let Some(index) = self
+                .reads
+                .iter()
+                .position(|pending| pending.master == name && pending.read == read)
+            else {
+                continue;
+            };
+            let pending = self.reads.remove(index);
+            // 未能确认领导权的读请求仍未返回
+            if outcome.is_ok() {
+                let value = master.get_log(pending.slot).ok().map(utf8);
+                served.push((pending.handle, pending.slot, value));
+            }
+        }
+
+        let mut committed = Vec::new();
+        self.pending.retain(|append| {
+            // 重启的议长会重新使用未形成决议的编号
+            let done = append.master == name
+                && applied.contains(&append.slot)
+                && master.get_log(append.slot).ok().map(utf8).as_ref() == Some(&append.value);
+            if done {
+                committed.push((append.handle, append.slot));
+            }
+            !done
+        });
+
+        for slot in failed {
+            self.log(format!("slot {} failed", slot));
+        }
+        for (handle, slot) in committed {
+            self.history.complete(handle, LogRet::Ok);
+            self.log(format!("slot {} committed", slot));
+        }
+        for (handle, slot, value) in served {
+            self.history.complete(handle, LogRet::Value(value.clone()));
+            self.log(format!("read slot {} = {:?}", slot, value));
+        }
+    }
+}
+
+/// 议长的编号为 `master`、`master-2` 等
+fn is_master(name: &str) -> bool {
+    name.starts_with(MASTER)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{SimConfig, Simulator};
+    use crate::mencius::Protocol;
+
+    /// 要运行的种子，重放时只运行 `SOMEPOX_SIM_SEED` 中的种子
+    fn seeds() -> Vec<u64> {
+        match std::env::var("SOMEPOX_SIM_SEED") {
+            Ok(seed) => vec![seed.parse().expect("SOMEPOX_SIM_SEED is not a number")],
+            Err(_) => (0..32).collect(),
+        }
+    }
+
+    /// 以 `config` 运行每个种子，检查每次运行，并检查确有决议与读取
+    fn check_seeds(config: SimConfig) {
+        let (mut committed, mut served) = (0, 0);
+        for seed in seeds() {
+            let mut sim = Simulator::new(seed, config.clone());
+            sim.run();
+            if let Err(e) = sim.check() {
+                panic!("{}", e);
+            }
+            committed += sim
+                .trace()
+                .iter()
+                .filter(|event| event.ends_with("committed"))
+                .count();
+            served += sim
+                .trace()
+                .iter()
+                .filter(|event| event.contains("read slot") && event.contains(" = "))
+                .count();
+        }
+        // 从不形成决议的集群总是线性一致的
+        assert!(committed > 0, "nothing was committed");
+        assert!(served > 0 || config.read_rate == 0, "no read was served");
+    }
+
+    /// 客户端访问任意一名议长，议长之间争夺领导权
+    #[test]
+    fn simulated_cluster_is_linearizable() {
+        check_seeds(SimConfig::default());
+    }
+
+    #[test]
+    fn simulated_single_master_cluster_is_linearizable() {
+        check_seeds(SimConfig {
+            masters: 1,
+            ..SimConfig::default()
+        });
+    }
+
+    #[test]
+    fn simulated_mencius_cluster_is_linearizable() {
+        check_seeds(SimConfig {
+            masters: 3,
+            protocol: Protocol::Mencius,
+            ..SimConfig::default()
+        });
+    }
+
+    #[test]
+    fn same_seed_replays_exactly() {
+        for seed in seeds().into_iter().take(4) {
+            let mut first = Simulator::new(seed, SimConfig::default());
+            let mut second = Simulator::new(seed, SimConfig::default());
+            first.run();
+            second.run();
+
+            assert_eq!(first.trace(), second.trace(), "seed {} diverged", seed);
+            assert_eq!(
+                format!("{:?}", first.history().ops()),
+                format!("{:?}", second.history().ops())
+            );
+        }
+    }
+}